@@ -0,0 +1,206 @@
+//! Lists and edits the files in a FAT disk image without mounting it.
+//!
+//! usage: fat32-tool [-p <index>] <image> <command> [args]
+//!
+//! The image may hold an MBR or GPT partition table, in which case the first
+//! FAT partition (or partition `index`) is used, or a bare FAT volume. Paths
+//! inside the image are absolute.
+
+extern crate fat32;
+
+mod image;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use fat32::traits::{Dir, Entry, File, FileSystem, Metadata};
+use fat32::vfat::{BiosParameterBlock, Shared, VFat};
+
+use image::Image;
+
+fn usage() -> ! {
+    eprintln!("usage: fat32-tool [-p <index>] <image> <command> [args]");
+    eprintln!("  -p <index>            use entry <index> of the partition table");
+    eprintln!("commands:");
+    eprintln!("  ls [-l] [path]        list a directory");
+    eprintln!("  cat <path>            write a file to standard output");
+    eprintln!("  get <path> <file>     copy a file out of the image");
+    eprintln!("  put <file> <path>     copy a file into the image");
+    eprintln!("  mkdir [-p] <path>     create a directory, and with -p its parents");
+    eprintln!("  rm [-r] <path>        remove a file, or with -r a directory tree");
+    process::exit(2);
+}
+
+fn fail<E: ::std::fmt::Debug>(what: &str, error: E) -> ! {
+    eprintln!("fat32-tool: {}: {:?}", what, error);
+    process::exit(1);
+}
+
+/// Splits a leading `flag` off `args`.
+fn flag<'a>(args: &'a [String], flag: &str) -> (bool, &'a [String]) {
+    match args.first() {
+        Some(first) if first == flag => (true, &args[1..]),
+        _ => (false, args),
+    }
+}
+
+/// Mounts the file system in `image`: partition `index` if given, otherwise
+/// a bare volume if sector 0 is a FAT boot sector, otherwise the first FAT
+/// partition.
+fn mount(path: &str, index: Option<usize>, write: bool) -> Shared<VFat> {
+    let file = OpenOptions::new().read(true).write(write).open(path)
+        .unwrap_or_else(|e| fail(path, e));
+    let vfat = match index {
+        Some(index) => VFat::from_partition(Image(file), index),
+        None => {
+            let bare = BiosParameterBlock::from(&mut Image(file.try_clone()
+                .unwrap_or_else(|e| fail(path, e))), 0)
+                .map(|ebpb| ebpb.is_fat())
+                .unwrap_or(false);
+            if bare {
+                VFat::from_volume(Image(file))
+            } else {
+                VFat::from(Image(file))
+            }
+        }
+    };
+    vfat.unwrap_or_else(|e| fail(path, e))
+}
+
+fn ls(vfat: &Shared<VFat>, args: &[String]) {
+    let (long, args) = flag(args, "-l");
+    let path = match args.len() {
+        0 => "/",
+        1 => args[0].as_str(),
+        _ => usage(),
+    };
+
+    let dir = vfat.open_dir(path).unwrap_or_else(|e| fail(path, e));
+    for entry in dir.entries().unwrap_or_else(|e| fail(path, e)) {
+        let metadata = entry.metadata();
+        if metadata.volume_id() {
+            continue;
+        }
+        if long {
+            let flags: String = [(metadata.directory(), 'd'), (metadata.read_only(), 'r'),
+                                 (metadata.hidden(), 'h'), (metadata.system(), 's'),
+                                 (metadata.archive(), 'a')].iter()
+                .map(|&(set, c)| if set { c } else { '-' })
+                .collect();
+            println!("{} {:>10} {} {}", flags, metadata.size(), metadata.modified(),
+                     entry.name());
+        } else if metadata.directory() {
+            println!("{}/", entry.name());
+        } else {
+            println!("{}", entry.name());
+        }
+    }
+}
+
+fn cat(vfat: &Shared<VFat>, args: &[String]) {
+    if args.len() != 1 {
+        usage();
+    }
+
+    let mut file = vfat.open_file(&args[0]).unwrap_or_else(|e| fail(&args[0], e));
+    let stdout = io::stdout();
+    io::copy(&mut file, &mut stdout.lock()).unwrap_or_else(|e| fail(&args[0], e));
+}
+
+fn get(vfat: &Shared<VFat>, args: &[String]) {
+    if args.len() != 2 {
+        usage();
+    }
+
+    let mut file = vfat.open_file(&args[0]).unwrap_or_else(|e| fail(&args[0], e));
+    let mut out = fs::File::create(&args[1]).unwrap_or_else(|e| fail(&args[1], e));
+    io::copy(&mut file, &mut out).unwrap_or_else(|e| fail(&args[1], e));
+}
+
+fn put(vfat: &Shared<VFat>, args: &[String]) {
+    if args.len() != 2 {
+        usage();
+    }
+
+    let mut data = Vec::new();
+    fs::File::open(&args[0]).and_then(|mut file| file.read_to_end(&mut data))
+        .unwrap_or_else(|e| fail(&args[0], e));
+
+    // Copying onto a directory puts the file in it, and copying onto a file
+    // replaces its contents.
+    let mut path = PathBuf::from(&args[1]);
+    if vfat.open_dir(&path).is_ok() {
+        let name = Path::new(&args[0]).file_name().unwrap_or_else(|| usage());
+        path.push(name);
+    }
+    let what = path.display().to_string();
+    let mut file = match vfat.open_file(&path) {
+        Ok(mut file) => {
+            file.set_len(0).unwrap_or_else(|e| fail(&what, e));
+            file
+        }
+        Err(_) => vfat.create_file(&path).unwrap_or_else(|e| fail(&what, e)),
+    };
+    file.write_all(&data).unwrap_or_else(|e| fail(&what, e));
+    file.sync().unwrap_or_else(|e| fail(&what, e));
+}
+
+fn mkdir(vfat: &Shared<VFat>, args: &[String]) {
+    let (parents, args) = flag(args, "-p");
+    if args.len() != 1 {
+        usage();
+    }
+
+    vfat.create_dir(&args[0], parents).unwrap_or_else(|e| fail(&args[0], e));
+}
+
+fn rm(vfat: &Shared<VFat>, args: &[String]) {
+    let (children, args) = flag(args, "-r");
+    if args.len() != 1 {
+        usage();
+    }
+
+    vfat.remove(&args[0], children).unwrap_or_else(|e| fail(&args[0], e));
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut index = None;
+    if args.first().map_or(false, |arg| arg == "-p") {
+        if args.len() < 2 {
+            usage();
+        }
+        index = Some(args[1].parse::<usize>().unwrap_or_else(|_| usage()));
+        args.drain(..2);
+    }
+    if args.len() < 2 {
+        usage();
+    }
+
+    let (image, command, rest) = (&args[0], &args[1], &args[2..]);
+    let write = match command.as_str() {
+        "ls" | "cat" | "get" => false,
+        "put" | "mkdir" | "rm" => true,
+        _ => usage(),
+    };
+
+    let vfat = mount(image, index, write);
+    match command.as_str() {
+        "ls" => ls(&vfat, rest),
+        "cat" => cat(&vfat, rest),
+        "get" => get(&vfat, rest),
+        "put" => put(&vfat, rest),
+        "mkdir" => mkdir(&vfat, rest),
+        "rm" => rm(&vfat, rest),
+        _ => unreachable!(),
+    }
+
+    // Dropping the file system would also write back anything still cached,
+    // but would ignore any error doing so.
+    if write {
+        vfat.borrow_mut().sync().unwrap_or_else(|e| fail(image, e));
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks, and optionally repairs, the FAT32 file system in a disk image.
+//!
+//! usage: fsck [-r] <image>
+//!
+//! Exits with 0 if the file system is clean, 1 if problems were repaired and
+//! 4 if problems were left unrepaired.
+
+extern crate fat32;
+
+mod image;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::process;
+
+use fat32::check;
+use fat32::vfat::VFat;
+
+use image::Image;
+
+fn usage() -> ! {
+    eprintln!("usage: fsck [-r] <image>");
+    eprintln!("  -r, --repair  repair the problems found");
+    process::exit(8);
+}
+
+fn fail<E: ::std::fmt::Debug>(what: &str, error: E) -> ! {
+    eprintln!("fsck: {}: {:?}", what, error);
+    process::exit(8);
+}
+
+fn main() {
+    let mut repair = false;
+    let mut image = None;
+    for arg in env::args().skip(1) {
+        if arg == "-r" || arg == "--repair" {
+            repair = true;
+        } else if image.is_none() && !arg.starts_with('-') {
+            image = Some(arg);
+        } else {
+            usage();
+        }
+    }
+
+    let path = image.unwrap_or_else(|| usage());
+    let file = OpenOptions::new().read(true).write(repair).open(&path)
+        .unwrap_or_else(|e| fail(&path, e));
+    let vfat = VFat::from(Image(file)).unwrap_or_else(|e| fail(&path, e));
+    let report = check::check(&vfat, repair).unwrap_or_else(|e| fail(&path, e));
+
+    if report.dirty {
+        println!("{}: volume was not cleanly unmounted", path);
+    }
+    for problem in report.problems.iter() {
+        println!("{}: {}", path, problem);
+    }
+    println!("{}: {} files, {} directories, {} clusters in use, {} problems{}",
+             path, report.files, report.dirs, report.used_clusters, report.problems.len(),
+             if report.repaired { " repaired" } else { "" });
+
+    process::exit(match (report.is_clean(), report.repaired) {
+        (true, _) => 0,
+        (false, true) => 1,
+        (false, false) => 4,
+    });
+}
@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::traits::BlockDevice;
+
+/// A disk image file used as a block device.
+pub struct Image(pub File);
+
+impl BlockDevice for Image {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size();
+        let to_read = ::std::cmp::min(sector_size as usize, buf.len());
+        self.0.seek(SeekFrom::Start(n * sector_size))?;
+        self.0.read_exact(&mut buf[..to_read])?;
+        Ok(to_read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size();
+        let to_write = ::std::cmp::min(sector_size as usize, buf.len());
+        self.0.seek(SeekFrom::Start(n * sector_size))?;
+        self.0.write_all(&buf[..to_write])?;
+        Ok(to_write)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.seek(SeekFrom::Start(n * self.sector_size()))?;
+        self.0.read_exact(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.seek(SeekFrom::Start(n * self.sector_size()))?;
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+}
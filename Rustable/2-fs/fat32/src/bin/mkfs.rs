@@ -0,0 +1,62 @@
+//! Formats a disk image as a single FAT32 partition.
+//!
+//! usage: mkfs <image> [size]
+//!
+//! If `size` is given, the image is created or truncated to `size` bytes,
+//! which may be suffixed with K, M or G. Otherwise the whole existing image is
+//! formatted.
+
+extern crate fat32;
+
+mod image;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::process;
+
+use fat32::format;
+
+use image::Image;
+
+fn usage() -> ! {
+    eprintln!("usage: mkfs <image> [size]");
+    eprintln!("  size  the size of the image to create, in bytes or with a K, M or G suffix");
+    process::exit(1);
+}
+
+fn fail<E: ::std::fmt::Debug>(what: &str, error: E) -> ! {
+    eprintln!("mkfs: {}: {:?}", what, error);
+    process::exit(1);
+}
+
+/// Parses a size such as `512`, `64K`, `32M` or `2G`.
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, unit) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1 << 10),
+        'M' | 'm' => (&size[..size.len() - 1], 1 << 20),
+        'G' | 'g' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, size) = match args.len() {
+        1 => (&args[0], None),
+        2 => (&args[0], Some(parse_size(&args[1]).unwrap_or_else(|| usage()))),
+        _ => usage(),
+    };
+
+    let file = OpenOptions::new().read(true).write(true).create(size.is_some()).open(path)
+        .unwrap_or_else(|e| fail(path, e));
+    if let Some(size) = size {
+        file.set_len(size).unwrap_or_else(|e| fail(path, e));
+    }
+
+    let bytes = file.metadata().unwrap_or_else(|e| fail(path, e)).len();
+    let layout = format::format(Image(file), bytes / 512).unwrap_or_else(|e| fail(path, e));
+    println!("{}: {} sectors, {} clusters of {} bytes, {} sectors per FAT",
+             path, layout.sectors, layout.clusters, layout.sectors_per_cluster as u64 * 512,
+             layout.sectors_per_fat);
+}
@@ -0,0 +1,366 @@
+//! Consistency checking and repair of FAT volumes.
+//!
+//! `check()` walks every directory reachable from the root directory and
+//! cross-references the cluster chains of files and directories against the
+//! FAT, much like `fsck.vfat`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use vfat::{VFat, Shared, Cluster, Status};
+use vfat::dir::{Dir, VFatDirEntry};
+
+/// A problem found by `check()`. Paths are absolute; clusters are raw
+/// cluster numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A cluster is marked as in use but belongs to no file or directory.
+    LostCluster(u32),
+    /// The chain of `path` runs into `cluster`, which belongs to `owner`.
+    CrossLinked { path: String, owner: String, cluster: u32 },
+    /// The chain of `path` runs into `cluster`, which is free, bad, reserved,
+    /// outside the data region, or already part of the chain.
+    BrokenChain { path: String, cluster: u32 },
+    /// The size of the file `path` does not fit the `clusters` clusters of its
+    /// chain.
+    SizeMismatch { path: String, size: u32, clusters: u32 },
+    /// The checksum in the LFN entries of the entry at `index` in the
+    /// directory `dir` does not match its 8.3 name.
+    BadLfnChecksum { dir: String, index: usize },
+    /// The LFN entry at `index` in the directory `dir` belongs to no regular
+    /// entry.
+    OrphanedLfn { dir: String, index: usize },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Problem::*;
+
+        match *self {
+            LostCluster(cluster) => write!(f, "cluster {} is lost", cluster),
+            CrossLinked { ref path, ref owner, cluster } => {
+                write!(f, "{} is cross-linked with {} at cluster {}", path, owner, cluster)
+            }
+            BrokenChain { ref path, cluster } => {
+                write!(f, "chain of {} is broken at cluster {}", path, cluster)
+            }
+            SizeMismatch { ref path, size, clusters } => {
+                write!(f, "{} has a size of {} bytes but {} clusters", path, size, clusters)
+            }
+            BadLfnChecksum { ref dir, index } => {
+                write!(f, "bad LFN checksum for entry {} of {}", index, dir)
+            }
+            OrphanedLfn { ref dir, index } => {
+                write!(f, "orphaned LFN entry {} in {}", index, dir)
+            }
+        }
+    }
+}
+
+/// The result of checking a volume.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Every problem found, in the order it was found.
+    pub problems: Vec<Problem>,
+    /// The number of files found.
+    pub files: usize,
+    /// The number of directories found, including the root directory.
+    pub dirs: usize,
+    /// The number of clusters belonging to files and directories.
+    pub used_clusters: u32,
+    /// Whether the volume was not cleanly unmounted.
+    pub dirty: bool,
+    /// Whether the problems found have been repaired.
+    pub repaired: bool,
+}
+
+impl Report {
+    /// Returns `true` if no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+struct Checker {
+    vfat: Shared<VFat>,
+    repair: bool,
+    /// The path of every file and directory found, indexed by owner.
+    paths: Vec<String>,
+    /// The owner of every cluster claimed so far.
+    owners: HashMap<u32, usize>,
+    report: Report,
+}
+
+/// Checks the file system `vfat` for lost clusters, cross-linked and broken
+/// chains, files whose size does not match their chain, and LFN entries with
+/// a bad checksum or without a regular entry.
+///
+/// If `repair` is `true`, every problem found is repaired: broken and
+/// cross-linked chains are cut off before the offending cluster, file sizes
+/// are fitted to their chains, lost clusters are freed, and bad LFN entries
+/// are deleted. The volume is then synced and marked clean.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidData` if the root directory's chain is
+/// invalid, or any error encountered while reading or writing the volume.
+pub fn check(vfat: &Shared<VFat>, repair: bool) -> io::Result<Report> {
+    let mut checker = Checker {
+        vfat: vfat.clone(),
+        repair,
+        paths: Vec::new(),
+        owners: HashMap::new(),
+        report: Report::default(),
+    };
+    checker.report.dirty = vfat.borrow().mounted_dirty();
+
+    let root = vfat.borrow().root_dir_cluster();
+    let owner = checker.add_path("/".to_string());
+    // The fixed root directory of FAT12 and FAT16 volumes has no chain.
+    if !root.is_root_region() && checker.claim_chain(root, owner)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "invalid root directory cluster"));
+    }
+    checker.report.dirs += 1;
+
+    let mut pending = vec![(root, "/".to_string())];
+    while let Some((cluster, path)) = pending.pop() {
+        checker.check_dir(cluster, &path, &mut pending)?;
+    }
+    checker.check_lost_clusters()?;
+
+    if repair {
+        let mut vfat = vfat.borrow_mut();
+        vfat.mark_checked();
+        vfat.sync()?;
+        checker.report.repaired = !checker.report.problems.is_empty();
+    }
+
+    Ok(checker.report)
+}
+
+impl Checker {
+    fn add_path(&mut self, path: String) -> usize {
+        self.paths.push(path);
+        self.paths.len() - 1
+    }
+
+    fn is_data_cluster(&self, raw: u32) -> bool {
+        raw >= 2 && raw < self.vfat.borrow().cluster_count() + 2
+    }
+
+    /// Follows the chain starting at `start`, claiming its clusters for
+    /// `owner`, and returns the number of clusters claimed. When repairing, a
+    /// chain that runs into an invalid or already claimed cluster is ended
+    /// before it.
+    fn claim_chain(&mut self, start: Cluster, owner: usize) -> io::Result<u32> {
+        let mut count = 0;
+        let mut prev = None;
+        let mut cluster = start;
+
+        loop {
+            let raw = cluster.raw();
+            let status = if self.is_data_cluster(raw) {
+                Some(self.vfat.borrow_mut().fat_entry(cluster)?.status())
+            } else {
+                None
+            };
+
+            let claimed = self.owners.get(&raw).cloned();
+            let problem = match (status, claimed) {
+                (Some(_), Some(other)) if other != owner => {
+                    Some(Problem::CrossLinked { path: self.paths[owner].clone(),
+                                                owner: self.paths[other].clone(),
+                                                cluster: raw })
+                }
+                (Some(Status::Data(_)), None) | (Some(Status::Eoc(_)), None) => None,
+                _ => Some(Problem::BrokenChain { path: self.paths[owner].clone(),
+                                                 cluster: raw }),
+            };
+
+            if let Some(problem) = problem {
+                self.report.problems.push(problem);
+                if let (true, Some(prev)) = (self.repair, prev) {
+                    self.vfat.borrow_mut().set_fat_entry(prev, Status::Eoc(0x0FFFFFFF))?;
+                }
+                break;
+            }
+
+            self.owners.insert(raw, owner);
+            self.report.used_clusters += 1;
+            count += 1;
+            match status {
+                Some(Status::Data(next)) => {
+                    prev = Some(cluster);
+                    cluster = next;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Reports the LFN entries at `indices` of the directory `dir` as
+    /// orphaned and, when repairing, deletes them.
+    fn orphan(&mut self, dir: &str, data: &mut [VFatDirEntry], indices: &mut Vec<usize>) {
+        for &index in indices.iter() {
+            self.report.problems.push(Problem::OrphanedLfn { dir: dir.to_string(), index });
+            if self.repair {
+                unsafe { data[index].unknown.set_unused(); }
+            }
+        }
+        indices.clear();
+    }
+
+    /// Checks the entries of the directory `path` starting at `cluster` and
+    /// the chains of its children. Subdirectories are pushed onto `pending`.
+    fn check_dir(&mut self, cluster: Cluster, path: &str,
+                 pending: &mut Vec<(Cluster, String)>) -> io::Result<()> {
+        let dir = Dir::new(cluster, self.vfat.clone());
+        let mut data = dir.raw_entries()?;
+        let problems = self.report.problems.len();
+
+        // The indices of the LFN entries read since the last regular entry,
+        // the sequence number expected next and the checksum they share.
+        let mut lfns: Vec<usize> = Vec::new();
+        let mut expected = 0;
+        let mut checksum = 0;
+
+        for index in 0..data.len() {
+            let unknown = unsafe { data[index].unknown };
+            if unknown.is_end() {
+                break;
+            }
+            if unknown.is_unused() {
+                self.orphan(path, &mut data, &mut lfns);
+                continue;
+            }
+
+            if unknown.is_LFN() {
+                let lfn = unsafe { data[index].long_filename };
+                let continues = !lfns.is_empty() && !lfn.is_last()
+                    && lfn.has_sequence_number() && lfn.sequence_number() == expected
+                    && lfn.checksum() == checksum;
+
+                if continues {
+                    lfns.push(index);
+                    expected -= 1;
+                } else {
+                    self.orphan(path, &mut data, &mut lfns);
+                    lfns.push(index);
+                    if lfn.is_last() && lfn.has_sequence_number() {
+                        expected = lfn.sequence_number() - 1;
+                        checksum = lfn.checksum();
+                    } else {
+                        self.orphan(path, &mut data, &mut lfns);
+                    }
+                }
+                continue;
+            }
+
+            let mut entry = unsafe { data[index].regular };
+            let mut name = entry.filename();
+            // The LFN entries naming this entry, removed along with it.
+            let mut entry_lfns = Vec::new();
+            if !lfns.is_empty() {
+                if expected != 0 {
+                    self.orphan(path, &mut data, &mut lfns);
+                } else if checksum != entry.checksum() {
+                    self.report.problems.push(Problem::BadLfnChecksum { dir: path.to_string(),
+                                                                        index });
+                    if self.repair {
+                        for &lfn in lfns.iter() {
+                            unsafe { data[lfn].unknown.set_unused(); }
+                        }
+                    }
+                } else {
+                    let mut units = Vec::new();
+                    for &lfn in lfns.iter().rev() {
+                        unsafe { data[lfn].long_filename.append_name(&mut units); }
+                    }
+                    name = String::from_utf16_lossy(&units);
+                    entry_lfns = ::std::mem::replace(&mut lfns, Vec::new());
+                }
+                lfns.clear();
+            }
+
+            if entry.attributes().volume_id() || name == "." || name == ".." {
+                continue;
+            }
+
+            let child = if path == "/" {
+                format!("/{}", name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+            let start = entry.cluster();
+            let owner = self.add_path(child.clone());
+
+            if entry.is_dir() {
+                self.report.dirs += 1;
+                if self.claim_chain(start, owner)? > 0 {
+                    pending.push((start, child));
+                } else if self.repair {
+                    for &index in entry_lfns.iter().chain(Some(index).iter()) {
+                        unsafe { data[index].unknown.set_unused(); }
+                    }
+                }
+                continue;
+            }
+
+            self.report.files += 1;
+            let clusters = if start.raw() == 0 { 0 } else { self.claim_chain(start, owner)? };
+            let cluster_size = self.vfat.borrow().cluster_size() as u64;
+            let size = entry.file_size();
+            let needed = ((size as u64 + cluster_size - 1) / cluster_size) as u32;
+
+            if clusters != needed {
+                self.report.problems.push(Problem::SizeMismatch { path: child, size, clusters });
+            }
+
+            if self.repair {
+                if clusters > needed {
+                    let start = self.vfat.borrow_mut().resize_chain(start, size as usize)?;
+                    entry.set_cluster(start);
+                } else if clusters < needed {
+                    let fitted = ::std::cmp::min(clusters as u64 * cluster_size, size as u64);
+                    entry.set_file_size(fitted as u32);
+                }
+                if clusters == 0 {
+                    entry.set_cluster(Cluster::from(0));
+                }
+                data[index].regular = entry;
+            }
+        }
+
+        if self.repair && self.report.problems.len() > problems {
+            dir.write_raw_entries(0, &data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports every cluster that is in use but was not claimed by a file or
+    /// directory and, when repairing, frees it.
+    fn check_lost_clusters(&mut self) -> io::Result<()> {
+        let cluster_count = self.vfat.borrow().cluster_count();
+        for raw in 2..cluster_count + 2 {
+            if self.owners.contains_key(&raw) {
+                continue;
+            }
+
+            let cluster = Cluster::from(raw);
+            let status = self.vfat.borrow_mut().fat_entry(cluster)?.status();
+            if status != Status::Free && status != Status::Bad {
+                self.report.problems.push(Problem::LostCluster(raw));
+                if self.repair {
+                    self.vfat.borrow_mut().set_fat_entry(cluster, Status::Free)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Creation of FAT32 file systems.
+
+use std::io;
+
+use traits::BlockDevice;
+
+/// The sector at which `format()` starts the FAT32 partition, aligning it to
+/// 1MiB.
+pub const PARTITION_START: u64 = 2048;
+
+const RESERVED_SECTORS: u64 = 32;
+const FAT_COUNT: u64 = 2;
+const FSINFO_SECTOR: u64 = 1;
+const BACKUP_BOOT_SECTOR: u64 = 6;
+const ROOT_CLUSTER: u32 = 2;
+const MEDIA_DESCRIPTOR: u8 = 0xF8;
+/// The fewest clusters a volume can have without being FAT12 or FAT16.
+const MIN_CLUSTERS: u64 = 65525;
+const MAX_CLUSTERS: u64 = 0x0FFFFFF5 - 2;
+
+/// The layout of a file system created by `format()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// The number of sectors in the partition.
+    pub sectors: u64,
+    /// The number of sectors per cluster.
+    pub sectors_per_cluster: u8,
+    /// The number of sectors in each FAT.
+    pub sectors_per_fat: u64,
+    /// The number of clusters in the data region.
+    pub clusters: u64,
+}
+
+impl Layout {
+    /// Computes the layout of a FAT32 partition of `sectors` sectors of
+    /// `sector_size` bytes, or `None` if the partition is too small or too
+    /// large to hold one. FAT32 volumes need at least 65525 clusters, so the
+    /// smallest partition is about 33MiB.
+    pub fn new(sectors: u64, sector_size: u64) -> Option<Layout> {
+        let sectors_per_cluster = Layout::sectors_per_cluster(sectors * sector_size, sector_size);
+
+        // Grow the FATs until they can hold every cluster that is left.
+        let mut sectors_per_fat = 1;
+        loop {
+            let data_sectors = sectors.checked_sub(RESERVED_SECTORS + FAT_COUNT * sectors_per_fat)?;
+            let clusters = data_sectors / sectors_per_cluster as u64;
+            let needed = ((clusters + 2) * 4 + sector_size - 1) / sector_size;
+            if needed <= sectors_per_fat {
+                if clusters < MIN_CLUSTERS || clusters > MAX_CLUSTERS {
+                    return None;
+                }
+                return Some(Layout { sectors, sectors_per_cluster, sectors_per_fat, clusters });
+            }
+            sectors_per_fat = needed;
+        }
+    }
+
+    /// Chooses the cluster size for a volume of `bytes` bytes the way
+    /// Microsoft's formatter does for FAT32.
+    fn sectors_per_cluster(bytes: u64, sector_size: u64) -> u8 {
+        const MB: u64 = 1024 * 1024;
+        let cluster_size = if bytes <= 260 * MB {
+            512
+        } else if bytes <= 8 * 1024 * MB {
+            4096
+        } else if bytes <= 16 * 1024 * MB {
+            8192
+        } else if bytes <= 32 * 1024 * MB {
+            16384
+        } else {
+            32768
+        };
+        ::std::cmp::max(1, cluster_size / sector_size) as u8
+    }
+
+    /// The sector offset, from the start of the partition, to the first data
+    /// sector.
+    fn data_start_sector(&self) -> u64 {
+        RESERVED_SECTORS + FAT_COUNT * self.sectors_per_fat
+    }
+}
+
+/// Stores `value` as a little-endian integer of `width` bytes at `offset` in
+/// `buf`.
+fn put(buf: &mut [u8], offset: usize, width: usize, value: u64) {
+    for i in 0..width {
+        buf[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Formats the `sectors` sectors of `device` as a disk holding a single FAT32
+/// partition: an MBR with one partition of type 0x0C starting at
+/// `PARTITION_START`, a boot sector and its backup, an FSInfo sector, two FATs
+/// and an empty root directory. The cluster size is chosen from the size of
+/// the partition. The volume serial number is derived from its size, so that
+/// formatting is deterministic. Returns the layout of the new file system.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `sectors` is too small or too large
+/// to hold a FAT32 partition, or any error encountered while writing to
+/// `device`.
+pub fn format<T: BlockDevice>(mut device: T, sectors: u64) -> io::Result<Layout> {
+    let sector_size = device.sector_size();
+    let too_small = || io::Error::new(io::ErrorKind::InvalidInput,
+                                      "device too small or too large for FAT32");
+    let partition_sectors = sectors.checked_sub(PARTITION_START).ok_or_else(too_small)?;
+    if partition_sectors > ::std::u32::MAX as u64 {
+        return Err(too_small());
+    }
+    let layout = Layout::new(partition_sectors, sector_size).ok_or_else(too_small)?;
+    let serial = 0x52757374 ^ partition_sectors as u32;
+
+    let mut mbr = vec![0u8; sector_size as usize];
+    put(&mut mbr, 440, 4, serial as u64);
+    // CHS addresses are unused; 0xFEFFFF tells readers to use the LBA fields.
+    mbr[446..462].copy_from_slice(&[0x00, 0xFE, 0xFF, 0xFF, 0x0C, 0xFE, 0xFF, 0xFF,
+                                    0, 0, 0, 0, 0, 0, 0, 0]);
+    put(&mut mbr, 454, 4, PARTITION_START);
+    put(&mut mbr, 458, 4, partition_sectors);
+    mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let mut boot = vec![0u8; sector_size as usize];
+    boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"RUSTABLE");
+    put(&mut boot, 11, 2, sector_size);
+    boot[13] = layout.sectors_per_cluster;
+    put(&mut boot, 14, 2, RESERVED_SECTORS);
+    boot[16] = FAT_COUNT as u8;
+    boot[21] = MEDIA_DESCRIPTOR;
+    put(&mut boot, 24, 2, 63);
+    put(&mut boot, 26, 2, 255);
+    put(&mut boot, 28, 4, PARTITION_START);
+    put(&mut boot, 32, 4, partition_sectors);
+    put(&mut boot, 36, 4, layout.sectors_per_fat);
+    put(&mut boot, 44, 4, ROOT_CLUSTER as u64);
+    put(&mut boot, 48, 2, FSINFO_SECTOR);
+    put(&mut boot, 50, 2, BACKUP_BOOT_SECTOR);
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    put(&mut boot, 67, 4, serial as u64);
+    boot[71..82].copy_from_slice(b"NO NAME    ");
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let mut fsinfo = vec![0u8; sector_size as usize];
+    put(&mut fsinfo, 0, 4, 0x41615252);
+    put(&mut fsinfo, 484, 4, 0x61417272);
+    put(&mut fsinfo, 488, 4, layout.clusters - 1);
+    put(&mut fsinfo, 492, 4, ROOT_CLUSTER as u64 + 1);
+    put(&mut fsinfo, 508, 4, 0xAA550000);
+
+    // FAT[0] holds the media descriptor, FAT[1] the clean shutdown and no
+    // hard error bits, and the root directory is a single cluster chain.
+    let mut fat = vec![0u8; sector_size as usize];
+    put(&mut fat, 0, 4, 0x0FFFFF00 | MEDIA_DESCRIPTOR as u64);
+    put(&mut fat, 4, 4, 0x0FFFFFFF);
+    put(&mut fat, 8, 4, 0x0FFFFFFF);
+
+    // Zero the reserved sectors, the FATs and the root directory first.
+    let zeroes = vec![0u8; sector_size as usize];
+    let cleared = layout.data_start_sector() + layout.sectors_per_cluster as u64;
+    for sector in 0..cleared {
+        device.write_sector(PARTITION_START + sector, &zeroes)?;
+    }
+
+    device.write_sector(0, &mbr)?;
+    for &offset in [0, BACKUP_BOOT_SECTOR].iter() {
+        device.write_sector(PARTITION_START + offset, &boot)?;
+        device.write_sector(PARTITION_START + offset + FSINFO_SECTOR, &fsinfo)?;
+    }
+    for i in 0..FAT_COUNT {
+        device.write_sector(PARTITION_START + RESERVED_SECTORS + i * layout.sectors_per_fat, &fat)?;
+    }
+
+    Ok(layout)
+}
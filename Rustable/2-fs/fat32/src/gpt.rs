@@ -0,0 +1,263 @@
+use std::{fmt, io};
+
+use mbr::MasterBootRecord;
+use partition::PartitionInfo;
+use traits::BlockDevice;
+
+/// A globally unique identifier as stored on disk: the first three fields are
+/// little-endian, the last two big-endian.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The type of unused partition entries.
+    pub const UNUSED: Guid = Guid([0; 16]);
+    /// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, the type of FAT and NTFS data
+    /// partitions.
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid([0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+                                                 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+    /// C12A7328-F81F-11D2-BA4B-00A0C93EC93B, the type of EFI system
+    /// partitions, which are formatted FAT.
+    pub const EFI_SYSTEM: Guid = Guid([0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+                                       0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in b[10..].iter() {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A used entry of the GPT partition array.
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    /// The index of the entry in the partition array.
+    pub index: usize,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    /// The first sector of the partition.
+    pub first_lba: u64,
+    /// The last sector of the partition, inclusive.
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    /// The number of sectors in the partition.
+    pub fn sectors(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+
+    /// Returns `true` if the partition's type allows it to hold a FAT file
+    /// system. See `PartitionInfo::may_be_fat()`.
+    pub fn may_be_fat(&self) -> bool {
+        PartitionInfo::from(self).may_be_fat()
+    }
+}
+
+/// A GUID partition table (GPT), read from its primary header or, if that or
+/// its partition array is invalid, from the backup at the end of the disk.
+#[derive(Debug, Clone)]
+pub struct GuidPartitionTable {
+    pub disk_guid: Guid,
+    /// The first and last sectors usable by partitions.
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    /// The used entries of the partition array, in order.
+    pub partitions: Vec<GptPartition>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The header signature was not "EFI PART".
+    BadSignature,
+    /// The header size or partition entry size is invalid.
+    BadHeader,
+    /// The CRC32 of the header or of the partition array does not match.
+    BadChecksum,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// Returns the CRC32 (IEEE 802.3) of `data`, as used by GPT.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn le(buf: &[u8]) -> u64 {
+    buf.iter().rev().fold(0, |value, &byte| value << 8 | byte as u64)
+}
+
+fn guid(buf: &[u8]) -> Guid {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&buf[..16]);
+    Guid(guid)
+}
+
+impl GuidPartitionTable {
+    /// The sector of the primary GPT header.
+    const HEADER_LBA: u64 = 1;
+    const SIGNATURE: &'static [u8] = b"EFI PART";
+    const MIN_HEADER_SIZE: usize = 92;
+    const MIN_ENTRY_SIZE: usize = 128;
+    const MAX_ENTRY_SIZE: usize = 4096;
+    /// The most partition entries read, four times the usual array size.
+    const MAX_ENTRIES: usize = 512;
+
+    /// Reads and returns the GUID partition table from `device`, which must
+    /// start with a protective MBR.
+    ///
+    /// If the primary header or partition array is invalid, the backup header
+    /// is read instead. It is found in the sector the primary header names,
+    /// or, if the primary header itself is invalid, in the last sector of the
+    /// disk according to the protective MBR.
+    ///
+    /// # Errors
+    ///
+    /// When neither table is valid, the error found in the primary one is
+    /// returned: `BadSignature` if the header signature is invalid,
+    /// `BadHeader` if its sizes are invalid, `BadChecksum` if the CRC32 of the
+    /// header or of the partition array does not match, and `Io(err)` if the
+    /// I/O error `err` occurred while reading.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mut alternate_lba = None;
+        let primary = match GuidPartitionTable::read(&mut device, GuidPartitionTable::HEADER_LBA,
+                                                     &mut alternate_lba) {
+            Ok(gpt) => return Ok(gpt),
+            Err(error) => error,
+        };
+
+        let backup_lba = alternate_lba.or_else(|| GuidPartitionTable::last_lba(&mut device));
+        match backup_lba {
+            Some(lba) if lba > GuidPartitionTable::HEADER_LBA => {
+                GuidPartitionTable::read(&mut device, lba, &mut None).map_err(|_| primary)
+            }
+            _ => Err(primary),
+        }
+    }
+
+    /// Returns the last sector of `device` as recorded by its protective MBR,
+    /// or `None` if the MBR cannot be read or the disk is too large for it.
+    fn last_lba<T: BlockDevice>(device: &mut T) -> Option<u64> {
+        let mbr = MasterBootRecord::from(device).ok()?;
+        let entry = mbr.partition_table.iter()
+            .find(|entry| entry.partition_type == MasterBootRecord::PROTECTIVE)?;
+        let (start, sectors) = (entry.relative_sector, entry.total_sectors_in_partition);
+        if sectors == ::std::u32::MAX {
+            return None;
+        }
+        Some(start as u64 + sectors as u64 - 1)
+    }
+
+    /// Reads the table whose header is in sector `lba`. Once the header is
+    /// known to be intact, `alternate_lba` is set to the sector of the other
+    /// header.
+    fn read<T: BlockDevice>(device: &mut T, lba: u64, alternate_lba: &mut Option<u64>)
+        -> Result<GuidPartitionTable, Error>
+    {
+        let mut header = Vec::new();
+        device.read_all_sector(lba, &mut header)?;
+        if &header[0..8] != GuidPartitionTable::SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        let header_size = le(&header[12..16]) as usize;
+        if header_size < GuidPartitionTable::MIN_HEADER_SIZE || header_size > header.len() {
+            return Err(Error::BadHeader);
+        }
+
+        // The header CRC is computed with the CRC field zeroed.
+        let header_crc = le(&header[16..20]) as u32;
+        let mut zeroed = header[..header_size].to_vec();
+        zeroed[16..20].copy_from_slice(&[0; 4]);
+        if crc32(&zeroed) != header_crc {
+            return Err(Error::BadChecksum);
+        }
+        if le(&header[24..32]) != lba {
+            return Err(Error::BadHeader);
+        }
+        *alternate_lba = Some(le(&header[32..40]));
+
+        let entries_lba = le(&header[72..80]);
+        let entry_count = le(&header[80..84]) as usize;
+        let entry_size = le(&header[84..88]) as usize;
+        if entry_size < GuidPartitionTable::MIN_ENTRY_SIZE
+            || entry_size > GuidPartitionTable::MAX_ENTRY_SIZE || entry_size % 8 != 0
+            || entry_count > GuidPartitionTable::MAX_ENTRIES {
+            return Err(Error::BadHeader);
+        }
+
+        let array_size = entry_count.checked_mul(entry_size).ok_or(Error::BadHeader)?;
+        let sector_size = device.sector_size() as usize;
+        let mut array = Vec::new();
+        for i in 0..(array_size + sector_size - 1) / sector_size {
+            device.read_all_sector(entries_lba + i as u64, &mut array)?;
+        }
+        array.truncate(array_size);
+        if crc32(&array) != le(&header[88..92]) as u32 {
+            return Err(Error::BadChecksum);
+        }
+
+        let mut partitions = Vec::new();
+        for (index, entry) in array.chunks(entry_size).enumerate() {
+            let type_guid = guid(&entry[0..16]);
+            if type_guid == Guid::UNUSED {
+                continue;
+            }
+
+            let name: Vec<u16> = entry[56..128].chunks(2)
+                .map(|unit| le(unit) as u16)
+                .take_while(|&unit| unit != 0)
+                .collect();
+            partitions.push(GptPartition {
+                index,
+                type_guid,
+                unique_guid: guid(&entry[16..32]),
+                first_lba: le(&entry[32..40]),
+                last_lba: le(&entry[40..48]),
+                attributes: le(&entry[48..56]),
+                name: String::from_utf16_lossy(&name),
+            });
+        }
+
+        Ok(GuidPartitionTable {
+            disk_guid: guid(&header[56..72]),
+            first_usable_lba: le(&header[40..48]),
+            last_usable_lba: le(&header[48..56]),
+            partitions,
+        })
+    }
+
+    /// Returns an iterator over the partitions of type `type_guid`.
+    pub fn partitions_of_type<'a>(&'a self, type_guid: Guid)
+        -> impl Iterator<Item = &'a GptPartition> + 'a
+    {
+        self.partitions.iter().filter(move |partition| partition.type_guid == type_guid)
+    }
+}
@@ -11,5 +11,10 @@ mod util;
 
 pub mod vfat;
 pub mod traits;
+pub mod check;
+pub mod format;
+pub mod gpt;
+pub mod partition;
+pub mod walk;
 
 pub use mbr::*;
@@ -18,7 +18,7 @@ pub struct PartitionEntry {
     pub partition_type: u8,
     ending_CHS: CHS,
     pub relative_sector: u32,
-    total_sectors_in_partition: u32,
+    pub total_sectors_in_partition: u32,
 }
 
 /// The master boot record (MBR).
@@ -41,6 +41,10 @@ pub enum Error {
 }
 
 impl MasterBootRecord {
+    /// The partition type of the single entry of a protective MBR, which
+    /// shields a GUID partition table from tools that only know MBRs.
+    pub const PROTECTIVE: u8 = 0xEE;
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -75,6 +79,13 @@ impl MasterBootRecord {
 
         Ok(mbr)
     }
+
+    /// Returns `true` if this is a protective MBR: the disk is partitioned by
+    /// the GUID partition table that follows it.
+    pub fn is_protective(&self) -> bool {
+        self.partition_table.iter()
+            .any(|entry| entry.partition_type == MasterBootRecord::PROTECTIVE)
+    }
 }
 
 impl fmt::Debug for MasterBootRecord {
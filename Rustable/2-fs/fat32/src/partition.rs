@@ -0,0 +1,174 @@
+//! Enumeration of the partitions of a disk, and views of each partition as a
+//! block device of its own.
+
+use std::io;
+
+use gpt::{self, Guid, GptPartition, GuidPartitionTable};
+use mbr::{self, MasterBootRecord};
+use traits::BlockDevice;
+
+/// The partition table entry a partition was found in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// An MBR entry with the given partition type.
+    Mbr(u8),
+    /// A GPT entry with the given partition type GUID.
+    Gpt(Guid),
+}
+
+/// A used partition table entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// The index of the entry in the MBR partition table or GPT partition
+    /// array.
+    pub index: usize,
+    pub kind: PartitionKind,
+    /// The first sector of the partition.
+    pub start: u64,
+    /// The number of sectors in the partition.
+    pub sectors: u64,
+}
+
+impl PartitionInfo {
+    /// Returns `true` if the partition's type allows it to hold a FAT file
+    /// system.
+    pub fn may_be_fat(&self) -> bool {
+        match self.kind {
+            PartitionKind::Mbr(partition_type) => match partition_type {
+                0x01 | 0x04 | 0x06 | 0x0E | 0x0B | 0x0C => true,
+                _ => false
+            },
+            PartitionKind::Gpt(type_guid) => {
+                type_guid == Guid::MICROSOFT_BASIC_DATA || type_guid == Guid::EFI_SYSTEM
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a GptPartition> for PartitionInfo {
+    fn from(partition: &'a GptPartition) -> PartitionInfo {
+        PartitionInfo {
+            index: partition.index,
+            kind: PartitionKind::Gpt(partition.type_guid),
+            start: partition.first_lba,
+            sectors: partition.sectors(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The MBR could not be read.
+    Mbr(mbr::Error),
+    /// The MBR is protective and the GPT that follows could not be read.
+    Gpt(gpt::Error),
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+/// Returns the used entries of the partition table of `device`, in order: the
+/// GPT's if its MBR is protective, otherwise the MBR's. Extended MBR
+/// partitions are listed but not followed.
+pub fn partitions<T: BlockDevice>(mut device: T) -> Result<Vec<PartitionInfo>, Error> {
+    let mbr = MasterBootRecord::from(&mut device)?;
+    if mbr.is_protective() {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        return Ok(gpt.partitions.iter().map(PartitionInfo::from).collect());
+    }
+
+    Ok(mbr.partition_table.iter().enumerate()
+        .filter(|&(_, entry)| entry.partition_type != 0)
+        .map(|(index, entry)| PartitionInfo {
+            index,
+            kind: PartitionKind::Mbr(entry.partition_type),
+            start: entry.relative_sector as u64,
+            sectors: entry.total_sectors_in_partition as u64,
+        })
+        .collect())
+}
+
+/// A view of one partition of `T` as a block device whose sector 0 is the
+/// first sector of the partition. Accesses past the end of the partition
+/// fail.
+#[derive(Debug)]
+pub struct PartitionDevice<T> {
+    device: T,
+    info: PartitionInfo,
+}
+
+impl<T: BlockDevice> PartitionDevice<T> {
+    /// Returns a view of the partition `info` of `device`.
+    pub fn new(device: T, info: PartitionInfo) -> PartitionDevice<T> {
+        PartitionDevice { device, info }
+    }
+
+    /// Returns a view of the partition in entry `index` of the partition
+    /// table of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Ok(None)` if entry `index` is unused, or an error if the
+    /// partition table cannot be read.
+    pub fn open(mut device: T, index: usize) -> Result<Option<PartitionDevice<T>>, Error> {
+        let info = partitions(&mut device)?.into_iter().find(|info| info.index == index);
+        Ok(info.map(|info| PartitionDevice::new(device, info)))
+    }
+
+    /// The partition this is a view of.
+    pub fn info(&self) -> &PartitionInfo {
+        &self.info
+    }
+
+    /// Returns the underlying device.
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    /// Maps the `count` sectors starting at sector `n` of the partition to
+    /// the first of them on the underlying device.
+    fn sectors(&self, n: u64, count: u64) -> io::Result<u64> {
+        if n.checked_add(count).map_or(true, |end| end > self.info.sectors) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "sector out of partition bounds"));
+        }
+        Ok(self.info.start + n)
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for PartitionDevice<T> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.sectors(n, 1)?;
+        self.device.read_sector(sector, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector = self.sectors(n, 1)?;
+        self.device.write_sector(sector, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let count = (buf.len() as u64 + self.sector_size() - 1) / self.sector_size();
+        let sector = self.sectors(n, count)?;
+        self.device.read_sectors(sector, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let count = (buf.len() as u64 + self.sector_size() - 1) / self.sector_size();
+        let sector = self.sectors(n, count)?;
+        self.device.write_sectors(sector, buf)
+    }
+}
@@ -6,6 +6,7 @@ use std::path::Path;
 
 use vfat::{Shared, VFat, BiosParameterBlock};
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
+use gpt::{GuidPartitionTable, Guid, Error as GptError, crc32};
 use traits::*;
 
 macro check_size($T:ty, $size:expr) {
@@ -57,6 +58,101 @@ macro vfat_from_resource($name:expr) {
     VFat::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+macro writable_vfat_from_resource($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    VFat::from(Cursor::new(data)).expect("failed to initialize VFAT from image")
+}}
+
+/// The size in sectors of the disks made by `formatted_disk()`, which is
+/// just large enough for a FAT32 volume.
+const FORMATTED_SECTORS: usize = 40 * 2048;
+
+/// Returns a zeroed in-memory disk of `FORMATTED_SECTORS` sectors after
+/// `format()` has written a FAT32 partition to it, and the partition's layout.
+fn formatted_disk() -> (SharedDevice, ::format::Layout) {
+    let device = SharedDevice::new(vec![0; FORMATTED_SECTORS * 512]);
+    let layout = ::format::format(device.clone(), FORMATTED_SECTORS as u64).expect("format");
+    (device, layout)
+}
+
+/// Returns the empty FAT32 volume of a disk made by `formatted_disk()`.
+fn formatted_vfat() -> Shared<VFat> {
+    VFat::from(formatted_disk().0).expect("mount")
+}
+
+/// An in-memory block device whose contents outlive the `VFat` or
+/// `CachedDevice` that owns it. Counts the sectors written to it, and the
+/// transfers to or from it: a multi-sector transfer counts once.
+#[derive(Clone)]
+struct SharedDevice {
+    data: ::std::sync::Arc<::std::sync::Mutex<Vec<u8>>>,
+    writes: ::std::sync::Arc<::std::sync::Mutex<u64>>,
+    transfers: ::std::sync::Arc<::std::sync::Mutex<u64>>,
+}
+
+impl SharedDevice {
+    fn new(data: Vec<u8>) -> SharedDevice {
+        SharedDevice {
+            data: ::std::sync::Arc::new(::std::sync::Mutex::new(data)),
+            writes: ::std::sync::Arc::new(::std::sync::Mutex::new(0)),
+            transfers: ::std::sync::Arc::new(::std::sync::Mutex::new(0)),
+        }
+    }
+
+    fn from_resource(mut file: ::std::fs::File) -> SharedDevice {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).expect("read resource data");
+        SharedDevice::new(data)
+    }
+
+    fn writes(&self) -> u64 {
+        *self.writes.lock().unwrap()
+    }
+
+    fn transfers(&self) -> u64 {
+        *self.transfers.lock().unwrap()
+    }
+
+    fn sector(&self, n: usize) -> Vec<u8> {
+        self.data.lock().unwrap()[n * 512..(n + 1) * 512].to_vec()
+    }
+
+    /// Returns the byte range of the `len` bytes starting at sector `n`.
+    fn range(&self, n: u64, len: usize) -> ::std::io::Result<::std::ops::Range<usize>> {
+        let start = n as usize * 512;
+        if len % 512 != 0 || start + len > self.data.lock().unwrap().len() {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput,
+                                             "sector out of range"));
+        }
+        *self.transfers.lock().unwrap() += 1;
+        Ok(start..start + len)
+    }
+}
+
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.read_sectors(n, &mut buf[..512])
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.write_sectors(n, &buf[..512])
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let range = self.range(n, buf.len())?;
+        buf.copy_from_slice(&self.data.lock().unwrap()[range]);
+        Ok(buf.len())
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        let range = self.range(n, buf.len())?;
+        self.data.lock().unwrap()[range].copy_from_slice(buf);
+        *self.writes.lock().unwrap() += buf.len() as u64 / 512;
+        Ok(buf.len())
+    }
+}
+
 #[test]
 fn check_mbr_size() {
     check_size!(MasterBootRecord, 512);
@@ -344,6 +440,1267 @@ fn test_mock4_files_recursive() {
     assert_hash_eq!("mock 4 file hashes", hash, hash_for!("files-2-3-4"));
 }
 
+fn read_all<T: File>(mut file: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    data
+}
+
+fn entry_names<T: Dir>(dir: T) -> Vec<String> {
+    let mut names: Vec<_> = dir.entries()
+        .expect("entries interator")
+        .map(|e| e.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_create_and_write_file() {
+    let vfat = writable_vfat_from_resource!("mock1.fat32.img");
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut file = vfat.create_file("/NEWFILE.BIN").expect("create file");
+    file.write_all(&data).expect("write file");
+    assert_eq!(file.size(), data.len() as u64);
+
+    let file = vfat.open_file("/newfile.bin").expect("open new file");
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(read_all(file), data);
+
+    let e = vfat.create_file("/NEWFILE.BIN").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_overwrite_extend_and_truncate_file() {
+    use std::io::SeekFrom;
+
+    let vfat = writable_vfat_from_resource!("mock2.fat32.img");
+    let mut file = vfat.create_file("/DATA.TXT").expect("create file");
+    file.write_all(b"hello, world").expect("write file");
+    file.seek(SeekFrom::Start(7)).expect("seek");
+    file.write_all(b"there, fat32").expect("write file");
+    assert_eq!(read_all(vfat.open_file("/DATA.TXT").unwrap()), b"hello, there, fat32");
+
+    file.set_len(5).expect("truncate");
+    assert_eq!(read_all(vfat.open_file("/DATA.TXT").unwrap()), b"hello");
+
+    file.set_len(8).expect("extend");
+    assert_eq!(read_all(vfat.open_file("/DATA.TXT").unwrap()), b"hello\0\0\0");
+}
+
+#[test]
+fn test_file_cluster_lookups() {
+    use std::io::SeekFrom;
+
+    let vfat = formatted_vfat();
+    let accesses = |vfat: &Shared<VFat>| {
+        let stats = vfat.borrow().cache_stats();
+        stats.hits + stats.misses
+    };
+
+    // 400 clusters of 512 bytes, written and read in chunks that straddle
+    // cluster boundaries.
+    let data: Vec<u8> = (0..400 * 512u32).map(|i| (i % 253) as u8).collect();
+    let mut file = vfat.create_file("/big.bin").expect("create file");
+    for chunk in data.chunks(100) {
+        file.write_all(chunk).expect("write chunk");
+    }
+
+    let mut file = vfat.open_file("/big.bin").expect("open file");
+    let before = accesses(&vfat);
+    let mut contents = Vec::new();
+    let mut chunk = [0u8; 100];
+    loop {
+        let n = file.read(&mut chunk).expect("read chunk");
+        if n == 0 {
+            break;
+        }
+        contents.extend_from_slice(&chunk[..n]);
+    }
+    assert_eq!(contents, data);
+    // At most two data sectors per chunk and one FAT entry per cluster.
+    assert!(accesses(&vfat) - before <= 2 * 2048 + 400);
+
+    // With the chain cached, seeking costs no FAT lookups at all.
+    file.cache_chain().expect("cache chain");
+    let before = accesses(&vfat);
+    for i in 0..400u64 {
+        let offset = (i * 7919) % data.len() as u64;
+        file.seek(SeekFrom::Start(offset)).expect("seek");
+        let mut byte = [0u8; 1];
+        file.read_exact(&mut byte).expect("read byte");
+        assert_eq!(byte[0], data[offset as usize]);
+    }
+    assert_eq!(accesses(&vfat) - before, 400);
+
+    // The cached chain follows the file as it grows and shrinks.
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(&data[..1000]).expect("append");
+    file.set_len(401 * 512).expect("truncate");
+    file.seek(SeekFrom::Start(400 * 512 - 10)).unwrap();
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).expect("read tail");
+    assert_eq!(&tail[..10], &data[data.len() - 10..]);
+    assert_eq!(&tail[10..], &data[..512]);
+
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data[..512]);
+    assert_eq!(read_all(vfat.open_file("/big.bin").unwrap()), expected);
+    assert!(::check::check(&vfat, false).expect("check").is_clean());
+}
+
+#[test]
+fn test_metadata_setters_and_volume_label() {
+    use vfat::{Date, Time, Timestamp};
+
+    let (device, layout) = formatted_disk();
+
+    // Put a volume label entry first in the root directory, which follows
+    // the 32 reserved sectors and both FATs.
+    let root = (::format::PARTITION_START + 32 + 2 * layout.sectors_per_fat) as usize * 512;
+    {
+        let mut data = device.data.lock().unwrap();
+        data[root..root + 11].copy_from_slice(b"RUSTABLE FS");
+        data[root + 11] = 0x08;
+    }
+
+    let stamp = Timestamp::new(2018, 3, 14, 15, 9, 27).expect("timestamp");
+    {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        assert_eq!(vfat.open_dir("/").unwrap().volume_label().expect("volume label"),
+                   Some("RUSTABLE FS".to_string()));
+
+        let mut root = vfat.open("/").expect("open root");
+        assert!(root.metadata().directory());
+        let metadata = root.metadata().clone();
+        assert_eq!(root.set_metadata(metadata).unwrap_err().kind(), ::std::io::ErrorKind::InvalidInput);
+
+        vfat.create_file("/notes.txt").unwrap().write_all(b"hello").expect("write file");
+        vfat.create_dir("/docs", false).expect("create dir");
+
+        let mut file = vfat.open("/notes.txt").expect("open file");
+        assert_eq!(file.metadata().size(), 5);
+        assert!(file.metadata().archive() && !file.metadata().directory());
+        let mut metadata = file.metadata().clone();
+        metadata.set_read_only(true);
+        metadata.set_hidden(true);
+        metadata.set_system(true);
+        metadata.set_archive(false);
+        metadata.set_created(stamp);
+        metadata.set_accessed(stamp);
+        metadata.set_modified(stamp);
+        file.set_metadata(metadata).expect("set file metadata");
+        assert!(file.metadata().read_only() && !file.metadata().archive());
+
+        let mut dir = vfat.open("/docs").expect("open dir");
+        let mut metadata = dir.metadata().clone();
+        metadata.set_hidden(true);
+        dir.set_metadata(metadata).expect("set dir metadata");
+
+        for mut dot in vfat.open_dir("/docs").unwrap().entries().unwrap() {
+            let metadata = dot.metadata().clone();
+            let e = dot.set_metadata(metadata).unwrap_err();
+            assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput, "{}", dot.name());
+        }
+    }
+
+    let vfat = VFat::from(device).expect("remount");
+    let file = vfat.open("/notes.txt").expect("open file");
+    let metadata = file.metadata();
+    assert!(metadata.read_only() && metadata.hidden() && metadata.system());
+    assert!(!metadata.archive() && !metadata.directory() && !metadata.volume_id());
+    assert_eq!(metadata.size(), 5);
+    assert_eq!(metadata.created(), stamp);
+    assert_eq!(metadata.modified(), stamp);
+    assert_eq!((metadata.modified().hour(), metadata.modified().second()), (15, 26));
+    assert_eq!(metadata.accessed(), Timestamp { date: stamp.date, time: Time::default() });
+    assert_eq!(read_all(file.into_file().unwrap()), b"hello");
+
+    let dir = vfat.open("/docs").expect("open dir");
+    assert!(dir.metadata().hidden() && dir.metadata().directory());
+    assert_eq!(dir.metadata().size(), 0);
+
+    assert!(Date::new(1979, 12, 31).is_none());
+    assert!(Date::new(2107, 12, 31).is_some());
+    assert!(Date::new(2021, 2, 31).is_none());
+    assert!(Date::new(2021, 4, 31).is_none());
+    assert!(Date::new(2021, 2, 29).is_none());
+    assert!(Date::new(2020, 2, 29).is_some());
+    assert!(Date::new(2100, 2, 29).is_none());
+    assert!(Date::new(2000, 2, 29).is_some());
+    assert!(Time::new(24, 0, 0).is_none());
+}
+
+#[test]
+fn test_walk_glob_and_stat() {
+    use walk::{Order, Pattern};
+
+    let vfat = formatted_vfat();
+    for dir in ["/bin/sub", "/etc", "/skip"].iter() {
+        vfat.create_dir(dir, true).expect("create dir");
+    }
+    for file in ["/bin/init.elf", "/bin/readme.txt", "/bin/sub/deep.elf", "/etc/motd",
+                 "/skip/x.elf"].iter() {
+        vfat.create_file(file).unwrap().write_all(file.as_bytes()).expect("write file");
+    }
+
+    let paths = |walk: &mut Iterator<Item = ::std::io::Result<::walk::WalkEntry<::vfat::Entry>>>| {
+        walk.map(|entry| entry.unwrap().path.to_str().unwrap().to_string()).collect::<Vec<_>>()
+    };
+
+    let mut walk = vfat.walk("/", Order::DepthFirst).expect("walk");
+    assert_eq!(paths(&mut walk), vec!["/bin", "/bin/sub", "/bin/sub/deep.elf", "/bin/init.elf",
+                                      "/bin/readme.txt", "/etc", "/etc/motd", "/skip",
+                                      "/skip/x.elf"]);
+
+    let mut walk = vfat.walk("/", Order::BreadthFirst).expect("walk");
+    assert_eq!(paths(&mut walk), vec!["/bin", "/etc", "/skip", "/bin/sub", "/bin/init.elf",
+                                      "/bin/readme.txt", "/etc/motd", "/skip/x.elf",
+                                      "/bin/sub/deep.elf"]);
+
+    let mut walk = vfat.walk("/bin", Order::DepthFirst).expect("walk")
+        .prune(|entry| entry.entry.name() == "sub");
+    assert_eq!(paths(&mut walk), vec!["/bin/sub", "/bin/init.elf", "/bin/readme.txt"]);
+
+    let depths: Vec<usize> = vfat.walk("/bin", Order::DepthFirst).unwrap()
+        .map(|entry| entry.unwrap().depth)
+        .collect();
+    assert_eq!(depths, vec![1, 2, 1, 1]);
+
+    assert_eq!(paths(&mut vfat.glob("/bin/*.elf").unwrap()), vec!["/bin/init.elf"]);
+    assert_eq!(paths(&mut vfat.glob("**/*.elf").unwrap()),
+               vec!["/bin/sub/deep.elf", "/bin/init.elf", "/skip/x.elf"]);
+    assert_eq!(paths(&mut vfat.glob("/bin/**").unwrap()),
+               vec!["/bin", "/bin/sub", "/bin/sub/deep.elf", "/bin/init.elf", "/bin/readme.txt"]);
+    assert_eq!(paths(&mut vfat.glob("/?tc/m*").unwrap()), vec!["/etc/motd"]);
+    assert_eq!(paths(&mut vfat.glob("/BIN/*.ELF").unwrap()), vec!["/bin/init.elf"]);
+    let sensitive = Pattern::new("/bin/*.ELF").case_sensitive(true);
+    assert!(vfat.glob_pattern(sensitive).unwrap().next().is_none());
+
+    let metadata = vfat.stat("/bin/readme.txt").expect("stat");
+    assert_eq!(metadata.size(), "/bin/readme.txt".len() as u64);
+    assert!(!metadata.directory());
+    assert!(vfat.stat("/bin/sub").unwrap().directory());
+    assert_eq!(vfat.stat("/nope").unwrap_err().kind(), ::std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_glob_pattern() {
+    use walk::Pattern;
+
+    assert!(Pattern::new("/a*b?d*").matches("/aXXbcd"));
+    assert!(Pattern::new("/*.tar.*").matches("/x.tar.gz"));
+    assert!(Pattern::new("/**/x").matches("/x"));
+    assert!(!Pattern::new("/a*b").matches("/abc"));
+    assert!(!Pattern::new("/*.ELF").matches("/init.elf"));
+    assert!(Pattern::new("/*.ELF").case_sensitive(false).matches("/init.elf"));
+    assert!(!Pattern::new("/STRASSE").case_sensitive(false).matches("/straße"));
+    assert!(Pattern::new("/ſ*").case_sensitive(false).matches("/S"));
+
+    // Would take exponential time if every `*` were retried at every offset.
+    let name = format!("/{}", "a".repeat(100));
+    assert!(!Pattern::new("/*a*a*a*a*a*a*a*a*a*a*a*a*b").matches(&name));
+    assert!(Pattern::new("/*a*a*a*a*a*a*a*a*a*a*a*a*a").matches(&name));
+}
+
+#[test]
+fn test_deleted_entries_and_undelete() {
+    use vfat::{Cluster, Status};
+
+    let vfat = formatted_vfat();
+
+    let long: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    vfat.create_file("/hello world.txt").unwrap().write_all(&long).unwrap();
+    vfat.create_file("/SHORT.TXT").unwrap().write_all(b"short").unwrap();
+    vfat.create_file("/empty.txt").expect("create empty file");
+    vfat.create_file("/kept.txt").unwrap().write_all(b"kept").unwrap();
+    for path in ["/hello world.txt", "/SHORT.TXT", "/empty.txt"].iter() {
+        vfat.remove(path, false).expect("remove");
+    }
+
+    let root = vfat.open_dir("/").unwrap();
+    let deleted: Vec<_> = root.deleted().expect("deleted entries").collect();
+    let names: Vec<&str> = deleted.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(names, vec!["hello world.txt", "_HORT.TXT", "_mpty.txt"]);
+    assert_eq!((deleted[0].size, deleted[1].size, deleted[2].size), (3000, 5, 0));
+    assert!(deleted[0].cluster.is_valid());
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["kept.txt"]);
+
+    assert_eq!(read_all(root.undelete(&deleted[0]).expect("undelete")), long);
+    assert_eq!(read_all(root.undelete(&deleted[1]).expect("undelete")), b"short");
+    assert_eq!(read_all(root.undelete(&deleted[2]).expect("undelete")), b"");
+    assert_eq!(root.undelete(&deleted[0]).unwrap_err().kind(), ::std::io::ErrorKind::NotFound);
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()),
+               vec!["_HORT.TXT", "_mpty.txt", "hello world.txt", "kept.txt"]);
+    assert_eq!(read_all(vfat.open_file("/hello world.txt").unwrap()), long);
+    assert!(::check::check(&vfat, false).expect("check").is_clean());
+
+    // A new file takes the first free entry, and then blocks the restore of
+    // a file of the same name.
+    vfat.create_file("/a.txt").expect("create file");
+    vfat.create_file("/b.txt").expect("create file");
+    vfat.remove("/a.txt", false).expect("remove");
+    vfat.remove("/b.txt", false).expect("remove");
+    vfat.create_file("/_.txt").expect("create file");
+    let b = root.deleted().unwrap().find(|entry| entry.name == "_.txt").expect("b.txt");
+    assert_eq!(root.undelete(&b).unwrap_err().kind(), ::std::io::ErrorKind::AlreadyExists);
+
+    // Files whose clusters have been reused cannot be restored.
+    vfat.remove("/kept.txt", false).expect("remove");
+    let kept = root.deleted().unwrap().find(|entry| entry.name == "_ept.txt").expect("kept.txt");
+    vfat.borrow_mut().set_fat_entry(kept.cluster, Status::Eoc(0x0FFFFFFF)).unwrap();
+    assert_eq!(root.undelete(&kept).unwrap_err().kind(), ::std::io::ErrorKind::Other);
+    vfat.borrow_mut().set_fat_entry(kept.cluster, Status::Free).unwrap();
+    assert_eq!(vfat.borrow_mut().relink_chain(Cluster::from(0), 1).unwrap_err().kind(),
+               ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_create_dir() {
+    let vfat = writable_vfat_from_resource!("mock3.fat32.img");
+
+    let e = vfat.create_dir("/TESTDIR/B", false).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+
+    vfat.create_dir("/TESTDIR/B/C", true).expect("create dirs");
+    assert_eq!(entry_names(vfat.open_dir("/TESTDIR/B").unwrap()), vec![".", "..", "C"]);
+
+    for i in 0..64 {
+        vfat.create_file(format!("/TESTDIR/B/C/F{}", i)).expect("create file");
+    }
+    assert_eq!(entry_names(vfat.open_dir("/TESTDIR/B/C").unwrap()).len(), 66);
+    assert_eq!(entry_names(vfat.open_dir("/TESTDIR/B/C/../..").unwrap()), vec![".", "..", "B"]);
+}
+
+#[test]
+fn test_rename_and_remove() {
+    let vfat = writable_vfat_from_resource!("mock4.fat32.img");
+    vfat.create_dir("/OLDDIR/SUB", true).expect("create dirs");
+    vfat.create_file("/OLDDIR/SUB/FILE.TXT").unwrap().write_all(b"data").unwrap();
+
+    vfat.rename("/OLDDIR/SUB", "/NEWDIR").expect("rename");
+    assert!(vfat.open("/OLDDIR/SUB").is_err());
+    assert_eq!(read_all(vfat.open_file("/NEWDIR/FILE.TXT").unwrap()), b"data");
+    assert_eq!(entry_names(vfat.open_dir("/NEWDIR/..").unwrap()),
+               entry_names(vfat.open_dir("/").unwrap()));
+
+    let e = vfat.rename("/NEWDIR", "/NEWDIR/INSIDE").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+
+    let e = vfat.remove("/NEWDIR", false).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+
+    vfat.remove("/NEWDIR", true).expect("remove recursively");
+    vfat.remove("/OLDDIR", false).expect("remove empty dir");
+    assert!(vfat.open("/NEWDIR").is_err());
+    assert!(vfat.open("/OLDDIR").is_err());
+}
+
+#[test]
+fn test_rename_changing_case() {
+    let vfat = writable_vfat_from_resource!("mock4.fat32.img");
+    vfat.create_file("/abc.txt").unwrap().write_all(b"data").unwrap();
+
+    vfat.rename("/abc.txt", "/ABC.txt").expect("rename to upper case");
+    assert!(entry_names(vfat.open_dir("/").unwrap()).contains(&"ABC.txt".to_string()));
+    assert!(!entry_names(vfat.open_dir("/").unwrap()).contains(&"abc.txt".to_string()));
+    assert_eq!(read_all(vfat.open_file("/abc.txt").unwrap()), b"data");
+
+    vfat.rename("/ABC.txt", "/Abc.Txt").expect("rename to mixed case");
+    assert!(entry_names(vfat.open_dir("/").unwrap()).contains(&"Abc.Txt".to_string()));
+    assert_eq!(read_all(vfat.open_file("/ABC.TXT").unwrap()), b"data");
+
+    vfat.create_file("/other.txt").unwrap();
+    let e = vfat.rename("/other.txt", "/ABC.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_new_entries_are_stamped() {
+    use vfat::Timestamp;
+
+    fn now() -> Timestamp {
+        Timestamp::new(2018, 2, 14, 12, 30, 0).unwrap()
+    }
+
+    let vfat = writable_vfat_from_resource!("mock4.fat32.img");
+    vfat.create_file("/EPOCH.TXT").unwrap();
+    let metadata = vfat.open("/EPOCH.TXT").unwrap().metadata().clone();
+    for timestamp in [metadata.created(), metadata.accessed(), metadata.modified()].iter() {
+        assert_eq!(*timestamp, Timestamp::EPOCH);
+    }
+
+    vfat.borrow_mut().set_clock(now);
+    vfat.create_dir("/STAMPED", false).unwrap();
+    let metadata = vfat.open("/STAMPED").unwrap().metadata().clone();
+    assert_eq!(metadata.created(), now());
+    assert_eq!(metadata.modified(), now());
+
+    let mut file = vfat.open_file("/EPOCH.TXT").unwrap();
+    file.write_all(b"modified").unwrap();
+    let metadata = vfat.open("/EPOCH.TXT").unwrap().metadata().clone();
+    assert_eq!(metadata.created(), Timestamp::EPOCH);
+    assert_eq!(metadata.modified(), now());
+    assert_eq!(metadata.accessed().date, now().date);
+}
+
+#[test]
+fn test_create_long_file_names() {
+    let vfat = writable_vfat_from_resource!("mock1.fat32.img");
+    vfat.create_dir("/Long Names", false).expect("create dir");
+
+    let names = ["Long file one.txt", "Long file two.txt", "Mixed.txt",
+                 "a.b.c", "\u{e9}t\u{e9} \u{2603}.md", &"x".repeat(255)];
+    for name in names.iter() {
+        let mut file = vfat.create_file(format!("/Long Names/{}", name)).expect("create file");
+        file.write_all(name.as_bytes()).expect("write file");
+    }
+
+    let mut expected: Vec<String> = names.iter().map(|s| s.to_string()).collect();
+    expected.extend(vec![".".to_string(), "..".to_string()]);
+    expected.sort();
+    assert_eq!(entry_names(vfat.open_dir("/long names").unwrap()), expected);
+
+    for name in names.iter() {
+        let file = vfat.open_file(format!("/Long Names/{}", name.to_ascii_uppercase()));
+        assert_eq!(read_all(file.expect("open by long name")), name.as_bytes());
+    }
+
+    assert_eq!(read_all(vfat.open_file("/LONGNA~1/LONGFI~1.TXT").unwrap()), b"Long file one.txt");
+    assert_eq!(read_all(vfat.open_file("/LONGNA~1/LONGFI~2.TXT").unwrap()), b"Long file two.txt");
+    assert_eq!(read_all(vfat.open_file("/LONGNA~1/MIXED~1.TXT").unwrap()), b"Mixed.txt");
+    assert_eq!(read_all(vfat.open_file("/LONGNA~1/AB~1.C").unwrap()), b"a.b.c");
+
+    for name in ["x".repeat(256), "a:b".to_string(), "trailing.".to_string()].iter() {
+        let e = vfat.create_file(format!("/Long Names/{}", name)).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+
+    vfat.remove("/Long Names/Long file one.txt", false).expect("remove");
+    vfat.rename("/Long Names/Long file two.txt", "/Long Names/Renamed, long.txt").expect("rename");
+    assert!(vfat.open("/LONGNA~1/LONGFI~1.TXT").is_err());
+    assert_eq!(read_all(vfat.open_file("/Long Names/Renamed, long.txt").unwrap()),
+               b"Long file two.txt");
+}
+
+#[test]
+fn test_unicode_case_insensitive_lookup_and_index() {
+    let vfat = formatted_vfat();
+
+    vfat.create_file("/中文文件.txt").unwrap().write_all(b"zh").unwrap();
+    vfat.create_file("/Éclair.txt").unwrap().write_all(b"fr").unwrap();
+    vfat.create_file("/ΣΟΦΟΣ.txt").unwrap().write_all(b"el").unwrap();
+    vfat.create_file("/Straße.txt").unwrap().write_all(b"de").unwrap();
+    vfat.create_file("/long file name.txt").unwrap().write_all(b"en").unwrap();
+
+    assert_eq!(read_all(vfat.open_file("/中文文件.TXT").unwrap()), b"zh");
+    assert_eq!(read_all(vfat.open_file("/éCLAIR.TXT").unwrap()), b"fr");
+    assert_eq!(read_all(vfat.open_file("/σοφος.txt").unwrap()), b"el");
+    assert_eq!(read_all(vfat.open_file("/STRAẞE.TXT").unwrap()), b"de");
+    assert_eq!(read_all(vfat.open_file("/longfi~1.txt").unwrap()), b"en");
+    assert!(vfat.open_file("/中文.txt").is_err());
+    assert_eq!(vfat.create_file("/ÉCLAIR.TXT").unwrap_err().kind(),
+               ::std::io::ErrorKind::AlreadyExists);
+
+    // Large directories are indexed on the first lookup; later lookups read
+    // far fewer entries.
+    let dir = vfat.create_dir("/big", false).expect("create dir");
+    for i in 0..200 {
+        dir.create_file(&format!("file number {}.txt", i)).expect("create file");
+    }
+    let accesses = |vfat: &Shared<VFat>| {
+        let stats = vfat.borrow().cache_stats();
+        stats.hits + stats.misses
+    };
+    let before = accesses(&vfat);
+    vfat.open("/big/FILE NUMBER 199.TXT").expect("scan");
+    let scan = accesses(&vfat) - before;
+    let before = accesses(&vfat);
+    vfat.open("/big/file number 198.txt").expect("indexed");
+    let indexed = accesses(&vfat) - before;
+    assert!(indexed * 4 < scan, "indexed lookup took {} accesses, scan {}", indexed, scan);
+
+    // Changes to the directory drop its index.
+    vfat.remove("/big/file number 198.txt", false).expect("remove");
+    assert!(vfat.open("/big/file number 198.txt").is_err());
+    vfat.rename("/big/file number 197.txt", "/big/renamed.txt").expect("rename");
+    assert!(vfat.open("/big/file number 197.txt").is_err());
+    assert!(vfat.open("/big/RENAMED.TXT").is_ok());
+    vfat.create_file("/big/file number 198.txt").expect("create file");
+    assert!(vfat.open("/big/file number 198.txt").is_ok());
+    assert_eq!(entry_names(vfat.open_dir("/big").unwrap()).len(), 202);
+}
+
+#[test]
+fn test_short_name_decoding() {
+    use vfat::{Attributes, Cluster};
+    use vfat::dir::VFatRegularDirEntry;
+
+    fn filename(name: &[u8; 8], extension: &[u8; 3], lowercase: (bool, bool)) -> String {
+        let mut entry = VFatRegularDirEntry::new(Attributes::file(), Cluster::from(0), 0);
+        entry.set_short_name(*name, *extension);
+        entry.set_lowercase(lowercase.0, lowercase.1);
+        entry.filename()
+    }
+
+    assert_eq!(filename(b"KERNEL  ", b"IMG", (false, false)), "KERNEL.IMG");
+    assert_eq!(filename(b"README  ", b"   ", (false, false)), "README");
+    assert_eq!(filename(b"A B     ", b"C  ", (false, false)), "A B.C");
+    assert_eq!(filename(b"KERNEL  ", b"IMG", (true, false)), "kernel.IMG");
+    assert_eq!(filename(b"KERNEL  ", b"IMG", (false, true)), "KERNEL.img");
+    assert_eq!(filename(b"CONFIG  ", b"TXT", (true, true)), "config.txt");
+    assert_eq!(filename(b"\x82T\x82     ", b"\x99  ", (true, true)), "\u{e9}t\u{e9}.\u{d6}");
+    assert_eq!(filename(b"\xe5LPHA   ", b"   ", (false, false)), "\u{3c3}LPHA");
+
+    let mut entry = VFatRegularDirEntry::new(Attributes::file(), Cluster::from(0), 0);
+    entry.set_short_name(*b"\xe5LPHA   ", *b"   ");
+    assert_eq!(entry.short_name()[0], 0x05);
+}
+
+#[test]
+fn test_lowercase_short_names() {
+    let vfat = writable_vfat_from_resource!("mock2.fat32.img");
+    vfat.create_dir("/CASES", false).expect("create dir");
+
+    let names = ["lower.txt", "UPPER.TXT", "name.TXT", "OTHER.txt", "noext", "Mixed.txt"];
+    for name in names.iter() {
+        vfat.create_file(format!("/CASES/{}", name)).expect("create file");
+    }
+
+    let mut expected: Vec<String> = names.iter().map(|s| s.to_string()).collect();
+    expected.extend(vec![".".to_string(), "..".to_string()]);
+    expected.sort();
+    assert_eq!(entry_names(vfat.open_dir("/CASES").unwrap()), expected);
+
+    // Single case names need no LFN entries, and so no `~N` alias.
+    assert!(vfat.open("/CASES/LOWER~1.TXT").is_err());
+    assert!(vfat.open("/CASES/NOEXT~1").is_err());
+    assert!(vfat.open("/CASES/MIXED~1.TXT").is_ok());
+
+    let e = vfat.create_file("/CASES/LOWER.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_cache_hits_and_misses() {
+    use vfat::{CachedDevice, Partition};
+
+    let device = SharedDevice::new(vec![0; 512 * 8]);
+    let mut cache = CachedDevice::new(device, Partition { start: 0, sector_size: 512 });
+    cache.get(1).unwrap();
+    cache.get(1).unwrap();
+    cache.get(2).unwrap();
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 2, 0));
+}
+
+#[test]
+fn test_cache_eviction_writes_back() {
+    use vfat::{CachedDevice, Partition};
+
+    let device = SharedDevice::new(vec![0; 512 * 16]);
+    let mut cache = CachedDevice::with_capacity(device.clone(),
+        Partition { start: 0, sector_size: 512 }, 4);
+
+    for i in 0..8 {
+        cache.write_sector(i, &[i as u8 + 1; 512]).unwrap();
+    }
+
+    // The four least recently used sectors were evicted and written back.
+    assert_eq!(cache.stats().evictions, 4);
+    assert_eq!(device.writes(), 4);
+    assert_eq!(device.sector(0), vec![1; 512]);
+    assert_eq!(device.sector(4), vec![0; 512]);
+
+    // A re-read of an evicted sector sees the written data.
+    let mut buf = [0u8; 512];
+    cache.read_sector(0, &mut buf).unwrap();
+    assert_eq!(&buf[..], &[1; 512][..]);
+
+    cache.sync().unwrap();
+    assert_eq!(device.sector(7), vec![8; 512]);
+    cache.sync().unwrap();
+    assert_eq!(device.writes(), 8);
+}
+
+#[test]
+fn test_cache_is_bounded() {
+    use vfat::{CachedDevice, Partition};
+
+    let device = SharedDevice::new(vec![0; 512 * 64]);
+    let mut cache = CachedDevice::with_capacity(device,
+        Partition { start: 0, sector_size: 512 }, 8);
+
+    for i in 0..64 {
+        cache.get_mut(i).unwrap()[0] = 1;
+    }
+    assert_eq!(cache.stats().evictions, 56);
+    assert_eq!(cache.stats().writebacks, 56);
+}
+
+#[test]
+fn test_cache_read_ahead_and_batched_write_back() {
+    use vfat::{CachedDevice, Partition};
+
+    let data: Vec<u8> = (0..64 * 512).map(|i| (i / 512) as u8).collect();
+    let device = SharedDevice::new(data);
+    let mut cache = CachedDevice::with_capacity(device.clone(),
+        Partition { start: 0, sector_size: 512 }, 64);
+
+    // Sequential misses read 1, 2, 4, 8 and then at most 16 sectors at once;
+    // the last read-ahead would run past the end of the device.
+    for i in 0..64 {
+        assert_eq!(cache.get(i).unwrap()[0], i as u8);
+    }
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.read_ahead), (56, 8, 56));
+    assert_eq!(device.transfers(), 8);
+
+    // Scattered misses read a single sector each.
+    let mut cache = CachedDevice::with_capacity(device.clone(),
+        Partition { start: 0, sector_size: 512 }, 64);
+    for &i in [5, 20, 9, 40].iter() {
+        cache.get(i).unwrap();
+    }
+    assert_eq!((cache.stats().misses, cache.stats().read_ahead), (4, 0));
+
+    // Consecutive dirty sectors are written back in a single transfer.
+    let transfers = device.transfers();
+    for i in 10..18 {
+        cache.write_sector(i, &[0xAB; 512]).unwrap();
+    }
+    cache.write_sector(30, &[0xCD; 512]).unwrap();
+    cache.sync().unwrap();
+    assert_eq!(device.transfers() - transfers, 2);
+    assert_eq!(cache.stats().writebacks, 9);
+    assert_eq!(device.writes(), 9);
+    assert_eq!(device.sector(17), vec![0xAB; 512]);
+    assert_eq!(device.sector(30), vec![0xCD; 512]);
+}
+
+#[test]
+fn test_vfat_writes_persist() {
+    let device = SharedDevice::from_resource(resource!("mock1.fat32.img"));
+
+    {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        let mut file = vfat.create_file("/SYNCED.TXT").expect("create file");
+        file.write_all(b"synced to disk").expect("write file");
+        file.sync().expect("sync");
+    }
+
+    {
+        // Dropping the file system writes back anything not yet synced.
+        let vfat = VFat::from(device.clone()).expect("remount");
+        assert_eq!(read_all(vfat.open_file("/SYNCED.TXT").unwrap()), b"synced to disk");
+        vfat.create_dir("/UNSYNCED", false).expect("create dir");
+    }
+
+    let vfat = VFat::from(device).expect("remount");
+    assert!(vfat.open_dir("/UNSYNCED").is_ok());
+}
+
+#[test]
+fn test_fat_mirroring_and_fsinfo() {
+    use vfat::FsInfo;
+
+    let mut device = SharedDevice::from_resource(resource!("mock3.fat32.img"));
+    let start = MasterBootRecord::from(&mut device).expect("mbr")
+        .partition_table[0].relative_sector as u64;
+    let ebpb = BiosParameterBlock::from(&mut device, start).expect("ebpb");
+    let fsinfo_sector = start + ebpb.fsinfo_sector().expect("FSInfo sector");
+
+    let (free, cluster_size) = {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        let free = vfat.borrow_mut().free_clusters().expect("free clusters");
+        let cluster_size = vfat.borrow().cluster_size();
+
+        vfat.create_dir("/MIRROR", false).expect("create dir");
+        let mut file = vfat.create_file("/MIRROR/DATA.BIN").expect("create file");
+        file.write_all(&vec![0xAB; cluster_size * 10]).expect("write file");
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 11);
+
+        vfat.remove("/MIRROR", true).expect("remove");
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+
+        vfat.create_file("/KEPT.BIN").unwrap().write_all(&vec![0xCD; cluster_size * 3]).unwrap();
+        (free - 3, cluster_size)
+    };
+
+    let fsinfo = FsInfo::from(&mut device, fsinfo_sector).expect("FSInfo");
+    assert_eq!(fsinfo.free_count(), Some(free));
+    assert!(fsinfo.next_free().is_some());
+
+    let fat_start = (start + ebpb.fat_start_sector()) as usize;
+    let fat_size = ebpb.sectors_per_fat() as usize;
+    if ebpb.fats_mirrored() {
+        for fat in 1..ebpb.fat_count() as usize {
+            for sector in 0..fat_size {
+                assert_eq!(device.sector(fat_start + sector),
+                           device.sector(fat_start + fat * fat_size + sector),
+                           "FAT {} differs in sector {}", fat, sector);
+            }
+        }
+    }
+
+    let vfat = VFat::from(device).expect("remount");
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+    assert_eq!(read_all(vfat.open_file("/KEPT.BIN").unwrap()), vec![0xCD; cluster_size * 3]);
+}
+
+#[test]
+fn test_dirty_volume_flag() {
+    let mut device = SharedDevice::from_resource(resource!("mock4.fat32.img"));
+    let start = MasterBootRecord::from(&mut device).expect("mbr")
+        .partition_table[0].relative_sector as u64;
+    let ebpb = BiosParameterBlock::from(&mut device, start).expect("ebpb");
+    let fat_start = (start + ebpb.fat_start_sector()) as usize;
+    let fat_size = ebpb.sectors_per_fat() as usize;
+    let clean = |device: &SharedDevice| -> Vec<bool> {
+        (0..ebpb.fat_count() as usize)
+            .map(|fat| device.sector(fat_start + fat * fat_size)[7] & 0x08 != 0)
+            .collect()
+    };
+
+    let all_clean = vec![true; ebpb.fat_count() as usize];
+    let all_dirty = vec![false; ebpb.fat_count() as usize];
+    assert_eq!(clean(&device), all_clean);
+
+    let crashed = {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        assert!(!vfat.borrow().mounted_dirty());
+        assert!(!vfat.borrow().hard_error());
+
+        vfat.open_dir("/").expect("read root");
+        assert_eq!(clean(&device), all_clean);
+
+        vfat.create_file("/DIRTY.TXT").unwrap().write_all(b"dirty").unwrap();
+        assert_eq!(clean(&device), all_dirty);
+        let crashed = SharedDevice::new(device.data.lock().unwrap().clone());
+
+        vfat.borrow_mut().sync().expect("sync");
+        assert_eq!(clean(&device), all_clean);
+
+        vfat.create_file("/AGAIN.TXT").expect("create file");
+        assert_eq!(clean(&device), all_dirty);
+        crashed
+    };
+
+    // Unmounting marks the volume clean again.
+    assert_eq!(clean(&device), all_clean);
+    assert!(!VFat::from(device).expect("remount").borrow().mounted_dirty());
+
+    // A volume left dirty is reported and stays dirty until it is checked.
+    let vfat = VFat::from(crashed.clone()).expect("mount crashed");
+    assert!(vfat.borrow().mounted_dirty());
+    vfat.create_file("/MORE.TXT").expect("create file");
+    vfat.borrow_mut().sync().expect("sync");
+    assert_eq!(clean(&crashed), all_dirty);
+}
+
+#[test]
+fn test_check_and_repair() {
+    use check::{check, Problem};
+    use vfat::dir::{Dir, VFatDirEntry};
+    use vfat::Status;
+
+    let device = SharedDevice::from_resource(resource!("mock4.fat32.img"));
+    let vfat = VFat::from(device.clone()).expect("mount");
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "unexpected problems: {:?}", report.problems);
+    assert!(report.files > 0 && report.dirs > 0);
+
+    let root_cluster = vfat.borrow().root_dir_cluster();
+    let root = Dir::new(root_cluster, vfat.clone());
+    let cluster_of = |name: &str| root.find_slot(name).expect("find").entry.cluster();
+    for &name in ["SIZE.TXT", "CROSS.TXT", "Orphaned name.txt", "Bad checksum.txt"].iter() {
+        vfat.create_file(&format!("/{}", name)).unwrap().write_all(b"0123456789").unwrap();
+    }
+
+    // One cluster too many for its size; a chain joining another one.
+    let size = cluster_of("SIZE.TXT");
+    let extra = vfat.borrow_mut().alloc_cluster(Some(size)).expect("alloc");
+    let cross = cluster_of("CROSS.TXT");
+    vfat.borrow_mut().set_fat_entry(cross, Status::Data(size)).expect("link");
+
+    // A cluster in use by nothing.
+    let lost = vfat.borrow_mut().alloc_cluster(None).expect("alloc");
+
+    // LFN entries whose regular entry is gone, leaving its cluster lost too.
+    let orphaned = root.find_slot("Orphaned name.txt").expect("find");
+    let mut data = root.raw_entries().expect("raw entries");
+    unsafe { data[orphaned.index].unknown.set_unused(); }
+
+    // LFN entries whose regular entry was renamed.
+    let renamed = root.find_slot("Bad checksum.txt").expect("find");
+    let mut entry = renamed.entry;
+    entry.set_short_name(*b"RENAMED ", *b"TXT");
+    data[renamed.index] = VFatDirEntry { regular: entry };
+    root.write_raw_entries(0, &data).expect("write entries");
+
+    let report = check(&vfat, false).expect("check");
+    let problems = report.problems;
+    assert!(problems.contains(&Problem::SizeMismatch { path: "/SIZE.TXT".to_string(),
+                                                       size: 10, clusters: 2 }));
+    assert!(problems.contains(&Problem::CrossLinked { path: "/CROSS.TXT".to_string(),
+                                                      owner: "/SIZE.TXT".to_string(),
+                                                      cluster: size.raw() }));
+    assert!(problems.contains(&Problem::LostCluster(lost.raw())));
+    assert!(problems.contains(&Problem::LostCluster(orphaned.entry.cluster().raw())));
+    assert!(problems.contains(&Problem::BadLfnChecksum { dir: "/".to_string(),
+                                                         index: renamed.index }));
+    for index in orphaned.first..orphaned.index {
+        assert!(problems.contains(&Problem::OrphanedLfn { dir: "/".to_string(), index }));
+    }
+    assert_eq!(problems.len(), 5 + orphaned.index - orphaned.first);
+
+    let report = check(&vfat, true).expect("repair");
+    assert!(report.repaired);
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "unrepaired problems: {:?}", report.problems);
+
+    assert_eq!(vfat.borrow_mut().fat_entry(extra).unwrap().status(), Status::Free);
+    assert_eq!(vfat.borrow_mut().fat_entry(lost).unwrap().status(), Status::Free);
+    assert_eq!(cluster_of("CROSS.TXT"), cross);
+    assert!(root.find_slot("Bad checksum.txt").is_err());
+    assert!(root.find_slot("RENAMED.TXT").is_ok());
+
+    let mut contents = String::new();
+    vfat.open_file("/SIZE.TXT").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "0123456789");
+}
+
+#[test]
+fn test_repair_cross_linked_dir() {
+    use check::{check, Problem};
+    use vfat::dir::{Dir, VFatDirEntry};
+
+    let vfat = formatted_vfat();
+    vfat.create_file("/FIRST.TXT").unwrap().write_all(b"0123456789").unwrap();
+    vfat.create_dir("/Cross linked dir", false).expect("create dir");
+
+    // A directory whose chain starts in the chain of a file before it.
+    let root = Dir::new(vfat.borrow().root_dir_cluster(), vfat.clone());
+    let first = root.find_slot("FIRST.TXT").expect("find").entry.cluster();
+    let slot = root.find_slot("Cross linked dir").expect("find");
+    assert!(slot.first < slot.index);
+    let lost = slot.entry.cluster();
+    let mut data = root.raw_entries().expect("raw entries");
+    let mut entry = slot.entry;
+    entry.set_cluster(first);
+    data[slot.index] = VFatDirEntry { regular: entry };
+    root.write_raw_entries(0, &data).expect("write entries");
+
+    let report = check(&vfat, true).expect("repair");
+    assert!(report.repaired);
+    assert!(report.problems.contains(&Problem::CrossLinked {
+        path: "/Cross linked dir".to_string(),
+        owner: "/FIRST.TXT".to_string(),
+        cluster: first.raw(),
+    }));
+    assert!(report.problems.contains(&Problem::LostCluster(lost.raw())));
+
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "unrepaired problems: {:?}", report.problems);
+    assert!(root.find_slot("Cross linked dir").is_err());
+    assert!(root.find_slot("FIRST.TXT").is_ok());
+}
+
+#[test]
+fn test_format() {
+    use format::{format, Layout, PARTITION_START};
+
+    assert_eq!(Layout::new(34, 512), None);
+    assert_eq!(Layout::new(32 * 2048, 512), None);
+    assert_eq!(Layout::new(300 * 2048, 512).unwrap().sectors_per_cluster, 8);
+    assert_eq!(Layout::new(20 * 1024 * 2048, 512).unwrap().sectors_per_cluster, 32);
+
+    let sectors = 40 * 2048;
+    let mut device = SharedDevice::new(vec![0xA5; sectors * 512]);
+    let layout = format(device.clone(), sectors as u64).expect("format");
+    assert_eq!(layout.sectors, sectors as u64 - PARTITION_START);
+    assert_eq!(layout.sectors_per_cluster, 1);
+
+    let mbr = MasterBootRecord::from(&mut device).expect("mbr");
+    assert_eq!(mbr.partition_table[0].partition_type, 0x0C);
+    assert_eq!(mbr.partition_table[0].relative_sector as u64, PARTITION_START);
+
+    {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        assert!(!vfat.borrow().mounted_dirty());
+        assert_eq!(vfat.borrow().cluster_count() as u64, layout.clusters);
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap() as u64, layout.clusters - 1);
+        assert_eq!(vfat.open_dir("/").unwrap().entries().unwrap().count(), 0);
+
+        vfat.create_dir("/docs", false).expect("create dir");
+        vfat.create_file("/docs/hello.txt").unwrap().write_all(b"Hello!").unwrap();
+        assert!(::check::check(&vfat, false).expect("check").is_clean());
+    }
+
+    let vfat = VFat::from(device).expect("remount");
+    let mut contents = String::new();
+    vfat.open_file("/docs/hello.txt").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "Hello!");
+}
+
+/// Builds an empty disk image of `sectors` sectors holding a single FAT12 or
+/// FAT16 partition the way DOS formats one: 63 sectors in, with 4 sectors per
+/// cluster, two FATs and a 512 entry root directory.
+fn fat_image(sectors: usize, bits: usize) -> Vec<u8> {
+    fn put(buf: &mut [u8], offset: usize, width: usize, value: usize) {
+        for i in 0..width {
+            buf[offset + i] = (value >> (8 * i)) as u8;
+        }
+    }
+
+    let (start, partition_sectors) = (63, sectors - 63);
+    let fat_sectors = ((partition_sectors / 4 + 2) * bits / 8 + 511) / 512 + 1;
+    let mut image = vec![0u8; sectors * 512];
+
+    image[446 + 4] = if bits == 12 { 0x01 } else { 0x06 };
+    put(&mut image, 446 + 8, 4, start);
+    put(&mut image, 446 + 12, 4, partition_sectors);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    {
+        let boot = &mut image[start * 512..(start + 1) * 512];
+        boot[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        put(boot, 11, 2, 512);
+        boot[13] = 4;
+        put(boot, 14, 2, 1);
+        boot[16] = 2;
+        put(boot, 17, 2, 512);
+        put(boot, 19, 2, partition_sectors);
+        boot[21] = 0xF8;
+        put(boot, 22, 2, fat_sectors);
+        put(boot, 28, 4, start);
+        boot[38] = 0x29;
+        boot[54..62].copy_from_slice(if bits == 12 { b"FAT12   " } else { b"FAT16   " });
+        boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    for fat in 0..2 {
+        let offset = (start + 1 + fat * fat_sectors) * 512;
+        let media: &[u8] = if bits == 12 { &[0xF8, 0xFF, 0xFF] } else { &[0xF8, 0xFF, 0xFF, 0xFF] };
+        image[offset..offset + media.len()].copy_from_slice(media);
+    }
+
+    image
+}
+
+/// Decodes entry `n` of a FAT12 or FAT16 FAT starting at byte `fat` of `data`.
+fn raw_fat_entry(data: &[u8], fat: usize, bits: usize, n: usize) -> u32 {
+    if bits == 12 {
+        let offset = fat + n + n / 2;
+        let pair = data[offset] as u32 | (data[offset + 1] as u32) << 8;
+        if n % 2 == 0 { pair & 0xFFF } else { pair >> 4 }
+    } else {
+        data[fat + 2 * n] as u32 | (data[fat + 2 * n + 1] as u32) << 8
+    }
+}
+
+#[test]
+fn test_fat12_and_fat16() {
+    use vfat::FatType;
+
+    let cases = [(4096, 12, FatType::Fat12), (32768, 16, FatType::Fat16)];
+    for &(sectors, bits, fat_type) in cases.iter() {
+        let device = SharedDevice::new(fat_image(sectors, bits));
+        // Large enough for the FAT12 chain to cross a sector boundary of the
+        // FAT, at cluster 341.
+        let data: Vec<u8> = (0..360 * 2048).map(|i| (i % 251) as u8).collect();
+
+        {
+            let vfat = VFat::from(device.clone()).expect("mount");
+            assert_eq!(vfat.borrow().fat_type(), fat_type);
+            assert!(!vfat.borrow().mounted_dirty());
+
+            vfat.create_file("/BIG.BIN").unwrap().write_all(&data).unwrap();
+            vfat.create_dir("/sub", false).expect("create dir");
+            vfat.create_file("/sub/A long file name.txt").unwrap().write_all(b"nested").unwrap();
+            vfat.open_file("/sub/A long file name.txt").expect("open in subdirectory");
+            assert!(::check::check(&vfat, false).expect("check").is_clean());
+        }
+
+        // Both FATs hold the same chain, allocated from cluster 2 onwards.
+        let raw = device.data.lock().unwrap().clone();
+        let fat_sectors = raw[63 * 512 + 22] as usize | (raw[63 * 512 + 23] as usize) << 8;
+        let fats = [64 * 512, (64 + fat_sectors) * 512];
+        let eoc = if bits == 12 { 0xFF8 } else { 0xFFF8 };
+        for n in 2..362 {
+            let entry = raw_fat_entry(&raw, fats[0], bits, n);
+            assert_eq!(entry, raw_fat_entry(&raw, fats[1], bits, n));
+            if n < 361 {
+                assert_eq!(entry, n as u32 + 1);
+            } else {
+                assert!(entry >= eoc);
+            }
+        }
+        if bits == 16 {
+            assert_eq!(raw_fat_entry(&raw, fats[0], bits, 1), 0xFFFF);
+        }
+
+        let vfat = VFat::from(device).expect("remount");
+        let mut contents = Vec::new();
+        vfat.open_file("/BIG.BIN").unwrap().read_to_end(&mut contents).unwrap();
+        assert!(contents == data);
+        let mut nested = String::new();
+        vfat.open_file("/SUB/a long file name.TXT").unwrap().read_to_string(&mut nested).unwrap();
+        assert_eq!(nested, "nested");
+
+        // The fixed-size root directory cannot grow.
+        let error = (0..512)
+            .map(|i| vfat.create_file(&format!("/FILE{}", i)).map(|_| ()))
+            .find(|result| result.is_err())
+            .expect("root directory never filled up")
+            .unwrap_err();
+        assert_eq!(error.kind(), ::std::io::ErrorKind::Other);
+        assert_eq!(vfat.open_dir("/").unwrap().entries().unwrap().count(), 512);
+    }
+}
+
+/// Rewrites the start of the formatted `device` of `sectors` sectors as a
+/// protective MBR and a GPT with a 128 entry partition array whose used
+/// entries are `partitions`: (type, first sector, last sector, name).
+fn write_gpt(device: &mut SharedDevice, sectors: u64, partitions: &[(Guid, u64, u64, &str)]) {
+    fn put(buf: &mut [u8], offset: usize, width: usize, value: u64) {
+        for i in 0..width {
+            buf[offset + i] = (value >> (8 * i)) as u8;
+        }
+    }
+
+    let mut mbr = vec![0u8; 512];
+    mbr[446..458].copy_from_slice(&[0x00, 0x00, 0x02, 0x00, 0xEE, 0xFF, 0xFF, 0xFF,
+                                    1, 0, 0, 0]);
+    put(&mut mbr[458..462], 0, 4, sectors - 1);
+    mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+    device.write_sector(0, &mbr).unwrap();
+
+    let mut array = vec![0u8; 128 * 128];
+    for (i, &(type_guid, first, last, name)) in partitions.iter().enumerate() {
+        let entry = &mut array[i * 128..(i + 1) * 128];
+        entry[0..16].copy_from_slice(&type_guid.0);
+        entry[16] = i as u8 + 1;
+        put(entry, 32, 8, first);
+        put(entry, 40, 8, last);
+        for (j, unit) in name.encode_utf16().enumerate() {
+            put(entry, 56 + 2 * j, 2, unit as u64);
+        }
+    }
+
+    // The primary header and array, then the backup array and header at the
+    // end of the disk.
+    for &(lba, alternate_lba, entries_lba) in [(1, sectors - 1, 2),
+                                               (sectors - 1, 1, sectors - 33)].iter() {
+        for (i, sector) in array.chunks(512).enumerate() {
+            device.write_sector(entries_lba + i as u64, sector).unwrap();
+        }
+
+        let mut header = vec![0u8; 512];
+        header[0..8].copy_from_slice(b"EFI PART");
+        put(&mut header, 8, 4, 0x00010000);
+        put(&mut header, 12, 4, 92);
+        put(&mut header, 24, 8, lba);
+        put(&mut header, 32, 8, alternate_lba);
+        put(&mut header, 40, 8, 34);
+        put(&mut header, 48, 8, sectors - 34);
+        header[56..72].copy_from_slice(&[0x42; 16]);
+        put(&mut header, 72, 8, entries_lba);
+        put(&mut header, 80, 4, 128);
+        put(&mut header, 84, 4, 128);
+        put(&mut header, 88, 4, crc32(&array) as u64);
+        let crc = crc32(&header[..92]);
+        put(&mut header, 16, 4, crc as u64);
+        device.write_sector(lba, &header).unwrap();
+    }
+}
+
+#[test]
+fn test_gpt() {
+    use format::PARTITION_START;
+
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(Guid::MICROSOFT_BASIC_DATA.to_string(), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+    assert_eq!(Guid::EFI_SYSTEM.to_string(), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+
+    let sectors = FORMATTED_SECTORS;
+    let (mut device, _) = formatted_disk();
+    {
+        let vfat = VFat::from(device.clone()).expect("mount");
+        vfat.create_file("/hello.txt").unwrap().write_all(b"Hello, GPT!").unwrap();
+    }
+
+    // A Linux partition, then an NTFS data partition, then the FAT32 one.
+    let linux = Guid([0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47,
+                      0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]);
+    let mut ntfs = vec![0u8; 512];
+    ntfs[3..11].copy_from_slice(b"NTFS    ");
+    ntfs[11..14].copy_from_slice(&[0x00, 0x02, 0x08]);
+    ntfs[510..512].copy_from_slice(&[0x55, 0xAA]);
+    device.write_sector(64, &ntfs).unwrap();
+    write_gpt(&mut device, sectors as u64, &[
+        (linux, 34, 63, "root"),
+        (Guid::MICROSOFT_BASIC_DATA, 64, 2047, "Windows"),
+        (Guid::MICROSOFT_BASIC_DATA, PARTITION_START, sectors as u64 - 34, "Rustable"),
+    ]);
+
+    let mbr = MasterBootRecord::from(&mut device).expect("mbr");
+    assert!(mbr.is_protective());
+    let gpt = GuidPartitionTable::from(&mut device).expect("gpt");
+    assert_eq!(gpt.disk_guid, Guid([0x42; 16]));
+    assert_eq!(gpt.partitions.len(), 3);
+    assert_eq!(gpt.partitions[0].type_guid, linux);
+    assert!(!gpt.partitions[0].may_be_fat());
+    assert_eq!(gpt.partitions[2].index, 2);
+    assert_eq!(gpt.partitions[2].name, "Rustable");
+    assert_eq!(gpt.partitions[2].sectors(), sectors as u64 - 34 - PARTITION_START + 1);
+    let data: Vec<u64> = gpt.partitions_of_type(Guid::MICROSOFT_BASIC_DATA)
+        .map(|partition| partition.first_lba)
+        .collect();
+    assert_eq!(data, vec![64, PARTITION_START]);
+
+    {
+        let vfat = VFat::from(device.clone()).expect("mount from GPT");
+        let mut contents = String::new();
+        vfat.open_file("/hello.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "Hello, GPT!");
+    }
+
+    // Corrupting the partition array or the header invalidates their CRCs,
+    // and the backup is read instead.
+    let mut sector = device.sector(2);
+    sector[32] ^= 1;
+    device.write_sector(2, &sector).unwrap();
+    let backup = GuidPartitionTable::from(&mut device).expect("backup gpt");
+    assert_eq!(backup.partitions.len(), 3);
+    assert_eq!(backup.partitions[2].first_lba, PARTITION_START);
+
+    let last = sectors - 1;
+    let mut backup_header = device.sector(last);
+    backup_header[0] = b'X';
+    device.write_sector(last as u64, &backup_header).unwrap();
+    expect_variant!(GuidPartitionTable::from(&mut device), Err(GptError::BadChecksum));
+    expect_variant!(VFat::from(device.clone()), Err(::vfat::Error::Gpt(GptError::BadChecksum)));
+    sector[32] ^= 1;
+    device.write_sector(2, &sector).unwrap();
+    GuidPartitionTable::from(&mut device).expect("gpt restored");
+
+    // Without an intact primary header, the backup is found through the
+    // protective MBR.
+    let mut header = device.sector(1);
+    header[80] = 64;
+    device.write_sector(1, &header).unwrap();
+    expect_variant!(GuidPartitionTable::from(&mut device), Err(GptError::BadChecksum));
+    backup_header[0] = b'E';
+    device.write_sector(last as u64, &backup_header).unwrap();
+    assert_eq!(GuidPartitionTable::from(&mut device).expect("backup gpt").partitions.len(), 3);
+    header[0] = b'X';
+    device.write_sector(1, &header).unwrap();
+    GuidPartitionTable::from(&mut device).expect("backup gpt");
+    backup_header[0] = b'X';
+    device.write_sector(last as u64, &backup_header).unwrap();
+    expect_variant!(GuidPartitionTable::from(&mut device), Err(GptError::BadSignature));
+
+    // Oversized partition entries are rejected before the array is read.
+    header[0] = b'E';
+    header[80] = 128;
+    header[84..88].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+    header[16..20].copy_from_slice(&[0; 4]);
+    let crc = crc32(&header[..92]);
+    for i in 0..4 {
+        header[16 + i] = (crc >> (8 * i)) as u8;
+    }
+    device.write_sector(1, &header).unwrap();
+    expect_variant!(GuidPartitionTable::from(&mut device), Err(GptError::BadHeader));
+}
+
+#[test]
+fn test_partitions() {
+    use format::PARTITION_START;
+    use partition::{partitions, PartitionDevice, PartitionInfo, PartitionKind};
+    use std::io::ErrorKind;
+
+    // A FAT16 partition in entry 0, a FAT32 partition in entry 1 and a
+    // Linux partition in entry 3.
+    let fat16_sectors = 20000;
+    let mut data = fat_image(fat16_sectors, 16);
+    let fat32_sectors = FORMATTED_SECTORS;
+    {
+        let (fat32, _) = formatted_disk();
+        let fat32 = fat32.data.lock().unwrap();
+        data.extend_from_slice(&fat32[PARTITION_START as usize * 512..]);
+    }
+    let fat32_start = fat16_sectors as u32;
+    let fat32_len = (fat32_sectors - PARTITION_START as usize) as u32;
+    let entry = |partition_type: u8, start: u32, sectors: u32| {
+        let mut entry = [0u8; 16];
+        entry[4] = partition_type;
+        for i in 0..4 {
+            entry[8 + i] = (start >> (8 * i)) as u8;
+            entry[12 + i] = (sectors >> (8 * i)) as u8;
+        }
+        entry
+    };
+    data[462..478].copy_from_slice(&entry(0x0C, fat32_start, fat32_len));
+    data[494..510].copy_from_slice(&entry(0x83, 1, 1));
+
+    let mut device = Shared::new(Cursor::new(data));
+    let found = partitions(&mut device).expect("partitions");
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[0], PartitionInfo { index: 0, kind: PartitionKind::Mbr(0x06), start: 63,
+                                         sectors: fat16_sectors as u64 - 63 });
+    assert_eq!(found[1].index, 1);
+    assert_eq!(found[1].start, fat32_start as u64);
+    assert!(found[1].may_be_fat());
+    assert_eq!(found[2].index, 3);
+    assert!(!found[2].may_be_fat());
+
+    let mut view = PartitionDevice::open(device.clone(), 3).unwrap().expect("entry 3");
+    let mut buf = [0u8; 512];
+    view.read_sector(0, &mut buf).expect("in bounds");
+    let err = view.read_sector(1, &mut buf).expect_err("out of bounds");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(PartitionDevice::open(device.clone(), 2).unwrap().is_none());
+
+    expect_variant!(VFat::from_partition(device.clone(), 2), Err(::vfat::Error::NotFound));
+    expect_variant!(VFat::from_partition(device.clone(), 3),
+                    Err(::vfat::Error::BadSignature));
+
+    // Both partitions mount side by side on the same device.
+    {
+        let boot = VFat::from(device.clone()).expect("mount first");
+        let data = VFat::from_partition(device.clone(), 1).expect("mount second");
+        assert_eq!(boot.borrow().fat_type(), ::vfat::FatType::Fat16);
+        assert_eq!(data.borrow().fat_type(), ::vfat::FatType::Fat32);
+        boot.create_file("/boot.txt").unwrap().write_all(b"boot").unwrap();
+        data.create_file("/data.txt").unwrap().write_all(b"data").unwrap();
+    }
+
+    let boot = VFat::from_partition(device.clone(), 0).expect("remount first");
+    let data = VFat::from_partition(device, 1).expect("remount second");
+    assert_eq!(read_all(boot.open_file("/boot.txt").unwrap()), b"boot");
+    assert_eq!(read_all(data.open_file("/data.txt").unwrap()), b"data");
+    assert!(data.open_file("/boot.txt").is_err());
+}
+
+#[test]
+fn test_from_volume() {
+    use format::PARTITION_START;
+
+    let (device, _) = formatted_disk();
+    expect_variant!(VFat::from_volume(device.clone()), Err(::vfat::Error::BadSignature));
+
+    let volume = device.data.lock().unwrap()[PARTITION_START as usize * 512..].to_vec();
+    let volume = Shared::new(Cursor::new(volume));
+    VFat::from_volume(volume.clone()).expect("mount")
+        .create_file("/bare.txt").unwrap().write_all(b"bare").unwrap();
+    let vfat = VFat::from_volume(volume).expect("remount");
+    assert_eq!(read_all(vfat.open_file("/bare.txt").unwrap()), b"bare");
+}
+
 #[test]
 fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
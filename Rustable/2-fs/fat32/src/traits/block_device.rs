@@ -52,9 +52,52 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Reads `buf.len() / self.sector_size()` consecutive sectors starting at
+    /// sector `n` into `buf`. The number of bytes read is returned.
+    ///
+    /// The default implementation reads one sector at a time; devices that
+    /// support multi-sector transfers should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut read = 0;
+        for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+            read += self.read_sector(n + i as u64, chunk)?;
+        }
+        Ok(read)
+    }
+
+    /// Overwrites the `buf.len() / self.sector_size()` consecutive sectors
+    /// starting at sector `n` with the contents of `buf`. The number of bytes
+    /// written is returned.
+    ///
+    /// The default implementation writes one sector at a time; devices that
+    /// support multi-sector transfers should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or writing to `self` fails. Returns an
+    /// error of `UnexpectedEof` if the length of `buf` is not a multiple of
+    /// `self.sector_size()`.
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut written = 0;
+        for (i, chunk) in buf.chunks(sector_size).enumerate() {
+            written += self.write_sector(n + i as u64, chunk)?;
+        }
+        Ok(written)
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -62,6 +105,14 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        (*self).write_sectors(n, buf)
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -83,6 +134,23 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            self.seek(io::SeekFrom::Start(n * self.sector_size()))?;
+            self.read_exact(buf)?;
+            Ok(buf.len())
+        }
+
+        fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size() as usize;
+            if buf.len() % sector_size != 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "buffer not a multiple of the sector size"));
+            }
+            self.seek(io::SeekFrom::Start(n * sector_size as u64))?;
+            self.write_all(buf)?;
+            Ok(buf.len())
+        }
     }
 }
 
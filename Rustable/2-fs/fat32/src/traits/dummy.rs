@@ -21,6 +21,7 @@ impl io::Seek for Dummy {
 impl File for Dummy {
     fn sync(&mut self) -> io::Result<()> { panic!("Dummy") }
     fn size(&self) -> u64 { panic!("Dummy") }
+    fn set_len(&mut self, _len: u64) -> io::Result<()> { panic!("Dummy") }
 }
 
 /// Trait implemented by directories in a file system.
@@ -45,6 +46,7 @@ impl Entry for Dummy {
 
     fn name(&self) -> &str { panic!("Dummy") }
     fn metadata(&self) -> &Self::Metadata { panic!("Dummy") }
+    fn set_metadata(&mut self, _metadata: Self::Metadata) -> io::Result<()> { panic!("Dummy") }
     fn as_file(&self) -> Option<&Self::File> { panic!("Dummy") }
     fn as_dir(&self) -> Option<&Self::Dir> { panic!("Dummy") }
     fn into_file(self) -> Option<Self::File> { panic!("Dummy") }
@@ -58,13 +60,26 @@ impl Timestamp for Dummy {
     fn hour(&self) -> u8 { panic!("Dummy") }
     fn minute(&self) -> u8 { panic!("Dummy") }
     fn second(&self) -> u8 { panic!("Dummy") }
+    fn new(_: usize, _: u8, _: u8, _: u8, _: u8, _: u8) -> Option<Dummy> { panic!("Dummy") }
 }
 
 impl Metadata for Dummy {
     type Timestamp = Dummy;
     fn read_only(&self) -> bool { panic!("Dummy") }
     fn hidden(&self) -> bool { panic!("Dummy") }
+    fn system(&self) -> bool { panic!("Dummy") }
+    fn volume_id(&self) -> bool { panic!("Dummy") }
+    fn directory(&self) -> bool { panic!("Dummy") }
+    fn archive(&self) -> bool { panic!("Dummy") }
+    fn size(&self) -> u64 { panic!("Dummy") }
     fn created(&self) -> Self::Timestamp { panic!("Dummy") }
     fn accessed(&self) -> Self::Timestamp { panic!("Dummy") }
     fn modified(&self) -> Self::Timestamp { panic!("Dummy") }
+    fn set_read_only(&mut self, _read_only: bool) { panic!("Dummy") }
+    fn set_hidden(&mut self, _hidden: bool) { panic!("Dummy") }
+    fn set_system(&mut self, _system: bool) { panic!("Dummy") }
+    fn set_archive(&mut self, _archive: bool) { panic!("Dummy") }
+    fn set_created(&mut self, _timestamp: Self::Timestamp) { panic!("Dummy") }
+    fn set_accessed(&mut self, _timestamp: Self::Timestamp) { panic!("Dummy") }
+    fn set_modified(&mut self, _timestamp: Self::Timestamp) { panic!("Dummy") }
 }
@@ -2,6 +2,7 @@ use std::io;
 use std::path::Path;
 
 use traits::Metadata;
+use walk::{Glob, Order, Pattern, Walk, WalkEntry};
 
 /// Trait implemented by files in the file system.
 pub trait File: io::Read + io::Write + io::Seek + Sized {
@@ -10,6 +11,9 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `len` bytes. Bytes added are zeroes.
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -39,6 +43,17 @@ pub trait Entry: Sized {
     /// The metadata associated with the entry.
     fn metadata(&self) -> &Self::Metadata;
 
+    /// Replaces the attributes and timestamps of the entry with those of
+    /// `metadata`, and writes them back to the file system. Whether the entry
+    /// is a directory or the volume label, and its size, are not changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the entry has no metadata of its
+    /// own, such as the root directory. All other error values are
+    /// implementation defined.
+    fn set_metadata(&mut self, metadata: Self::Metadata) -> io::Result<()>;
+
     /// If `self` is a file, returns `Some` of a reference to the file.
     /// Otherwise returns `None`.
     fn as_file(&self) -> Option<&Self::File>;
@@ -115,6 +130,62 @@ pub trait FileSystem: Sized {
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
 
+    /// Returns the metadata of the entry at `path` without opening it as a
+    /// file or directory. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// Fails under the same conditions as `open()`.
+    fn stat<P: AsRef<Path>>(self, path: P) -> io::Result<<Self::Entry as Entry>::Metadata> {
+        Ok(self.open(path)?.metadata().clone())
+    }
+
+    /// Returns an iterator over every entry below the directory at `path`, in
+    /// `order`. `path` must be absolute. See `walk::Walk`.
+    ///
+    /// # Errors
+    ///
+    /// Fails under the same conditions as `open_dir()`, or if the directory
+    /// cannot be read.
+    fn walk<P: AsRef<Path>>(self, path: P, order: Order)
+        -> io::Result<Walk<Self::Dir, fn(&WalkEntry<Self::Entry>) -> bool>>
+    {
+        let path = path.as_ref().to_path_buf();
+        let dir = self.open_dir(&path)?;
+        Walk::new(path, dir, order)
+    }
+
+    /// Returns `true` if names that differ only in case refer to different
+    /// entries. Globs are matched case-insensitively otherwise.
+    fn case_sensitive(&self) -> bool {
+        true
+    }
+
+    /// Returns an iterator over the entries whose absolute paths match the
+    /// glob `pattern`, such as `/bin/*.elf` or `/bin/**`. The pattern is
+    /// matched case-insensitively if the file system is. See
+    /// `walk::Pattern`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the root directory cannot be read.
+    fn glob(self, pattern: &str) -> io::Result<Glob<Self::Dir>> {
+        let pattern = Pattern::new(pattern).case_sensitive(self.case_sensitive());
+        self.glob_pattern(pattern)
+    }
+
+    /// Returns an iterator over the entries whose absolute paths match
+    /// `pattern`, as `glob()` does, with the case sensitivity set on the
+    /// pattern.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the root directory cannot be read.
+    fn glob_pattern(self, pattern: Pattern) -> io::Result<Glob<Self::Dir>> {
+        let root = self.open_dir("/")?;
+        Glob::new(root, pattern)
+    }
+
     /// Creates a new file at `path`, opens it, and returns it.
     ///
     /// `path` must be absolute.
@@ -179,8 +250,8 @@ pub trait FileSystem: Sized {
     ///
     /// If there is no entry at `path`, an error kind of `NotFound` is returned.
     ///
-    /// If the entry at `path` is a directory and `children` is `false`, an
-    /// error kind of `Other` is returned.
+    /// If the entry at `path` is a non-empty directory and `children` is
+    /// `false`, an error kind of `Other` is returned.
     ///
     /// All other error values are implementation defined.
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()>;
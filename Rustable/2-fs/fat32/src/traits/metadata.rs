@@ -21,10 +21,19 @@ pub trait Timestamp: Copy + Clone + Sized {
 
     /// The second. Always in range [0, 60).
     fn second(&self) -> u8;
+
+    /// Returns the given point in time, or `None` if it is out of the ranges
+    /// above or cannot be represented by this type. Types with a coarser
+    /// resolution may round it.
+    fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8)
+        -> Option<Self>;
 }
 
 /// Trait for directory entry metadata.
-pub trait Metadata: Sized {
+///
+/// The setters only change `self`; see `Entry::set_metadata()` to write the
+/// changes back to the file system.
+pub trait Metadata: Sized + Clone {
     /// Type corresponding to a point in time.
     type Timestamp: Timestamp;
 
@@ -34,15 +43,20 @@ pub trait Metadata: Sized {
     /// Whether the entry should be "hidden" from directory traversals.
     fn hidden(&self) -> bool;
 
-    // fn system(&self) -> bool;
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool;
 
-    // fn volume_id(&self) -> bool;
+    /// Whether the entry holds the volume label rather than a file.
+    fn volume_id(&self) -> bool;
 
-    // fn is_lfn_entry(&self) -> bool;
+    /// Whether the entry is a directory.
+    fn directory(&self) -> bool;
 
-    // fn is_directory(&self) -> bool;
+    /// Whether the entry changed since it was last archived.
+    fn archive(&self) -> bool;
 
-    // fn is_archive(&self) -> bool;
+    /// The size of the entry's file in bytes. Always 0 for directories.
+    fn size(&self) -> u64;
 
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp;
@@ -52,5 +66,26 @@ pub trait Metadata: Sized {
 
     /// The timestamp for the entry's last modification.
     fn modified(&self) -> Self::Timestamp;
+
+    /// Sets whether the entry is read only.
+    fn set_read_only(&mut self, read_only: bool);
+
+    /// Sets whether the entry is hidden.
+    fn set_hidden(&mut self, hidden: bool);
+
+    /// Sets whether the entry belongs to the operating system.
+    fn set_system(&mut self, system: bool);
+
+    /// Sets whether the entry changed since it was last archived.
+    fn set_archive(&mut self, archive: bool);
+
+    /// Sets the timestamp when the entry was created.
+    fn set_created(&mut self, timestamp: Self::Timestamp);
+
+    /// Sets the timestamp for the entry's last access.
+    fn set_accessed(&mut self, timestamp: Self::Timestamp);
+
+    /// Sets the timestamp for the entry's last modification.
+    fn set_modified(&mut self, timestamp: Self::Timestamp);
 }
 
@@ -1,12 +1,30 @@
 use std::{io, fmt};
-use std::collections::HashMap;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
 
 use traits::BlockDevice;
 
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
-    dirty: bool
+    dirty: bool,
+    /// The value of the cache's clock when this entry was last accessed.
+    last_used: u64
+}
+
+/// Hit, miss, and write-back counters for a `CachedDevice`.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of accesses served from the cache.
+    pub hits: u64,
+    /// Number of accesses that had to read from the device.
+    pub misses: u64,
+    /// Number of sectors evicted to make room for others.
+    pub evictions: u64,
+    /// Number of dirty sectors written back to the device.
+    pub writebacks: u64,
+    /// Number of sectors read ahead of a miss.
+    pub read_ahead: u64,
 }
 
 pub struct Partition {
@@ -19,14 +37,30 @@ pub struct Partition {
 pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
-    partition: Partition
+    /// The cached sectors keyed by `last_used`, least recently used first.
+    lru: BTreeMap<u64, u64>,
+    partition: Partition,
+    capacity: usize,
+    clock: u64,
+    stats: CacheStats,
+    /// The most sectors read at once on a miss.
+    max_read_ahead: u64,
+    /// The number of sectors read on the last miss.
+    read_ahead: u64,
+    /// The sector whose miss would continue a sequential run of misses.
+    next_miss: Option<u64>,
 }
 
 impl CachedDevice {
+    /// The number of sectors cached by a device created with `new()`.
+    pub const DEFAULT_CAPACITY: usize = 1024;
+    /// The most sectors read at once on a miss by default.
+    pub const DEFAULT_READ_AHEAD: u64 = 32;
+
     /// Creates a new `CachedDevice` that transparently caches sectors from
     /// `device` and maps physical sectors to logical sectors inside of
     /// `partition`. All reads and writes from `CacheDevice` are performed on
-    /// in-memory caches.
+    /// in-memory caches. At most `DEFAULT_CAPACITY` sectors are cached.
     ///
     /// The `partition` parameter determines the size of a logical sector and
     /// where logical sectors begin. An access to a sector `n` _before_
@@ -44,16 +78,46 @@ impl CachedDevice {
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedDevice
         where T: BlockDevice + 'static
+    {
+        CachedDevice::with_capacity(device, partition, CachedDevice::DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new `CachedDevice` like `new()` that caches at most
+    /// `capacity` sectors. When the cache is full, the least recently used
+    /// sector is evicted, and written back to `device` first if it is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize)
+        -> CachedDevice where T: BlockDevice + 'static
     {
         assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0);
 
         CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
-            partition: partition
+            lru: BTreeMap::new(),
+            partition: partition,
+            capacity: capacity,
+            clock: 0,
+            stats: CacheStats::default(),
+            max_read_ahead: min(CachedDevice::DEFAULT_READ_AHEAD, capacity as u64 / 4),
+            read_ahead: 1,
+            next_miss: None,
         }
     }
 
+    /// Sets the most sectors read at once when a run of sequential misses is
+    /// detected. Each miss that continues the run doubles the number of
+    /// sectors read, up to `sectors`; any other miss reads a single sector.
+    /// A value of 0 or 1 disables read-ahead. The value is capped at a quarter
+    /// of the cache's capacity.
+    pub fn set_read_ahead(&mut self, sectors: u64) {
+        self.max_read_ahead = min(sectors, self.capacity as u64 / 4);
+    }
     /// Maps a user's request for a sector `virt` to the physical sector and
     /// number of physical sectors required to access `virt`.
     fn virtual_to_physical(&self, virt: u64) -> (u64, u64) {
@@ -70,6 +134,179 @@ impl CachedDevice {
         }
     }
 
+    /// Returns `true` if the `count` logical sectors starting at `sector` are
+    /// consecutive on the underlying device.
+    fn is_contiguous(&self, sector: u64, count: u64) -> bool {
+        let (first, factor) = self.virtual_to_physical(sector);
+        let (last, _) = self.virtual_to_physical(sector + count - 1);
+        last == first + (count - 1) * factor
+    }
+
+    /// Writes `data`, the contents of the logical sector `sector`, to the
+    /// underlying device.
+    fn write_back(device: &mut Box<BlockDevice>, physical_sector: u64,
+                  data: &[u8]) -> io::Result<()> {
+        device.write_sectors(physical_sector, data)?;
+        Ok(())
+    }
+
+    /// Evicts the least recently used sector, writing it back to the device
+    /// if it is dirty.
+    fn evict(&mut self) -> io::Result<()> {
+        let (last_used, victim) = match self.lru.iter().next() {
+            Some((&last_used, &sector)) => (last_used, sector),
+            None => return Ok(())
+        };
+
+        if self.cache[&victim].dirty {
+            let (physical_sector, _) = self.virtual_to_physical(victim);
+            CachedDevice::write_back(&mut self.device, physical_sector,
+                                     &self.cache[&victim].data)?;
+            self.stats.writebacks += 1;
+        }
+
+        self.lru.remove(&last_used);
+        self.cache.remove(&victim);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    /// Inserts `data` as the cached contents of `sector`, evicting sectors as
+    /// needed to stay within capacity.
+    fn insert(&mut self, sector: u64, data: Vec<u8>, dirty: bool) -> io::Result<()> {
+        while self.cache.len() >= self.capacity {
+            self.evict()?;
+        }
+
+        self.clock += 1;
+        let entry = CacheEntry { data, dirty, last_used: self.clock };
+        if let Some(old) = self.cache.insert(sector, entry) {
+            self.lru.remove(&old.last_used);
+        }
+        self.lru.insert(self.clock, sector);
+        Ok(())
+    }
+
+    /// Marks the cached sector `sector` as the most recently used.
+    fn touch(&mut self, sector: u64) {
+        self.clock += 1;
+        let entry = self.cache.get_mut(&sector).unwrap();
+        self.lru.remove(&entry.last_used);
+        entry.last_used = self.clock;
+        self.lru.insert(self.clock, sector);
+    }
+
+    /// Writes every dirty cached sector back to the device. Sectors stay
+    /// cached but are no longer dirty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the device fails. Sectors that were not
+    /// written back remain dirty.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        // Runs of consecutive dirty sectors are written back at once.
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut run = 1;
+            while i + run < dirty.len() && dirty[i + run] == dirty[i] + run as u64
+                && self.is_contiguous(dirty[i], run as u64 + 1) {
+                run += 1;
+            }
+
+            if run == 1 {
+                self.flush(dirty[i])?;
+            } else {
+                let mut data = Vec::new();
+                for sector in &dirty[i..i + run] {
+                    data.extend_from_slice(&self.cache[sector].data);
+                }
+                let (physical_sector, _) = self.virtual_to_physical(dirty[i]);
+                CachedDevice::write_back(&mut self.device, physical_sector, &data)?;
+                for sector in &dirty[i..i + run] {
+                    self.cache.get_mut(sector).unwrap().dirty = false;
+                }
+                self.stats.writebacks += run as u64;
+            }
+            i += run;
+        }
+
+        Ok(())
+    }
+
+    /// Writes sector `n` back to the device if it is cached and dirty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the device fails. The sector then
+    /// remains dirty.
+    pub fn flush(&mut self, n: u64) -> io::Result<()> {
+        let (physical_sector, _) = self.virtual_to_physical(n);
+        if let Some(entry) = self.cache.get_mut(&n) {
+            if entry.dirty {
+                CachedDevice::write_back(&mut self.device, physical_sector, &entry.data)?;
+                entry.dirty = false;
+                self.stats.writebacks += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the uncached sector `sector` into the cache. If the miss
+    /// continues a sequential run, the uncached sectors that follow it are
+    /// read along with it; see `set_read_ahead()`. A failed read-ahead, such
+    /// as one past the end of the device, falls back to reading `sector`
+    /// alone.
+    fn read_miss(&mut self, sector: u64) -> io::Result<()> {
+        self.read_ahead = if self.next_miss == Some(sector) {
+            ::std::cmp::max(1, min(self.read_ahead * 2, self.max_read_ahead))
+        } else {
+            1
+        };
+
+        let mut count = 1;
+        while count < self.read_ahead && !self.cache.contains_key(&(sector + count)) {
+            count += 1;
+        }
+
+        if count > 1 && self.is_contiguous(sector, count) {
+            let (physical_sector, factor) = self.virtual_to_physical(sector);
+            let sector_size = (factor * self.device.sector_size()) as usize;
+            let mut data = vec![0u8; sector_size * count as usize];
+            if let Ok(read) = self.device.read_sectors(physical_sector, &mut data) {
+                if read == data.len() {
+                    // Insert `sector` last so that it is the most recently
+                    // used.
+                    for (i, chunk) in data.chunks(sector_size).enumerate().rev() {
+                        self.insert(sector + i as u64, chunk.to_vec(), false)?;
+                    }
+                    self.stats.read_ahead += count - 1;
+                    self.next_miss = Some(sector + count);
+                    return Ok(());
+                }
+            }
+        }
+
+        let (physical_sector, factor) = self.virtual_to_physical(sector);
+        let mut data = Vec::new();
+        for i in 0..factor {
+            self.device.read_all_sector(physical_sector + i, &mut data)?;
+        }
+        self.next_miss = Some(sector + 1);
+        self.insert(sector, data, false)
+    }
+
+    /// Returns the cache's hit, miss, and write-back counters.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
     /// Returns a mutable reference to the cached sector `sector`. If the sector
     /// is not already cached, the sector is first read from the disk.
     ///
@@ -95,53 +332,64 @@ impl CachedDevice {
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
         if !self.cache.contains_key(&sector) {
-            let (physical_sector, factor) = self.virtual_to_physical(sector);
-            let mut data = Vec::new();
-
-            for i in 0..factor {
-                self.device.read_all_sector(physical_sector + i, &mut data)?;
-            }
+            self.read_miss(sector)?;
+            self.stats.misses += 1;
+        } else {
+            self.stats.hits += 1;
+        }
 
-            self.cache.insert(sector, CacheEntry { data, dirty: false });
-        } 
-        let cache_entry = self.cache.get(&sector).unwrap();
-        Ok(cache_entry.data.as_slice())
+        self.touch(sector);
+        Ok(self.cache[&sector].data.as_slice())
     }
 }
 
-// FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
-// `write_sector` methods should only read/write from/to cached sectors.
 impl BlockDevice for CachedDevice {
     fn sector_size(&self) -> u64 { //logical sector size
         self.partition.sector_size
     }
 
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.get(n)?;
+        let bytes = min(data.len(), buf.len());
+        buf[..bytes].copy_from_slice(&data[..bytes]);
+        Ok(bytes)
+    }
 
-        use std::io::Read;
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let (_, factor) = self.virtual_to_physical(n);
+        let sector_size = (factor * self.device.sector_size()) as usize;
+        if buf.len() < sector_size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "buffer smaller than sector"));
+        }
 
         if self.cache.contains_key(&n) {
-            
-            let cached_entry = &self.cache[&n];
-            
-            io::Cursor::new(&cached_entry.data).read(buf)
-
+            self.get_mut(n)?.copy_from_slice(&buf[..sector_size]);
         } else {
-
-            Err( io::Error::new( io::ErrorKind::Interrupted, "sector not exist" ) )
-                
+            // The whole sector is overwritten: no need to read it first.
+            self.stats.misses += 1;
+            self.insert(n, buf[..sector_size].to_vec(), true)?;
         }
+
+        Ok(sector_size)
     }
-    
-    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!();
+}
+
+impl Drop for CachedDevice {
+    /// Writes dirty sectors back to the device. Errors are ignored; call
+    /// `sync()` beforehand to observe them.
+    fn drop(&mut self) {
+        let _ = self.sync();
     }
 }
+
 impl fmt::Debug for CachedDevice {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CachedDevice")
             .field("device", &"<block device>")
-            .field("cache", &self.cache)
+            .field("cached_sectors", &self.cache.len())
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats)
             .finish()
     }
 }
@@ -0,0 +1,58 @@
+//! Case-insensitive comparison of file names.
+//!
+//! Long file names are compared under Unicode simple case folding, which maps
+//! every character to a single character. 8.3 names are stored in upper case,
+//! so names are compared to them after conversion to upper case instead.
+
+/// Returns the simple case folding of `c`.
+pub fn fold_char(c: char) -> char {
+    // Letters whose case folding differs from their lowercase form.
+    match c {
+        '\u{00B5}' => '\u{03BC}',
+        '\u{017F}' => 's',
+        '\u{0345}' | '\u{1FBE}' => '\u{03B9}',
+        '\u{03C2}' => '\u{03C3}',
+        '\u{03D0}' => '\u{03B2}',
+        '\u{03D1}' => '\u{03B8}',
+        '\u{03D5}' => '\u{03C6}',
+        '\u{03D6}' => '\u{03C0}',
+        '\u{03F0}' => '\u{03BA}',
+        '\u{03F1}' => '\u{03C1}',
+        '\u{03F5}' => '\u{03B5}',
+        '\u{1E9B}' => '\u{1E61}',
+        _ => single(c.to_lowercase()).unwrap_or(c),
+    }
+}
+
+/// Returns the simple case folding of `name`.
+pub fn fold(name: &str) -> String {
+    name.chars().map(fold_char).collect()
+}
+
+/// Returns `name` with every character that has a single character upper
+/// case form replaced by it.
+pub fn upper(name: &str) -> String {
+    name.chars().map(|c| single(c.to_uppercase()).unwrap_or(c)).collect()
+}
+
+/// Returns the only item of `iter`, if it has exactly one.
+fn single<I: Iterator<Item = char>>(mut iter: I) -> Option<char> {
+    match (iter.next(), iter.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// A name to look up, prepared for comparison with both long file names and
+/// 8.3 names.
+#[derive(Debug, Clone)]
+pub struct NameKey {
+    pub folded: String,
+    pub upper: String,
+}
+
+impl NameKey {
+    pub fn new(name: &str) -> NameKey {
+        NameKey { folded: fold(name), upper: upper(name) }
+    }
+}
@@ -13,7 +13,25 @@ impl From<u32> for Cluster {
 impl Cluster {
     /// Is this a valid cluster?
     pub fn is_valid(&self) -> bool {
-        self.0 > 2
+        self.0 >= 2
+    }
+
+    /// Stands for the fixed-size root directory region of FAT12 and FAT16
+    /// volumes, which lies outside the data region. Cluster 1 is never a data
+    /// cluster.
+    pub fn root_region() -> Cluster {
+        Cluster(1)
+    }
+
+    /// Is this the fixed-size root directory region of a FAT12 or FAT16
+    /// volume?
+    pub fn is_root_region(&self) -> bool {
+        self.0 == 1
+    }
+
+    /// The raw cluster number, as stored in FAT entries and directory entries.
+    pub fn raw(&self) -> u32 {
+        self.0
     }
 
     pub fn fat_index(&self) -> usize {
@@ -0,0 +1,31 @@
+//! Code page 437, the OEM character set short names are stored in.
+
+/// The characters of bytes 0x80 to 0xFF. Bytes below 0x80 are ASCII.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Returns the character encoded by `byte`.
+pub fn decode(byte: u8) -> char {
+    if byte < 0x80 {
+        byte as char
+    } else {
+        HIGH[(byte - 0x80) as usize]
+    }
+}
+
+/// Returns the byte encoding `c`, if `c` is part of code page 437.
+pub fn encode(c: char) -> Option<u8> {
+    if (c as u32) < 0x80 {
+        Some(c as u8)
+    } else {
+        HIGH.iter().position(|&h| h == c).map(|i| (i + 0x80) as u8)
+    }
+}
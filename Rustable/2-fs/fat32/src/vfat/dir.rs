@@ -1,12 +1,15 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::char::decode_utf16;
-use std::borrow::Cow;
 use std::io;
+use std::mem::{self, size_of};
 
 use traits;
-use util::{VecExt, Unused};
-use vfat::{VFat, Shared, File, Cluster, Entry};
+use util::{VecExt, SliceExt, Unused};
+use vfat::{VFat, Shared, File, Cluster, Entry, cp437};
 use vfat::{Metadata, Attributes, Timestamp, Time, Date};
+use vfat::case::{self, NameKey};
+use vfat::index::DirIndexes;
 
 #[derive(Debug)]
 pub struct Dir {
@@ -22,7 +25,7 @@ pub struct VFatRegularDirEntry {
     filename: [u8; 8],
     extension: [u8; 3],
     attributes: Attributes,
-    reserved: Unused<u8>,
+    case: u8,
     creation_time_subsecond: Unused<u8>,
     created: Timestamp,
     accessed: Date,
@@ -57,66 +60,122 @@ pub struct VFatUnknownDirEntry {
 }
 
 pub union VFatDirEntry {
-    unknown: VFatUnknownDirEntry,
-    regular: VFatRegularDirEntry,
-    long_filename: VFatLfnDirEntry,
+    pub(crate) unknown: VFatUnknownDirEntry,
+    pub(crate) regular: VFatRegularDirEntry,
+    pub(crate) long_filename: VFatLfnDirEntry,
 }
 
 pub struct DirIterator {
     data: Vec<VFatDirEntry>,
     offset: usize,
+    dir: Cluster,
     vfat: Shared<VFat>,
 }
 
 impl VFatRegularDirEntry {
+    /// Creates a new regular entry with an empty name. The name must be set
+    /// with `set_short_name()` before the entry is written to disk. Every
+    /// timestamp is `Timestamp::EPOCH` until set with `set_times()`.
+    pub fn new(attributes: Attributes, cluster: Cluster, file_size: u32)
+        -> VFatRegularDirEntry
+    {
+        let mut entry: VFatRegularDirEntry = unsafe { mem::zeroed() };
+        entry.attributes = attributes;
+        entry.set_cluster(cluster);
+        entry.file_size = file_size;
+        entry.set_times(Timestamp::EPOCH);
+        entry
+    }
+
+    /// Set in `case` if the name is displayed in lower case.
+    const LOWERCASE_NAME: u8 = 0x08;
+    /// Set in `case` if the extension is displayed in lower case.
+    const LOWERCASE_EXTENSION: u8 = 0x10;
+    /// Stored as the first byte of names starting with 0xE5, which otherwise
+    /// marks the entry as unused.
+    const ESCAPED_E5: u8 = 0x05;
+
+    /// Returns the 8.3 name of the entry as `NAME.EXT`, or `NAME` if the
+    /// extension is empty. The name is decoded from code page 437 and each
+    /// part is lower cased if its NT lowercase flag is set.
     pub fn filename(&self) -> String {
-        // let name = VFatRegularDirEntry::fat_string(&self.filename);
-
-        // if !self.is_dir() {
-        //     let extension = VFatRegularDirEntry::fat_string(&self.extension);
-
-        //     if !extension.is_empty() {
-        //         let mut full_name = name.into_owned();
-        //         full_name.push('.');
-        //         full_name.push_str(&extension);
-        //         return full_name;
-        //     }
-        // }
-
-        // name.into_owned()
-        use std::str;
-        let short_file_name = self.filename.clone();
-        let short_file_ext = self.extension.clone();
-        let file_name = [104, 101, 108, 108, 111, 111, 111, 111];
-        // String::from( str::from_utf8( &file_name ).unwrap().trim_right() )
-        String::from( str::from_utf8( &short_file_name ).unwrap().trim_right() )
-        // let short_name = str::from_utf8( &regular_entry.file_name ).unwrap().trim_right();
-        // let short_ext = str::from_utf8( &regular_entry.file_extension ).unwrap().trim_right();
-        // let short_name = str::from_utf8( &short_file_name ).unwrap().trim_right();
-        // let short_ext = str::from_utf8( &short_file_ext ).unwrap().trim_right();
-        
-        // let short_file_name = if short_ext.len() > 0 {
-        //     let mut s = String::from( short_name );
-        //     s.push_str( &"." );
-        //     s.push_str( short_ext );
-        //     s
-        // } else {
-        //     String::from( short_name )
-        // };
-    }
-
-    pub fn fat_string<'a>(buf: &'a [u8]) -> Cow<'a, str> {
-        let mut end = 0;
-        for i in 0..buf.len() {
-            // A file name may be terminated early using 0x00 or 0x20 characters.
-            if buf[i] == 0x00 || buf[i] == 0x20 {
-                break
-            }
+        self.format_name(self.case & VFatRegularDirEntry::LOWERCASE_NAME != 0,
+                         self.case & VFatRegularDirEntry::LOWERCASE_EXTENSION != 0)
+    }
 
-            end += 1;
+    /// Returns the 8.3 name of the entry as stored, in upper case, ignoring
+    /// its NT lowercase flags.
+    pub fn alias(&self) -> String {
+        self.format_name(false, false)
+    }
+
+    fn format_name(&self, lowercase_name: bool, lowercase_extension: bool) -> String {
+        let mut filename = self.filename;
+        if filename[0] == VFatRegularDirEntry::ESCAPED_E5 {
+            filename[0] = 0xE5;
+        }
+
+        let name = VFatRegularDirEntry::fat_string(&filename, lowercase_name);
+        let extension = VFatRegularDirEntry::fat_string(&self.extension, lowercase_extension);
+
+        if extension.is_empty() {
+            name
+        } else {
+            format!("{}.{}", name, extension)
         }
+    }
+
+    /// Sets the on-disk 8.3 name of the entry and clears the lowercase flags.
+    /// Both parts must already be padded with spaces.
+    pub fn set_short_name(&mut self, mut name: [u8; 8], extension: [u8; 3]) {
+        if name[0] == 0xE5 {
+            name[0] = VFatRegularDirEntry::ESCAPED_E5;
+        }
+
+        self.filename = name;
+        self.extension = extension;
+        self.case = 0;
+    }
+
+    /// Sets whether the name and the extension are displayed in lower case.
+    pub fn set_lowercase(&mut self, name: bool, extension: bool) {
+        self.case = 0;
+        if name {
+            self.case |= VFatRegularDirEntry::LOWERCASE_NAME;
+        }
+        if extension {
+            self.case |= VFatRegularDirEntry::LOWERCASE_EXTENSION;
+        }
+    }
+
+    /// Returns the raw, space padded 8.3 name of the entry.
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut name = [0u8; 11];
+        name[..8].copy_from_slice(&self.filename);
+        name[8..].copy_from_slice(&self.extension);
+        name
+    }
+
+    /// The checksum of the 8.3 name stored in every LFN entry belonging to
+    /// this entry.
+    pub fn checksum(&self) -> u8 {
+        self.short_name().iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+    }
+
+    /// Decodes one part of an 8.3 name, dropping the padding.
+    pub fn fat_string(buf: &[u8], lowercase: bool) -> String {
+        // A file name may be terminated early using 0x00 characters and is
+        // padded with 0x20 characters.
+        let end = buf.iter().position(|&c| c == 0x00).unwrap_or(buf.len());
+        let end = buf[..end].iter().rposition(|&c| c != 0x20).map_or(0, |i| i + 1);
+
+        buf[..end].iter()
+            .map(|&c| if lowercase { cp437::decode(c.to_ascii_lowercase()) } else { cp437::decode(c) })
+            .collect()
+    }
 
-        String::from_utf8_lossy(&buf[..end])
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
     }
 
     pub fn is_dir(&self) -> bool {
@@ -127,9 +186,103 @@ impl VFatRegularDirEntry {
         return Cluster::from((self.cluster_high as u32) << 16
                                 | self.cluster_low as u32)
     }
+
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.cluster_high = (cluster.raw() >> 16) as u16;
+        self.cluster_low = cluster.raw() as u16;
+    }
+
+    pub fn file_size(&self) -> u32 {
+        self.file_size
+    }
+
+    /// Returns the metadata stored in the entry.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(self.attributes, self.created,
+                      Timestamp { time: Time::default(), date: self.accessed },
+                      self.modified, self.file_size)
+    }
+
+    /// Stores the settable attributes and the timestamps of `metadata` in the
+    /// entry.
+    pub fn set_metadata(&mut self, metadata: &Metadata) {
+        self.attributes = self.attributes.with_settable(metadata.attributes);
+        self.created = metadata.created;
+        self.accessed = metadata.accessed.date;
+        self.modified = metadata.modified;
+    }
+
+    /// Sets the creation, last access and last modification timestamps to
+    /// `now`, as for a newly created entry.
+    pub fn set_times(&mut self, now: Timestamp) {
+        self.created = now;
+        self.accessed = now.date;
+        self.modified = now;
+    }
+
+    /// Sets the last modification timestamp, and the last access date with it.
+    pub fn set_modified(&mut self, now: Timestamp) {
+        self.accessed = now.date;
+        self.modified = now;
+    }
+
+    pub fn set_file_size(&mut self, size: u32) {
+        self.file_size = size;
+    }
 }
 
 impl VFatLfnDirEntry {
+    /// The number of UTF-16 code units held by a single LFN entry.
+    const CHARS: usize = 13;
+    /// Set in the sequence number of the last LFN entry of a name.
+    const LAST_ENTRY: u8 = 0x40;
+    /// The most LFN entries a name can take: 255 characters, 13 per entry.
+    const MAX_ENTRIES: usize = 20;
+
+    /// Creates the LFN entry holding the `sequence`th (starting at 1) group
+    /// of 13 code units of `name` for the regular entry with checksum
+    /// `checksum`. The name is terminated with 0x0000 and padded with 0xFFFF.
+    pub fn new(name: &[u16], sequence: usize, checksum: u8) -> VFatLfnDirEntry {
+        let start = (sequence - 1) * VFatLfnDirEntry::CHARS;
+        let mut chars = [0xFFFFu16; 13];
+        for i in 0..VFatLfnDirEntry::CHARS {
+            if start + i < name.len() {
+                chars[i] = name[start + i];
+            } else if start + i == name.len() {
+                chars[i] = 0x0000;
+            }
+        }
+
+        let mut entry: VFatLfnDirEntry = unsafe { mem::zeroed() };
+        entry.sequence_number = sequence as u8;
+        if start + VFatLfnDirEntry::CHARS >= name.len() {
+            entry.sequence_number |= VFatLfnDirEntry::LAST_ENTRY;
+        }
+        entry.attributes = VFatUnknownDirEntry::FLAG_LFN;
+        entry.checksum = checksum;
+        entry.name_1 = [chars[0], chars[1], chars[2], chars[3], chars[4]];
+        entry.name_2 = [chars[5], chars[6], chars[7], chars[8], chars[9], chars[10]];
+        entry.name_3 = [chars[11], chars[12]];
+        entry
+    }
+
+    /// Returns `true` if the entry holds the last part of a name, which is
+    /// stored first.
+    pub fn is_last(&self) -> bool {
+        self.sequence_number & VFatLfnDirEntry::LAST_ENTRY != 0
+    }
+
+    /// Returns `false` if the sequence number of the entry is 0.
+    pub fn has_sequence_number(&self) -> bool {
+        self.sequence_number & 0b11111 != 0
+    }
+
+    /// The checksum of the 8.3 name of the regular entry this entry belongs
+    /// to.
+    pub fn checksum(&self) -> u8 {
+        self.checksum
+    }
+
     pub fn sequence_number(&self) -> usize {
         let result = self.sequence_number & 0b11111;
         assert!(result != 0);
@@ -142,9 +295,9 @@ impl VFatLfnDirEntry {
         buf.extend_from_slice(&self.name_2);
         buf.extend_from_slice(&self.name_3);
 
-        // A file name may be terminated early using 0x00 or 0xFF characters. *from PDF
+        // A file name may be terminated early using 0x0000 or 0xFFFF characters.
         for i in start..buf.len() {
-            if buf[i] == 0x0000 || buf[i] == 0x00FF {
+            if buf[i] == 0x0000 || buf[i] == 0xFFFF {
                 buf.resize(i, 0);
                 return;
             }
@@ -169,6 +322,218 @@ impl VFatUnknownDirEntry {
     pub fn is_LFN(&self) -> bool {
         self.attributes == VFatUnknownDirEntry::FLAG_LFN
     }
+
+    /// Marks the entry as deleted.
+    pub fn set_unused(&mut self) {
+        self.entry_info = VFatUnknownDirEntry::FLAG_UNUSED;
+    }
+}
+
+impl VFatDirEntry {
+    fn regular(entry: VFatRegularDirEntry) -> VFatDirEntry {
+        VFatDirEntry { regular: entry }
+    }
+
+    fn long_filename(entry: VFatLfnDirEntry) -> VFatDirEntry {
+        VFatDirEntry { long_filename: entry }
+    }
+}
+
+/// The on-disk position of a regular directory entry: the first cluster of
+/// the directory holding it and the entry's index within that directory.
+#[derive(Debug, Copy, Clone)]
+pub struct EntryLocation {
+    pub dir: Cluster,
+    pub index: usize,
+}
+
+impl EntryLocation {
+    /// Reads the regular directory entry at `self`.
+    pub fn read(&self, vfat: &mut VFat) -> io::Result<VFatRegularDirEntry> {
+        let mut buf = [0u8; 32];
+        vfat.read_chain_at(self.dir, self.index * size_of::<VFatDirEntry>(), &mut buf)?;
+        Ok(unsafe { mem::transmute(buf) })
+    }
+
+    /// Overwrites the regular directory entry at `self` with `entry`.
+    pub fn write(&self, vfat: &mut VFat, entry: VFatRegularDirEntry) -> io::Result<()> {
+        let buf: [u8; 32] = unsafe { mem::transmute(entry) };
+        vfat.write_chain_at(self.dir, self.index * size_of::<VFatDirEntry>(), &buf)?;
+        Ok(())
+    }
+}
+
+/// A deleted regular entry, as found by `Dir::deleted()`.
+///
+/// Deleting an entry overwrites the first byte of its 8.3 name. If the entry
+/// had a long file name, the byte is recovered from the checksum stored in its
+/// LFN entries; otherwise it is replaced with `_`.
+#[derive(Debug, Clone)]
+pub struct DeletedEntry {
+    /// The index of the raw regular entry in the directory.
+    pub index: usize,
+    /// The long file name of the entry if it had one, otherwise its 8.3 name.
+    pub name: String,
+    /// The first cluster the entry's data was stored in.
+    pub cluster: Cluster,
+    /// The size of the file in bytes.
+    pub size: u32,
+    pub metadata: Metadata,
+    /// The raw 8.3 name with its first byte restored.
+    short_name: [u8; 11],
+    /// The number of LFN entries preceding the regular entry.
+    lfn_entries: usize,
+}
+
+/// A regular entry found in a directory together with the range of raw
+/// entries (its LFN entries followed by the entry itself) that it occupies.
+pub struct Slot {
+    pub first: usize,
+    pub index: usize,
+    pub name: String,
+    pub entry: VFatRegularDirEntry,
+}
+
+impl Slot {
+    /// Returns `true` if `key` is, ignoring case, the name of the entry or
+    /// its 8.3 alias.
+    fn matches(&self, key: &NameKey) -> bool {
+        self.name.chars().map(case::fold_char).eq(key.folded.chars())
+            || self.entry.alias() == key.upper
+    }
+}
+
+/// The printable ASCII characters that may not appear in 8.3 names.
+const INVALID_SHORT_NAME_CHARS: &'static [u8] = b"\"*+,/:;<=>?[\\]| ";
+
+/// Converts `name` into a space padded 8.3 short name.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `name` cannot be stored as an 8.3
+/// short name.
+fn short_name(name: &str) -> io::Result<([u8; 8], [u8; 3])> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput,
+                                    "not a valid 8.3 file name");
+
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+        return Err(invalid());
+    }
+
+    let mut short = ([b' '; 8], [b' '; 3]);
+    for (i, &c) in base.as_bytes().iter().enumerate() {
+        if !c.is_ascii() || c < 0x20 || c == b'.' || INVALID_SHORT_NAME_CHARS.contains(&c) {
+            return Err(invalid());
+        }
+        short.0[i] = c.to_ascii_uppercase();
+    }
+    for (i, &c) in ext.as_bytes().iter().enumerate() {
+        if !c.is_ascii() || c < 0x20 || INVALID_SHORT_NAME_CHARS.contains(&c) {
+            return Err(invalid());
+        }
+        short.1[i] = c.to_ascii_uppercase();
+    }
+
+    Ok(short)
+}
+
+/// Returns the NT lowercase flags of the name and the extension if `name` can
+/// be stored as an 8.3 short name without an LFN entry: each part must be
+/// either entirely upper case or entirely lower case.
+fn short_name_case(name: &str) -> Option<(bool, bool)> {
+    fn lowercase(part: &str) -> Option<bool> {
+        let lower = part.bytes().any(|c| c.is_ascii_lowercase());
+        let upper = part.bytes().any(|c| c.is_ascii_uppercase());
+        if lower && upper { None } else { Some(lower) }
+    }
+
+    short_name(name).ok()?;
+    match name.rfind('.') {
+        Some(i) => Some((lowercase(&name[..i])?, lowercase(&name[i + 1..])?)),
+        None => Some((lowercase(name)?, false)),
+    }
+}
+
+/// Converts `name` into the UTF-16 code units stored in LFN entries.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `name` is too long or contains
+/// characters not allowed in long file names.
+fn long_name(name: &str) -> io::Result<Vec<u16>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput,
+                                    "not a valid long file name");
+
+    if name.ends_with('.') || name.ends_with(' ')
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c)) {
+        return Err(invalid());
+    }
+
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.is_empty() || units.len() > 255 {
+        return Err(invalid());
+    }
+
+    Ok(units)
+}
+
+/// Generates an 8.3 alias of the form `NAME~N.EXT` for the long file name
+/// `name` that collides with none of the short names in `taken`.
+///
+/// # Errors
+///
+/// Returns an error of `AlreadyExists` if every numeric tail is taken.
+fn short_alias(name: &str, taken: &[[u8; 11]]) -> io::Result<([u8; 8], [u8; 3])> {
+    // Characters allowed in long but not in short names, or missing from
+    // code page 437, become underscores; spaces and leading and embedded
+    // periods are dropped.
+    fn convert(part: &str, max: usize) -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c {
+                '+' | ',' | ';' | '=' | '[' | ']' => b'_',
+                c => {
+                    let mut upper = c.to_uppercase();
+                    match (upper.next(), upper.next()) {
+                        (Some(u), None) => cp437::encode(u).unwrap_or(b'_'),
+                        _ => b'_',
+                    }
+                }
+            })
+            .take(max)
+            .collect()
+    }
+
+    let trimmed = name.trim_left_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) => (convert(&trimmed[..i], 8), convert(&trimmed[i + 1..], 3)),
+        None => (convert(trimmed, 8), Vec::new()),
+    };
+
+    let mut short = ([b' '; 8], [b' '; 3]);
+    short.1[..ext.len()].copy_from_slice(&ext);
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let keep = ::std::cmp::min(base.len(), 8 - tail.len());
+        short.0 = [b' '; 8];
+        short.0[..keep].copy_from_slice(&base[..keep]);
+        short.0[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+
+        let mut candidate = [0u8; 11];
+        candidate[..8].copy_from_slice(&short.0);
+        candidate[8..].copy_from_slice(&short.1);
+        if !taken.contains(&candidate) {
+            return Ok(short);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free short name alias"))
 }
 
 impl Dir {
@@ -176,8 +541,13 @@ impl Dir {
         Dir { start_cluster, vfat }
     }
 
-    /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    pub(crate) fn vfat(&self) -> &Shared<VFat> {
+        &self.vfat
+    }
+
+    /// Finds the entry named `name` in `self` and returns it. Long file names
+    /// are compared under Unicode simple case folding, and entries can also be
+    /// found by their 8.3 alias, compared in upper case.
     ///
     /// # Errors
     ///
@@ -187,63 +557,541 @@ impl Dir {
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
-        use traits::{Dir, Entry};
-        // let print_func = unsafe { &*(self.vfat.borrow_mut().print_func) };
-        // print_func(1111111111);
-
         let name_str = name.as_ref().to_str().ok_or(
             io::Error::new(io::ErrorKind::InvalidInput, "Invalid UTF-8"))?;
 
-        self.entries()?.find(|item| {
-            item.name().eq_ignore_ascii_case(name_str)
-        }).ok_or(io::Error::new(io::ErrorKind::NotFound, "Not found"))
+        let slot = self.find_slot(name_str)?;
+        Ok(self.iter_from(Vec::new()).create_entry(slot))
     }
-}
 
-impl DirIterator {
-    fn LFN_filename(LFN_entry: &mut Vec<&VFatLfnDirEntry>) -> String {
-        LFN_entry.sort_by_key(|a| a.sequence_number());
+    /// Finds the entry named `name` in `self` and returns the slot it occupies.
+    /// Names are compared as in `find()`.
+    ///
+    /// Directories with many entries are indexed by name on the first lookup,
+    /// so that later lookups read only the entries found through the index.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    pub fn find_slot(&self, name: &str) -> io::Result<Slot> {
+        self.lookup_slot(&NameKey::new(name), true)
+    }
 
-        // LFN entry has 13 unicodes
-        let mut name_string: Vec<u16> = Vec::with_capacity(13 * LFN_entry.len());
-        for entry in LFN_entry.iter() {
-            entry.append_name(&mut name_string);
+    /// Finds the slot of the entry matching `key`, through the index of
+    /// `self` if it has one. Otherwise the directory is scanned, and indexed
+    /// if `build_index` is `true` and it is large enough.
+    fn lookup_slot(&self, key: &NameKey, build_index: bool) -> io::Result<Slot> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "Not found");
+
+        let indexed = self.vfat.borrow_mut().dir_indexes().lookup(self.start_cluster, key)
+            .map(|(index, clusters)| (index, clusters.to_vec()));
+        match indexed {
+            Some((Some(index), clusters)) => {
+                if let Some(slot) = self.slot_at(index, &clusters)? {
+                    if slot.matches(key) {
+                        return Ok(slot);
+                    }
+                }
+                self.vfat.borrow_mut().dir_indexes().invalidate(self.start_cluster);
+            }
+            Some((None, _)) => return Err(not_found()),
+            None => {}
         }
 
-        String::from_utf16_lossy(name_string.as_slice())
+        let mut entries = self.iter()?;
+        let mut index = HashMap::new();
+        let mut found = None;
+        let mut count = 0;
+        while let Some(slot) = entries.next_slot() {
+            if !build_index {
+                if slot.matches(key) {
+                    return Ok(slot);
+                }
+                continue;
+            }
+
+            count += 1;
+            index.entry(case::fold(&slot.name)).or_insert(slot.index);
+            index.entry(slot.entry.alias()).or_insert(slot.index);
+            if found.is_none() && slot.matches(key) {
+                found = Some(slot);
+            }
+        }
+
+        if count >= DirIndexes::MIN_ENTRIES {
+            let mut vfat = self.vfat.borrow_mut();
+            let clusters = if self.start_cluster.is_root_region() {
+                Vec::new()
+            } else {
+                vfat.chain_clusters(self.start_cluster)?
+            };
+            vfat.dir_indexes().insert(self.start_cluster, clusters, index);
+        }
+        found.ok_or_else(not_found)
     }
 
-    pub fn create_entry(
-        &self, 
-        LFN_entry: &mut Vec<&VFatLfnDirEntry>,
-        entry: VFatRegularDirEntry
-    ) -> Entry {
-        let name = if LFN_entry.is_empty() {
-            entry.filename()
-        } else {
-            DirIterator::LFN_filename(LFN_entry)
+    /// Reads the slot whose regular entry is raw entry `index`, or returns
+    /// `None` if there is no such slot. `clusters` is the cluster chain of
+    /// `self`, or empty to walk the chain instead.
+    fn slot_at(&self, index: usize, clusters: &[Cluster]) -> io::Result<Option<Slot>> {
+        // The slot's LFN entries are among those just before it.
+        let first = index.saturating_sub(VFatLfnDirEntry::MAX_ENTRIES);
+        let mut buf = vec![0u8; (index + 1 - first) * size_of::<VFatDirEntry>()];
+        let offset = first * size_of::<VFatDirEntry>();
+        let read = {
+            let mut vfat = self.vfat.borrow_mut();
+            if clusters.is_empty() {
+                vfat.read_chain_at(self.start_cluster, offset, &mut buf)?
+            } else {
+                let cluster_size = vfat.cluster_size();
+                let mut read = 0;
+                while read < buf.len() {
+                    let position = offset + read;
+                    let cluster = match clusters.get(position / cluster_size) {
+                        Some(&cluster) => cluster,
+                        None => break,
+                    };
+                    read += vfat.read_cluster(cluster, position % cluster_size, &mut buf[read..])?;
+                }
+                read
+            }
         };
+        if read < buf.len() {
+            return Ok(None);
+        }
 
-        let metadata = Metadata::new(
-            entry.attributes,
-            entry.created,
-            Timestamp { time: Time::default(), date: entry.accessed },
-            entry.modified
-        );
+        let mut entries = self.iter_from(unsafe { buf.cast() });
+        while let Some(mut slot) = entries.next_slot() {
+            if first + slot.index == index {
+                slot.first += first;
+                slot.index = index;
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
 
-        if entry.is_dir() {
-            Entry::new_dir(name, metadata, Dir::new(entry.cluster(), self.vfat.clone()))
+    /// Returns an iterator over the entries in this directory.
+    fn iter(&self) -> io::Result<DirIterator> {
+        Ok(self.iter_from(self.raw_entries()?))
+    }
+
+    /// Returns an iterator over the entries in `data`, which are raw entries
+    /// of `self`.
+    fn iter_from(&self, data: Vec<VFatDirEntry>) -> DirIterator {
+        DirIterator { data, offset: 0, dir: self.start_cluster, vfat: self.vfat.clone() }
+    }
+
+    /// Reads every raw entry in the directory's cluster chain.
+    pub(crate) fn raw_entries(&self) -> io::Result<Vec<VFatDirEntry>> {
+        let mut data = Vec::new();
+        self.vfat.borrow_mut().read_chain(self.start_cluster, &mut data)?;
+        Ok(unsafe { data.cast() })
+    }
+
+    /// Writes `entries` to consecutive raw entries starting at `index`, and
+    /// drops the name index of `self`.
+    pub(crate) fn write_raw_entries(&self, index: usize, entries: &[VFatDirEntry]) -> io::Result<()> {
+        let buf: &[u8] = unsafe { entries.cast() };
+        let offset = index * size_of::<VFatDirEntry>();
+        let mut vfat = self.vfat.borrow_mut();
+        vfat.dir_indexes().invalidate(self.start_cluster);
+        vfat.write_chain_at(self.start_cluster, offset, buf)?;
+        Ok(())
+    }
+
+    /// Finds `count` consecutive free raw entries, growing the directory by a
+    /// cluster if no such run exists, and returns the index of the first one.
+    fn alloc_raw_entries(&self, count: usize) -> io::Result<usize> {
+        let data = self.raw_entries()?;
+        let mut run_start = data.len();
+        let mut run_length = 0;
+
+        for index in 0..data.len() {
+            let unknown_entry = unsafe { data[index].unknown };
+            if unknown_entry.is_end() {
+                // Every entry following the end marker is free as well.
+                if run_length == 0 {
+                    run_start = index;
+                }
+                run_length += data.len() - index;
+                break;
+            } else if unknown_entry.is_unused() {
+                if run_length == 0 {
+                    run_start = index;
+                }
+                run_length += 1;
+                if run_length >= count {
+                    return Ok(run_start);
+                }
+            } else {
+                run_length = 0;
+            }
+        }
+
+        if run_length == 0 {
+            run_start = data.len();
+        }
+
+        if run_length < count {
+            // Newly allocated clusters are zeroed: they hold only end markers.
+            let needed = (run_start + count) * size_of::<VFatDirEntry>();
+            self.vfat.borrow_mut().resize_chain(self.start_cluster, needed)?;
+        }
+
+        Ok(run_start)
+    }
+
+    /// Adds a regular entry named `name` to `self`. The short name of `entry`
+    /// is derived from `name`: 8.3 names whose name and extension are each in
+    /// a single case are stored using the NT lowercase flags, while other
+    /// names are stored in LFN entries preceding `entry`, which gets a unique
+    /// `NAME~N.EXT` alias. Returns the location of the new regular entry.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists` is
+    /// returned. If `name` is not a valid file name, an error of
+    /// `InvalidInput` is returned.
+    pub fn insert(&self, name: &str, mut entry: VFatRegularDirEntry) -> io::Result<EntryLocation> {
+        if name == "." || name == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "reserved file name"));
+        }
+
+        // The directory is about to change, so indexing it would be wasted.
+        match self.lookup_slot(&NameKey::new(name), false) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                               "entry already exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut entries = Vec::new();
+        if let Some((lowercase_name, lowercase_extension)) = short_name_case(name) {
+            let (base, extension) = short_name(name)?;
+            entry.set_short_name(base, extension);
+            entry.set_lowercase(lowercase_name, lowercase_extension);
+        } else {
+            let units = long_name(name)?;
+            let (base, extension) = short_alias(name, &self.short_names()?)?;
+            entry.set_short_name(base, extension);
+
+            // LFN entries are stored last part first, before the regular entry.
+            let checksum = entry.checksum();
+            let count = (units.len() + VFatLfnDirEntry::CHARS - 1) / VFatLfnDirEntry::CHARS;
+            for sequence in (1..count + 1).rev() {
+                entries.push(VFatDirEntry::long_filename(
+                    VFatLfnDirEntry::new(&units, sequence, checksum)));
+            }
+        }
+        entries.push(VFatDirEntry::regular(entry));
+
+        let index = self.alloc_raw_entries(entries.len())?;
+        self.write_raw_entries(index, &entries)?;
+        Ok(EntryLocation { dir: self.start_cluster, index: index + entries.len() - 1 })
+    }
+
+    /// Returns the raw 8.3 names of every regular entry in `self`.
+    fn short_names(&self) -> io::Result<Vec<[u8; 11]>> {
+        let mut names = Vec::new();
+        let mut entries = self.iter()?;
+        while let Some(slot) = entries.next_slot() {
+            names.push(slot.entry.short_name());
+        }
+
+        Ok(names)
+    }
+
+    /// Marks every raw entry occupied by `slot` as deleted.
+    pub fn delete(&self, slot: &Slot) -> io::Result<()> {
+        let mut data = self.raw_entries()?;
+        for index in slot.first..slot.index + 1 {
+            unsafe { data[index].unknown.set_unused(); }
+        }
+        self.write_raw_entries(slot.first, &data[slot.first..slot.index + 1])
+    }
+
+    /// The raw cluster number stored in `.` and `..` entries referring to
+    /// `self`. The root directory is always referred to as cluster 0.
+    fn dot_cluster(&self) -> Cluster {
+        if self.start_cluster == self.vfat.borrow().root_dir_cluster() {
+            Cluster::from(0)
         } else {
-            Entry::new_file(name, metadata, File::new(entry.cluster(), self.vfat.clone(), entry.file_size))
+            self.start_cluster
         }
     }
+
+    /// Creates an empty file named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// See `insert()`.
+    pub fn create_file(&self, name: &str) -> io::Result<File> {
+        let mut entry = VFatRegularDirEntry::new(Attributes::file(), Cluster::from(0), 0);
+        entry.set_times(self.vfat.borrow().now());
+        let location = self.insert(name, entry)?;
+        Ok(File::new(Cluster::from(0), self.vfat.clone(), 0, location))
+    }
+
+    /// Creates an empty directory named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// See `insert()`.
+    pub fn create_dir(&self, name: &str) -> io::Result<Dir> {
+        let cluster = self.vfat.borrow_mut().alloc_cluster(None)?;
+        let dir = Dir::new(cluster, self.vfat.clone());
+        let now = self.vfat.borrow().now();
+
+        let mut dot = VFatRegularDirEntry::new(Attributes::dir(), dir.dot_cluster(), 0);
+        dot.set_short_name(*b".       ", *b"   ");
+        dot.set_times(now);
+        let mut dotdot = VFatRegularDirEntry::new(Attributes::dir(), self.dot_cluster(), 0);
+        dotdot.set_short_name(*b"..      ", *b"   ");
+        dotdot.set_times(now);
+
+        let mut entry = VFatRegularDirEntry::new(Attributes::dir(), cluster, 0);
+        entry.set_times(now);
+        let result = dir.write_raw_entries(0, &[VFatDirEntry::regular(dot),
+                                                 VFatDirEntry::regular(dotdot)])
+            .and_then(|_| self.insert(name, entry));
+
+        match result {
+            Ok(_) => Ok(dir),
+            Err(e) => {
+                self.vfat.borrow_mut().free_chain(cluster)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns `true` if `self` contains no entries other than `.` and `..`.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        let mut entries = self.iter()?;
+        while let Some(slot) = entries.next_slot() {
+            if slot.name != "." && slot.name != ".." {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the volume label stored in `self`, or `None` if it has none.
+    /// Only the root directory holds a volume label entry.
+    pub fn volume_label(&self) -> io::Result<Option<String>> {
+        let mut entries = self.iter()?;
+        while let Some(slot) = entries.next_slot() {
+            if slot.entry.attributes().volume_id() {
+                return Ok(Some(VFatRegularDirEntry::fat_string(&slot.entry.short_name(), false)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns an iterator over the deleted regular entries of `self`.
+    pub fn deleted(&self) -> io::Result<DeletedEntries> {
+        Ok(DeletedEntries { data: self.raw_entries()?, offset: 0 })
+    }
+
+    /// Restores the deleted file `deleted`, as returned by `self.deleted()`,
+    /// and returns it. FAT keeps no record of the cluster chain of deleted
+    /// files, so the file's clusters are assumed to be consecutive, which they
+    /// usually are on unfragmented volumes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `deleted` is a directory, of
+    /// `NotFound` if its raw entries have been reused since, of
+    /// `AlreadyExists` if `self` already holds an entry of the same name, and
+    /// of `Other` if any of its clusters has been reused.
+    pub fn undelete(&self, deleted: &DeletedEntry) -> io::Result<File> {
+        if deleted.metadata.attributes().directory() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "only files can be undeleted"));
+        }
+
+        let mut data = self.raw_entries()?;
+        let first = deleted.index - deleted.lfn_entries;
+        let unchanged = deleted.index < data.len() && (first..deleted.index + 1).all(|i| {
+            let unknown_entry = unsafe { data[i].unknown };
+            unknown_entry.is_unused() && unknown_entry.is_LFN() == (i != deleted.index)
+        }) && {
+            let entry = unsafe { data[deleted.index].regular };
+            entry.short_name()[1..] == deleted.short_name[1..]
+                && entry.cluster() == deleted.cluster && entry.file_size == deleted.size
+        };
+        if !unchanged {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                                      "deleted entry has been overwritten"));
+        }
+
+        let exists = match self.find_slot(&deleted.name) {
+            Ok(_) => true,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if exists || self.short_names()?.contains(&deleted.short_name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+
+        if deleted.size > 0 && deleted.cluster.is_valid() {
+            let mut vfat = self.vfat.borrow_mut();
+            let cluster_size = vfat.cluster_size();
+            let clusters = (deleted.size as usize + cluster_size - 1) / cluster_size;
+            vfat.relink_chain(deleted.cluster, clusters)?;
+        }
+
+        // LFN entries are stored last part first, before the regular entry.
+        for sequence in 1..deleted.lfn_entries + 1 {
+            let lfn = unsafe { &mut data[deleted.index - sequence].long_filename };
+            lfn.sequence_number = sequence as u8;
+            if sequence == deleted.lfn_entries {
+                lfn.sequence_number |= VFatLfnDirEntry::LAST_ENTRY;
+            }
+        }
+        unsafe { data[deleted.index].regular.filename[0] = deleted.short_name[0]; }
+        self.write_raw_entries(first, &data[first..deleted.index + 1])?;
+
+        let location = EntryLocation { dir: self.start_cluster, index: deleted.index };
+        Ok(File::new(deleted.cluster, self.vfat.clone(), deleted.size, location))
+    }
+
+    /// Removes the entry named `name` from `self`, releasing its clusters. If
+    /// the entry is a directory and `children` is `true`, its contents are
+    /// removed recursively.
+    ///
+    /// # Errors
+    ///
+    /// If no entry named `name` exists, an error of `NotFound` is returned.
+    /// If the entry is a non-empty directory and `children` is `false`, an
+    /// error of `Other` is returned.
+    pub fn remove(&self, name: &str, children: bool) -> io::Result<()> {
+        if name == "." || name == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "reserved file name"));
+        }
+
+        let slot = self.find_slot(name)?;
+        if slot.entry.is_dir() {
+            let dir = Dir::new(slot.entry.cluster(), self.vfat.clone());
+            if !dir.is_empty()? {
+                if !children {
+                    return Err(io::Error::new(io::ErrorKind::Other,
+                                              "directory not empty"));
+                }
+
+                let mut names = Vec::new();
+                let mut entries = dir.iter()?;
+                while let Some(child) = entries.next_slot() {
+                    if child.name != "." && child.name != ".." {
+                        names.push(child.name);
+                    }
+                }
+
+                for child in names {
+                    dir.remove(&child, true)?;
+                }
+            }
+        }
+
+        self.delete(&slot)?;
+        self.vfat.borrow_mut().free_chain(slot.entry.cluster())
+    }
+
+    /// Moves the entry named `name` in `self` into the directory `to` under
+    /// the name `to_name`.
+    ///
+    /// # Errors
+    ///
+    /// If no entry named `name` exists, an error of `NotFound` is returned. If
+    /// an entry named `to_name` exists in `to`, an error of `AlreadyExists` is
+    /// returned. If a directory would be moved into itself, an error of
+    /// `InvalidInput` is returned.
+    pub fn rename(&self, name: &str, to: &Dir, to_name: &str) -> io::Result<()> {
+        if name == "." || name == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "reserved file name"));
+        }
+
+        let slot = self.find_slot(name)?;
+        let moved = slot.entry.cluster();
+        let same_dir = self.start_cluster == to.start_cluster;
+        let moves_dir = slot.entry.is_dir() && !same_dir;
+
+        if same_dir {
+            // A name differing only in case finds the entry itself, which
+            // `insert()` would take for an existing entry: rewrite it instead.
+            match self.find_slot(to_name) {
+                Ok(ref existing) if existing.index == slot.index => {
+                    let mut saved = self.raw_entries()?;
+                    saved.truncate(slot.index + 1);
+                    let saved = saved.split_off(slot.first);
+
+                    self.delete(&slot)?;
+                    if let Err(e) = self.insert(to_name, slot.entry) {
+                        self.write_raw_entries(slot.first, &saved)?;
+                        return Err(e);
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        if moves_dir {
+            // Refuse to move a directory into its own subtree.
+            let root = self.vfat.borrow().root_dir_cluster();
+            let mut current = to.start_cluster;
+            while current != root {
+                if current == moved {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "cannot move a directory into itself"));
+                }
+                let parent = Dir::new(current, self.vfat.clone()).find_slot("..")?;
+                current = match parent.entry.cluster().raw() {
+                    0 => root,
+                    _ => parent.entry.cluster(),
+                };
+            }
+        }
+
+        to.insert(to_name, slot.entry)?;
+        self.delete(&slot)?;
+
+        if moves_dir {
+            let dir = Dir::new(moved, self.vfat.clone());
+            let mut entries = dir.iter()?;
+            while let Some(mut dotdot) = entries.next_slot() {
+                if dotdot.name == ".." {
+                    dotdot.entry.set_cluster(to.dot_cluster());
+                    return dir.write_raw_entries(dotdot.index,
+                                                 &[VFatDirEntry::regular(dotdot.entry)]);
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
-impl Iterator for DirIterator {
-    type Item = Entry;
+impl DirIterator {
+    fn LFN_filename(LFN_entry: &mut Vec<&VFatLfnDirEntry>) -> String {
+        LFN_entry.sort_by_key(|a| a.sequence_number());
 
-    fn next(&mut self) -> Option<Self::Item> {
+        // LFN entry has 13 unicodes
+        let mut name_string: Vec<u16> = Vec::with_capacity(13 * LFN_entry.len());
+        for entry in LFN_entry.iter() {
+            entry.append_name(&mut name_string);
+        }
+
+        String::from_utf16_lossy(name_string.as_slice())
+    }
+
+    /// Advances to the next regular entry and returns the slot it occupies.
+    pub fn next_slot(&mut self) -> Option<Slot> {
         let mut LFN_entry: Vec<&VFatLfnDirEntry> = Vec::with_capacity(20);
+        let mut first = self.offset;
 
         for offset in self.offset..self.data.len() {
             let dir_entry = &self.data[offset];
@@ -253,21 +1101,157 @@ impl Iterator for DirIterator {
                 break;
             } 
             if unknown_entry.is_unused() {
+                LFN_entry.clear();
+                first = offset + 1;
                 continue;
             }
             if unknown_entry.is_LFN() {
                 // 长文件名目录项后面还会跟一个短文件名目录项，这个目录项记录了除文件名以外的这个文件的信息
                 LFN_entry.push( unsafe{ &dir_entry.long_filename } );
             } else {
+                let entry = unsafe { dir_entry.regular };
+                let name = if LFN_entry.is_empty() {
+                    entry.filename()
+                } else {
+                    DirIterator::LFN_filename(&mut LFN_entry)
+                };
+
                 self.offset = offset + 1;
-                return Some(self.create_entry(&mut LFN_entry,
-                                              unsafe { dir_entry.regular }));
+                return Some(Slot { first, index: offset, name, entry });
             }
         }
 
         self.offset = self.data.len();
         None
     }
+
+    pub fn create_entry(&self, slot: Slot) -> Entry {
+        let entry = slot.entry;
+        let metadata = entry.metadata();
+        let location = EntryLocation { dir: self.dir, index: slot.index };
+
+        if entry.is_dir() {
+            // `..` entries of first level directories refer to the root
+            // directory as cluster 0.
+            let cluster = match entry.cluster().raw() {
+                0 => self.vfat.borrow().root_dir_cluster(),
+                _ => entry.cluster(),
+            };
+            Entry::new_dir(slot.name, metadata, Dir::new(cluster, self.vfat.clone()),
+                           Some(location))
+        } else {
+            Entry::new_file(slot.name, metadata,
+                            File::new(entry.cluster(), self.vfat.clone(), entry.file_size, location))
+        }
+    }
+}
+
+/// An iterator over the deleted regular entries of a directory. Created by
+/// `Dir::deleted()`.
+pub struct DeletedEntries {
+    data: Vec<VFatDirEntry>,
+    offset: usize,
+}
+
+impl DeletedEntries {
+    /// Returns the deleted LFN entries immediately preceding `index`, first
+    /// part first, if they all share one checksum.
+    fn lfn_entries(&self, index: usize) -> Vec<VFatLfnDirEntry> {
+        let mut entries: Vec<VFatLfnDirEntry> = Vec::new();
+        for i in (index.saturating_sub(VFatLfnDirEntry::MAX_ENTRIES)..index).rev() {
+            let unknown_entry = unsafe { self.data[i].unknown };
+            if !unknown_entry.is_unused() || !unknown_entry.is_LFN() {
+                break;
+            }
+            let entry = unsafe { self.data[i].long_filename };
+            if entries.first().map_or(false, |first| first.checksum() != entry.checksum()) {
+                break;
+            }
+            entries.push(entry);
+        }
+        entries
+    }
+
+    /// Returns the byte that, as the first byte of `short_name`, gives the
+    /// checksum `checksum`, if it is a valid first byte of an 8.3 name. Every
+    /// checksum is matched by exactly one byte.
+    fn recover_first_byte(short_name: &[u8; 11], checksum: u8) -> Option<u8> {
+        let mut name = *short_name;
+        let byte = (0..256u16).map(|byte| byte as u8).find(|&byte| {
+            name[0] = byte;
+            name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c)) == checksum
+        })?;
+
+        let valid = byte == VFatRegularDirEntry::ESCAPED_E5 || byte >= 0x80
+            || (byte > 0x20 && byte != b'.' && !byte.is_ascii_lowercase()
+                && !INVALID_SHORT_NAME_CHARS.contains(&byte));
+        if valid && byte != 0xE5 { Some(byte) } else { None }
+    }
+}
+
+impl Iterator for DeletedEntries {
+    type Item = DeletedEntry;
+
+    fn next(&mut self) -> Option<DeletedEntry> {
+        while self.offset < self.data.len() {
+            let index = self.offset;
+            self.offset += 1;
+
+            let unknown_entry = unsafe { self.data[index].unknown };
+            if unknown_entry.is_end() {
+                self.offset = self.data.len();
+                break;
+            }
+            if !unknown_entry.is_unused() || unknown_entry.is_LFN() {
+                continue;
+            }
+            let mut entry = unsafe { self.data[index].regular };
+            if entry.attributes.volume_id() {
+                continue;
+            }
+
+            let mut lfn_entries = self.lfn_entries(index);
+            let first_byte = match lfn_entries.first() {
+                Some(lfn) => DeletedEntries::recover_first_byte(&entry.short_name(), lfn.checksum()),
+                None => None,
+            };
+            if first_byte.is_none() {
+                lfn_entries.clear();
+            }
+
+            entry.filename[0] = first_byte.unwrap_or(b'_');
+            let name = if lfn_entries.is_empty() {
+                entry.filename()
+            } else {
+                let mut units = Vec::new();
+                for lfn in lfn_entries.iter() {
+                    lfn.append_name(&mut units);
+                }
+                String::from_utf16_lossy(&units)
+            };
+
+            return Some(DeletedEntry {
+                index,
+                name,
+                cluster: entry.cluster(),
+                size: entry.file_size,
+                metadata: entry.metadata(),
+                short_name: entry.short_name(),
+                lfn_entries: lfn_entries.len(),
+            });
+        }
+
+        None
+    }
+}
+
+impl Iterator for DirIterator {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = self.next_slot()?;
+        Some(self.create_entry(slot))
+    }
 }
 
 
@@ -281,11 +1265,6 @@ impl traits::Dir for Dir {
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter> {
-        let mut data = Vec::new();
-        self.vfat.borrow_mut().read_chain(self.start_cluster, &mut data);
-
-        Ok(DirIterator { data: unsafe { data.cast() }, 
-                         offset: 0,
-                         vfat: self.vfat.clone() })
+        self.iter()
     }
 }
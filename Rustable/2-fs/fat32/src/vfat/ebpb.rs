@@ -73,13 +73,28 @@ impl BiosParameterBlock {
         Ok(bpb)
     }
 
+    /// Returns `true` if the BPB describes a FAT file system. Boot sectors of
+    /// other file systems, such as NTFS, carry the same signature.
+    pub fn is_fat(&self) -> bool {
+        let bytes_per_sector = self.num_bytes_per_sector;
+        let sectors_per_cluster = self.num_sectors_per_cluster;
+        bytes_per_sector >= 512 && bytes_per_sector.is_power_of_two()
+            && sectors_per_cluster != 0 && sectors_per_cluster.is_power_of_two()
+            && self.num_reserved_sectors != 0 && self.num_file_allocation_tables != 0
+            && self.sectors_per_fat() != 0
+    }
+
     pub fn bytes_per_sector(&self) -> u16 {
         self.num_bytes_per_sector
     }
 
-    /// Sectors per FAT.
+    /// Sectors per FAT. FAT12 and FAT16 volumes store it in the BPB, FAT32
+    /// volumes in the extended BPB.
     pub fn sectors_per_fat(&self) -> u32 {
-        self.sectors_per_fat
+        match self.num_sectors_per_fat {
+            0 => self.sectors_per_fat,
+            n => n as u32
+        }
     }
 
     /// Sectors per cluster.
@@ -97,13 +112,66 @@ impl BiosParameterBlock {
         self.num_file_allocation_tables
     }
 
+    /// Returns `true` if every FAT is kept up to date. Otherwise only FAT
+    /// number `active_fat()` is in use.
+    pub fn fats_mirrored(&self) -> bool {
+        self.flags[0] & 0x80 == 0
+    }
+
+    /// The 0-indexed FAT in use when FATs are not mirrored.
+    pub fn active_fat(&self) -> u8 {
+        self.flags[0] & 0x0F
+    }
+
+    /// The sector offset, from the start of the partition, to the FSInfo
+    /// sector, or `None` if the volume has no FSInfo sector.
+    pub fn fsinfo_sector(&self) -> Option<u64> {
+        let sector = &self.sector_num_FSInfo;
+        match sector[0] as u64 | (sector[1] as u64) << 8 {
+            0 | 0xFFFF => None,
+            n => Some(n)
+        }
+    }
+
+    /// The number of entries in the fixed-size root directory of a FAT12 or
+    /// FAT16 volume. Always 0 on FAT32 volumes.
+    pub fn root_entries(&self) -> u64 {
+        let entries = &self.max_num_directory_entries;
+        entries[0] as u64 | (entries[1] as u64) << 8
+    }
+
+    /// The sector offset, from the start of the partition, to the fixed-size
+    /// root directory of a FAT12 or FAT16 volume, which follows the FATs.
+    pub fn root_dir_start_sector(&self) -> u64 {
+        self.fat_start_sector() + self.sectors_per_fat() as u64 * self.fat_count() as u64
+    }
+
+    /// The number of sectors in the fixed-size root directory of a FAT12 or
+    /// FAT16 volume.
+    pub fn root_dir_sectors(&self) -> u64 {
+        let bytes_per_sector = self.bytes_per_sector() as u64;
+        (self.root_entries() * 32 + bytes_per_sector - 1) / bytes_per_sector
+    }
+
     /// The sector offset, from the start of the partition, to the first data
     /// sector.
     pub fn data_start_sector(&self) -> u64 {
-        self.fat_start_sector() + self.sectors_per_fat() as u64 * self.fat_count() as u64
+        self.root_dir_start_sector() + self.root_dir_sectors()
+    }
+
+    /// Total number of logical sectors in the partition.
+    pub fn total_sectors(&self) -> u64 {
+        let small = &self.total_logical_sections;
+        let large = &self.total_logical_sectors;
+        match small[0] as u64 | (small[1] as u64) << 8 {
+            0 => large[0] as u64 | (large[1] as u64) << 8
+                | (large[2] as u64) << 16 | (large[3] as u64) << 24,
+            n => n
+        }
     }
 
-    /// Root dir cluster.
+    /// Root dir cluster. Only FAT32 volumes store the root directory in a
+    /// cluster chain.
     pub fn root_cluster(&self) -> u32 {
         self.cluster_num_root_dir
     }
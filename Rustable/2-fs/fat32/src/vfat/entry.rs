@@ -1,5 +1,8 @@
+use std::io;
+
 use traits;
-use vfat::{File, Dir, Metadata, Cluster};
+use vfat::{File, Dir, Metadata, Shared, VFat};
+use vfat::dir::EntryLocation;
 
 // TODO: You may need to change this definition.
 #[derive(Debug)]
@@ -13,16 +16,28 @@ pub struct Entry {
     item: EntryData,
     name: String,
     metadata: Metadata,
+    /// The position of the entry in its parent directory, or `None` for the
+    /// root directory.
+    location: Option<EntryLocation>,
 }
 
 // TODO: Implement any useful helper methods on `Entry`.
 impl Entry {
     pub fn new_file(name: String, metadata: Metadata, file: File) -> Entry {
-        Entry { item: EntryData::File(file), name, metadata }
+        let location = Some(file.location());
+        Entry { item: EntryData::File(file), name, metadata, location }
+    }
+
+    pub fn new_dir(name: String, metadata: Metadata, dir: Dir,
+                   location: Option<EntryLocation>) -> Entry {
+        Entry { item: EntryData::Dir(dir), name, metadata, location }
     }
 
-    pub fn new_dir(name: String, metadata: Metadata, dir: Dir) -> Entry {
-        Entry { item: EntryData::Dir(dir), name, metadata }
+    fn vfat(&self) -> &Shared<VFat> {
+        match self.item {
+            EntryData::File(ref file) => file.vfat(),
+            EntryData::Dir(ref dir) => dir.vfat(),
+        }
     }
 }
 
@@ -42,6 +57,33 @@ impl traits::Entry for Entry {
         &self.metadata
     }
 
+    /// Writes the settable attributes and the timestamps of `metadata` to the
+    /// entry's directory entry. The size is always taken from disk, since the
+    /// file may have changed since `self` was read.
+    ///
+    /// The root directory and the `.` and `..` entries, which only refer to a
+    /// directory whose own entry is elsewhere, are rejected with an error of
+    /// `InvalidInput`.
+    fn set_metadata(&mut self, metadata: Self::Metadata) -> io::Result<()> {
+        if self.name == "." || self.name == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "dot entries have no metadata of their own"));
+        }
+        let location = self.location.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "the root directory has no metadata")
+        })?;
+
+        let entry = {
+            let mut vfat = self.vfat().borrow_mut();
+            let mut entry = location.read(&mut vfat)?;
+            entry.set_metadata(&metadata);
+            location.write(&mut vfat, entry)?;
+            entry
+        };
+        self.metadata = entry.metadata();
+        Ok(())
+    }
+
     /// If `self` is a file, returns `Some` of a reference to the file.
     /// Otherwise returns `None`.
     fn as_file(&self) -> Option<&Self::File> {
@@ -1,10 +1,13 @@
 use std::io;
 
 use mbr;
+use gpt;
+use partition;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
     NotFound
@@ -16,6 +19,21 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+impl From<partition::Error> for Error {
+    fn from(error: partition::Error) -> Error {
+        match error {
+            partition::Error::Mbr(error) => Error::Mbr(error),
+            partition::Error::Gpt(error) => Error::Gpt(error),
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
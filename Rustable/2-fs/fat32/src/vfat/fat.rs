@@ -3,7 +3,7 @@ use vfat::*;
 
 use self::Status::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Status {
     /// The FAT entry corresponds to an unused (free) cluster.
     Free,
@@ -19,10 +19,92 @@ pub enum Status {
     Eoc(u32)
 }
 
-#[repr(C, packed)]
+/// The width of the entries of a FAT, which is determined by the number of
+/// clusters on the volume.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Determines the FAT type of a volume with `clusters` data clusters, as
+    /// the FAT specification requires.
+    pub fn from_cluster_count(clusters: u32) -> FatType {
+        if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// The byte offset of the entry of `cluster` within a FAT.
+    pub fn entry_offset(&self, cluster: Cluster) -> usize {
+        let index = cluster.fat_index();
+        match *self {
+            FatType::Fat12 => index + index / 2,
+            FatType::Fat16 => index * 2,
+            FatType::Fat32 => index * 4,
+        }
+    }
+
+    /// The number of entries that fit in a FAT of `bytes` bytes.
+    pub fn entries(&self, bytes: u64) -> u64 {
+        match *self {
+            FatType::Fat12 => bytes * 2 / 3,
+            FatType::Fat16 => bytes / 2,
+            FatType::Fat32 => bytes / 4,
+        }
+    }
+
+    /// The bit of FAT[1] that is set while the volume is not mounted or has
+    /// no unwritten changes. FAT12 has no such bit.
+    pub fn clean_shutdown_bit(&self) -> Option<u32> {
+        match *self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(0x8000),
+            FatType::Fat32 => Some(0x08000000),
+        }
+    }
+
+    /// The bit of FAT[1] that is cleared when a disk I/O error was
+    /// encountered. FAT12 has no such bit.
+    pub fn no_hard_error_bit(&self) -> Option<u32> {
+        match *self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(0x4000),
+            FatType::Fat32 => Some(0x04000000),
+        }
+    }
+}
+
+/// A FAT entry. FAT12 and FAT16 entries are held as the equivalent FAT32
+/// entry, so that reserved, bad and end of chain markers decode the same.
 pub struct FatEntry(pub u32);
 
 impl FatEntry {
+    /// Converts the on-disk value `raw` of an entry of a FAT of type
+    /// `fat_type`.
+    pub fn from_raw(fat_type: FatType, raw: u32) -> FatEntry {
+        match fat_type {
+            FatType::Fat12 if raw >= 0xFF0 => FatEntry(raw | 0x0FFFF000),
+            FatType::Fat16 if raw >= 0xFFF0 => FatEntry(raw | 0x0FFF0000),
+            _ => FatEntry(raw),
+        }
+    }
+
+    /// The on-disk value of the entry in a FAT of type `fat_type`.
+    pub fn raw(&self, fat_type: FatType) -> u32 {
+        match fat_type {
+            FatType::Fat12 => self.0 & 0xFFF,
+            FatType::Fat16 => self.0 & 0xFFFF,
+            FatType::Fat32 => self.0,
+        }
+    }
+
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
         match !( 0xF << 28 ) & self.0 {
@@ -35,6 +117,19 @@ impl FatEntry {
             _ => { unreachable!(); }
         } 
     }
+
+    /// Sets the entry `self` to `status`. The upper four reserved bits of the
+    /// entry are preserved.
+    pub fn set(&mut self, status: Status) {
+        let value = match status {
+            Status::Free => 0x00000000,
+            Status::Reserved => 0x0FFFFFF0,
+            Status::Data(next) => next.raw(),
+            Status::Bad => 0x0FFFFFF7,
+            Status::Eoc(_) => 0x0FFFFFFF,
+        };
+        self.0 = (self.0 & (0xF << 28)) | value;
+    }
 }
 
 impl fmt::Debug for FatEntry {
@@ -1,8 +1,9 @@
-use std::cmp::{min, max};
+use std::cmp::min;
 use std::io::{self, SeekFrom};
 
 use traits;
 use vfat::{VFat, Shared, Cluster, Metadata};
+use vfat::dir::EntryLocation;
 
 #[derive(Debug)]
 pub struct File {
@@ -13,28 +14,167 @@ pub struct File {
 
     pointer: u64,
 
-    cluster_current: Cluster,
-    cluster_current_start: usize,
+    /// The index in the chain and number of the last cluster looked up, from
+    /// which lookups further into the chain continue.
+    cursor: (usize, Cluster),
+    /// The whole cluster chain, once `cache_chain()` has been called.
+    chain: Option<Vec<Cluster>>,
+
+    location: EntryLocation,
 }
 
 impl File {
-    pub fn new(start_cluster: Cluster, vfat: Shared<VFat>, size: u32)
-        -> File
+    pub fn new(start_cluster: Cluster, vfat: Shared<VFat>, size: u32,
+               location: EntryLocation) -> File
     {
         File { start_cluster, vfat, size, 
                pointer: 0, 
-               cluster_current: start_cluster,
-               cluster_current_start: 0 }
+               cursor: (0, start_cluster),
+               chain: None,
+               location }
     }
 
-    fn set_pointer(&mut self, pointer: u64) -> io::Result<u64> {
-        self.pointer = pointer;
+    /// Reads the file's whole cluster chain into memory, so that seeking
+    /// anywhere in the file no longer walks the FAT. The cached chain is kept
+    /// up to date as the file grows or shrinks through this handle.
+    pub fn cache_chain(&mut self) -> io::Result<()> {
+        let mut chain = Vec::new();
+        let mut cluster = self.start_cluster;
+        if cluster.is_valid() {
+            let mut vfat = self.vfat.borrow_mut();
+            chain.push(cluster);
+            while let Some(next) = vfat.next_cluster(cluster)? {
+                chain.push(next);
+                cluster = next;
+            }
+        }
+        self.chain = Some(chain);
+        Ok(())
+    }
+
+    /// Returns the `index`th (0-indexed) cluster of the file's chain, or
+    /// `None` if the chain is shorter than that. Without a cached chain, the
+    /// FAT is walked from the last cluster looked up if `index` is not before
+    /// it, so sequential access costs a single FAT lookup per cluster.
+    fn cluster_at(&mut self, index: usize) -> io::Result<Option<Cluster>> {
+        if let Some(ref chain) = self.chain {
+            return Ok(chain.get(index).cloned());
+        }
+        if !self.start_cluster.is_valid() {
+            return Ok(None);
+        }
+
+        let (mut i, mut cluster) = if index >= self.cursor.0 {
+            self.cursor
+        } else {
+            (0, self.start_cluster)
+        };
+        let mut vfat = self.vfat.borrow_mut();
+        while i < index {
+            cluster = match vfat.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+            i += 1;
+            self.cursor = (i, cluster);
+        }
+        Ok(Some(cluster))
+    }
+
+    /// Grows the cluster chain to hold `len` bytes, which must be more than
+    /// the file's size. The chain is extended from the last cluster looked up
+    /// rather than from its start when possible.
+    fn extend_chain(&mut self, len: usize) -> io::Result<()> {
+        let cluster_size = self.vfat.borrow().cluster_size();
+        let last = (len - 1) / cluster_size;
+        if !self.start_cluster.is_valid() {
+            self.start_cluster = self.vfat.borrow_mut().resize_chain(self.start_cluster, len)?;
+            self.cursor = (0, self.start_cluster);
+            if self.chain.is_some() {
+                self.cache_chain()?;
+            }
+            return Ok(());
+        }
+
+        let (index, cluster) = match self.chain {
+            Some(ref chain) => {
+                let index = min(last, chain.len() - 1);
+                (index, chain[index])
+            }
+            None if self.cursor.0 <= last => self.cursor,
+            None => (0, self.start_cluster),
+        };
+
+        let mut vfat = self.vfat.borrow_mut();
+        vfat.resize_chain(cluster, len - index * cluster_size)?;
+        if let Some(ref mut chain) = self.chain {
+            chain.truncate(index + 1);
+            let mut cluster = cluster;
+            while let Some(next) = vfat.next_cluster(cluster)? {
+                chain.push(next);
+                cluster = next;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn vfat(&self) -> &Shared<VFat> {
+        &self.vfat
+    }
+
+    /// The position of the file's directory entry.
+    pub(crate) fn location(&self) -> EntryLocation {
+        self.location
+    }
+
+    /// Writes the file's start cluster and size back to its directory entry
+    /// and stamps it as modified now.
+    fn update_entry(&mut self) -> io::Result<()> {
+        let mut vfat = self.vfat.borrow_mut();
+        let mut entry = self.location.read(&mut vfat)?;
+        entry.set_cluster(self.start_cluster);
+        entry.set_file_size(self.size);
+        entry.set_modified(vfat.now());
+        self.location.write(&mut vfat, entry)
+    }
+
+    /// Truncates or extends the file to `len` bytes. Extended regions read as
+    /// zeroes. The file pointer is moved to `len` if it was beyond it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `len` does not fit in a FAT32
+    /// file size.
+    pub fn set_len(&mut self, len: u64) -> io::Result<()> {
+        if len > ::std::u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "file too large"));
+        }
+
+        let old_size = self.size as usize;
+        self.start_cluster = self.vfat.borrow_mut().resize_chain(
+            self.start_cluster, len as usize)?;
+        self.size = len as u32;
+        self.cursor = (0, self.start_cluster);
+        if self.chain.is_some() {
+            self.cache_chain()?;
+        }
+
+        if len as usize > old_size {
+            let zeroes = vec![0u8; len as usize - old_size];
+            self.vfat.borrow_mut().write_chain_at(self.start_cluster, old_size, &zeroes)?;
+        }
 
-        let (cluster, cluster_start) = self.vfat.borrow_mut().find_sector(
-            self.start_cluster, self.pointer as usize)?;
-        self.cluster_current = cluster;
-        self.cluster_current_start = cluster_start;
+        self.update_entry()?;
+        let pointer = min(self.pointer, len);
+        self.set_pointer(pointer)?;
+        Ok(())
+    }
 
+    /// Moves the file pointer. Clusters are only looked up when the file is
+    /// next read or written.
+    fn set_pointer(&mut self, pointer: u64) -> io::Result<u64> {
+        self.pointer = pointer;
         Ok(self.pointer)
     }
 }
@@ -43,27 +183,36 @@ impl File {
 impl traits::File for File {
     /// Writes any buffered data to disk.
     fn sync(&mut self) -> io::Result<()> {
-        // No-ops, this is a write-only-to-memory filesystem.
-        Ok(())
+        self.vfat.borrow_mut().sync()
     }
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64 {
         self.size as u64
     }
+
+    /// Truncates or extends the file to `len` bytes.
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
 }
 
 impl io::Read for File {
     /// Read from the file into a buffer.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut bytes_read: usize = 0;
+        let cluster_size = self.vfat.borrow().cluster_size();
         let max_read = min(self.size as usize - self.pointer as usize,
                            buf.len());
 
-        while self.pointer < self.size as u64 {
+        let mut bytes_read: usize = 0;
+        while bytes_read < max_read {
+            let pointer = self.pointer as usize;
+            let cluster = self.cluster_at(pointer / cluster_size)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Data does not match size")
+            })?;
             let bytes = self.vfat.borrow_mut().read_cluster(
-                self.cluster_current,
-                self.pointer as usize - self.cluster_current_start,
+                cluster,
+                pointer % cluster_size,
                 &mut buf[bytes_read..max_read])?;
             if bytes == 0 {
                 break;
@@ -71,12 +220,6 @@ impl io::Read for File {
 
             bytes_read += bytes;
             self.pointer += bytes as u64;
-
-            let (cluster, offset) = self.vfat.borrow_mut().find_sector(
-                self.cluster_current,
-                self.pointer as usize - self.cluster_current_start)?;
-            self.cluster_current = cluster;
-            self.cluster_current_start += offset;
         }
 
         Ok(bytes_read)
@@ -84,15 +227,53 @@ impl io::Read for File {
 }
 
 impl io::Write for File {
-    /// Write into the file.
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("write")
+    /// Write into the file at the current position, extending the file and
+    /// its cluster chain as needed.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = self.pointer + buf.len() as u64;
+        if end > ::std::u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "file too large"));
+        }
+
+        if end > self.size as u64 {
+            self.extend_chain(end as usize)?;
+        }
+
+        let cluster_size = self.vfat.borrow().cluster_size();
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            let pointer = self.pointer as usize;
+            let cluster = self.cluster_at(pointer / cluster_size)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "cluster chain too short")
+            })?;
+            let bytes = self.vfat.borrow_mut().write_cluster(
+                cluster,
+                pointer % cluster_size,
+                &buf[bytes_written..])?;
+
+            bytes_written += bytes;
+            self.pointer += bytes as u64;
+        }
+
+        if end > self.size as u64 {
+            self.size = end as u32;
+        }
+        if bytes_written > 0 {
+            self.update_entry()?;
+        }
+
+        Ok(bytes_written)
     }
 
-    /// Flush the file changes to disk, not implemented for in-memory
-    /// cached filesystem.
+    /// Flush the file changes to disk.
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        use traits::File;
+        self.sync()
     }
 }
 
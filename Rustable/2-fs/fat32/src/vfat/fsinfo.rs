@@ -0,0 +1,88 @@
+use std::{fmt, mem};
+
+use traits::BlockDevice;
+use vfat::Error;
+
+/// The FAT32 FSInfo sector, which records the number of free clusters and a
+/// hint for where to start looking for a free cluster.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct FsInfo {
+    lead_signature: u32,
+    _reserved_1: [u8; 480],
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    _reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FsInfo {
+    const LEAD_SIGNATURE: u32 = 0x41615252;
+    const STRUCT_SIGNATURE: u32 = 0x61417272;
+    const TRAIL_SIGNATURE: u32 = 0xAA550000;
+    /// Stored in place of a free count or hint that is not known.
+    const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    /// The byte offset of the free cluster count within the sector.
+    pub const FREE_COUNT_OFFSET: usize = 488;
+    /// The byte offset of the next free cluster hint within the sector.
+    pub const NEXT_FREE_OFFSET: usize = 492;
+
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut buf = [0u8; 512];
+        device.read_sector(sector, &mut buf)?;
+
+        let fsinfo: FsInfo = unsafe { mem::transmute(buf) };
+        if fsinfo.lead_signature != FsInfo::LEAD_SIGNATURE
+            || fsinfo.struct_signature != FsInfo::STRUCT_SIGNATURE
+            || fsinfo.trail_signature != FsInfo::TRAIL_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(fsinfo)
+    }
+
+    /// The last known number of free clusters, if any.
+    pub fn free_count(&self) -> Option<u32> {
+        match self.free_count {
+            FsInfo::UNKNOWN => None,
+            n => Some(n)
+        }
+    }
+
+    /// The cluster number at which to start looking for a free cluster, if
+    /// any.
+    pub fn next_free(&self) -> Option<u32> {
+        match self.next_free {
+            FsInfo::UNKNOWN => None,
+            n => Some(n)
+        }
+    }
+
+    /// Writes `free_count` and `next_free` into the FSInfo sector `buf`.
+    pub fn update(buf: &mut [u8], free_count: Option<u32>, next_free: Option<u32>) {
+        let fields = [(FsInfo::FREE_COUNT_OFFSET, free_count.unwrap_or(FsInfo::UNKNOWN)),
+                      (FsInfo::NEXT_FREE_OFFSET, next_free.unwrap_or(FsInfo::UNKNOWN))];
+        for &(offset, value) in fields.iter() {
+            for i in 0..4 {
+                buf[offset + i] = (value >> (8 * i)) as u8;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &self.free_count())
+            .field("next_free", &self.next_free())
+            .finish()
+    }
+}
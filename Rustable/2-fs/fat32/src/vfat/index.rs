@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use vfat::Cluster;
+use vfat::case::NameKey;
+
+/// Lookup indexes for the names in large directories, keyed by the first
+/// cluster of each directory. Each index maps the case folded long name and
+/// the 8.3 name of every entry to the index of its regular entry, and holds
+/// the directory's cluster chain so that entries can be read without walking
+/// the FAT.
+///
+/// Lookups through an index must be verified against the directory, and the
+/// index of a directory must be dropped whenever names in it change.
+#[derive(Debug, Default)]
+pub struct DirIndexes {
+    indexes: HashMap<Cluster, DirIndex>,
+}
+
+#[derive(Debug)]
+struct DirIndex {
+    clusters: Vec<Cluster>,
+    names: HashMap<String, usize>,
+}
+
+impl DirIndexes {
+    /// Directories with fewer entries are scanned instead of indexed.
+    pub const MIN_ENTRIES: usize = 32;
+    /// The most directories indexed at once. All indexes are dropped when a
+    /// directory beyond this is indexed.
+    const MAX_DIRS: usize = 16;
+
+    /// Returns `None` if `dir` is not indexed, otherwise `Some` of the index
+    /// of the regular entry matching `key`, if any, and the clusters of `dir`.
+    pub fn lookup(&self, dir: Cluster, key: &NameKey) -> Option<(Option<usize>, &[Cluster])> {
+        let index = self.indexes.get(&dir)?;
+        let found = index.names.get(&key.folded).or_else(|| index.names.get(&key.upper));
+        Some((found.cloned(), &index.clusters))
+    }
+
+    /// Stores `names` as the index of `dir`, whose cluster chain is
+    /// `clusters`.
+    pub fn insert(&mut self, dir: Cluster, clusters: Vec<Cluster>, names: HashMap<String, usize>) {
+        if self.indexes.len() >= DirIndexes::MAX_DIRS && !self.indexes.contains_key(&dir) {
+            self.indexes.clear();
+        }
+        self.indexes.insert(dir, DirIndex { clusters, names });
+    }
+
+    /// Drops the index of `dir`, if any.
+    pub fn invalidate(&mut self, dir: Cluster) {
+        self.indexes.remove(&dir);
+    }
+}
@@ -8,6 +8,26 @@ use traits;
 pub struct Date(u16);
 
 impl Date {
+    /// Returns the date `year`-`month`-`day`, or `None` if it does not exist
+    /// or cannot be represented: FAT dates range from 1980 to 2107.
+    pub fn new(year: usize, month: u8, day: u8) -> Option<Date> {
+        if year < 1980 || year > 2107 || month < 1 || month > 12 || day < 1
+            || day > Date::days_in_month(year, month) {
+            return None;
+        }
+        Some(Date(((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16))
+    }
+
+    /// The number of days in `month` of `year`.
+    fn days_in_month(year: usize, month: u8) -> u8 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
     pub fn year(&self) -> usize {
         (self.0 >> 9) as usize + 1980
     }
@@ -27,6 +47,16 @@ impl Date {
 pub struct Time(u16);
 
 impl Time {
+    /// Returns the time `hour`:`minute`:`second`, or `None` if it is out of
+    /// range. FAT times have a resolution of two seconds, so odd seconds are
+    /// rounded down.
+    pub fn new(hour: u8, minute: u8, second: u8) -> Option<Time> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        Some(Time((hour as u16) << 11 | (minute as u16) << 5 | (second / 2) as u16))
+    }
+
     pub fn hour(&self) -> u8 {
         (self.0 >> 11) as u8
     }
@@ -55,10 +85,11 @@ pub struct Timestamp {
 /// Metadata for a directory entry.
 #[derive(Default, Debug, Clone)]
 pub struct Metadata {
-    attributes: Attributes,
-    created: Timestamp,
-    accessed: Timestamp,
-    modified: Timestamp,
+    pub(crate) attributes: Attributes,
+    pub(crate) created: Timestamp,
+    pub(crate) accessed: Timestamp,
+    pub(crate) modified: Timestamp,
+    size: u32,
 }
 
 impl Attributes {
@@ -68,6 +99,20 @@ impl Attributes {
     const VOLUME_ID: u8 = 0x08;
     const DIRECTORY: u8 = 0x10;
     const ARCHIVE: u8 = 0x20;
+    /// The attributes that can be changed through `Metadata`. The others
+    /// determine what kind of entry it is.
+    const SETTABLE: u8 = Attributes::READ_ONLY | Attributes::HIDDEN | Attributes::SYSTEM
+        | Attributes::ARCHIVE;
+
+    /// The attributes of a newly created regular file.
+    pub fn file() -> Attributes {
+        Attributes(Attributes::ARCHIVE)
+    }
+
+    /// The attributes of a newly created directory.
+    pub fn dir() -> Attributes {
+        Attributes(Attributes::DIRECTORY)
+    }
 
     pub fn read_only(&self) -> bool {
         (self.0 & Attributes::READ_ONLY) != 0
@@ -92,6 +137,20 @@ impl Attributes {
     pub fn archive(&self) -> bool {
         (self.0 & Attributes::ARCHIVE) != 0
     }
+
+    fn set(&mut self, attribute: u8, value: bool) {
+        if value {
+            self.0 |= attribute;
+        } else {
+            self.0 &= !attribute;
+        }
+    }
+
+    /// Returns `self` with the attributes that can be changed through
+    /// `Metadata` replaced by those of `other`.
+    pub fn with_settable(&self, other: Attributes) -> Attributes {
+        Attributes((self.0 & !Attributes::SETTABLE) | (other.0 & Attributes::SETTABLE))
+    }
 }
 
 impl fmt::Display for Attributes {
@@ -118,6 +177,19 @@ impl fmt::Display for Attributes {
     }
 }
 
+impl Timestamp {
+    /// The earliest point in time FAT can represent: 1980-01-01 00:00:00.
+    pub const EPOCH: Timestamp = Timestamp { time: Time(0), date: Date(1 << 5 | 1) };
+
+    /// Returns the given point in time, or `None` if either the date or the
+    /// time cannot be represented. See `Date::new()` and `Time::new()`.
+    pub fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8)
+        -> Option<Timestamp>
+    {
+        Some(Timestamp { date: Date::new(year, month, day)?, time: Time::new(hour, minute, second)? })
+    }
+}
+
 impl traits::Timestamp for Timestamp {
     /// The calendar year.
     ///
@@ -152,6 +224,13 @@ impl traits::Timestamp for Timestamp {
     fn second(&self) -> u8 {
         self.time.second()
     }
+
+    /// See `Timestamp::new()`.
+    fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8)
+        -> Option<Timestamp>
+    {
+        Timestamp::new(year, month, day, hour, minute, second)
+    }
 }
 
 impl fmt::Display for Timestamp {
@@ -164,8 +243,17 @@ impl fmt::Display for Timestamp {
 
 impl Metadata {
     pub fn new(attributes: Attributes, created: Timestamp, accessed: Timestamp,
-               modified: Timestamp) -> Metadata {
-        Metadata { attributes, created, accessed, modified }
+               modified: Timestamp, size: u32) -> Metadata {
+        Metadata { attributes, created, accessed, modified, size }
+    }
+
+    /// The metadata of the root directory, which has no entry of its own.
+    pub fn root() -> Metadata {
+        Metadata { attributes: Attributes::dir(), ..Metadata::default() }
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
     }
 }
 
@@ -182,6 +270,31 @@ impl traits::Metadata for Metadata {
         self.attributes.hidden()
     }
 
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool {
+        self.attributes.system()
+    }
+
+    /// Whether the entry holds the volume label rather than a file.
+    fn volume_id(&self) -> bool {
+        self.attributes.volume_id()
+    }
+
+    /// Whether the entry is a directory.
+    fn directory(&self) -> bool {
+        self.attributes.directory()
+    }
+
+    /// Whether the entry changed since it was last archived.
+    fn archive(&self) -> bool {
+        self.attributes.archive()
+    }
+
+    /// The size of the entry's file in bytes. Always 0 for directories.
+    fn size(&self) -> u64 {
+        self.size as u64
+    }
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp {
         self.created
@@ -196,11 +309,42 @@ impl traits::Metadata for Metadata {
     fn modified(&self) -> Self::Timestamp {
         self.modified
     }
+
+    fn set_read_only(&mut self, read_only: bool) {
+        self.attributes.set(Attributes::READ_ONLY, read_only)
+    }
+
+    fn set_hidden(&mut self, hidden: bool) {
+        self.attributes.set(Attributes::HIDDEN, hidden)
+    }
+
+    fn set_system(&mut self, system: bool) {
+        self.attributes.set(Attributes::SYSTEM, system)
+    }
+
+    fn set_archive(&mut self, archive: bool) {
+        self.attributes.set(Attributes::ARCHIVE, archive)
+    }
+
+    /// Sets the creation timestamp.
+    fn set_created(&mut self, timestamp: Self::Timestamp) {
+        self.created = timestamp
+    }
+
+    /// Sets the last access timestamp. Only its date is stored on disk.
+    fn set_accessed(&mut self, timestamp: Self::Timestamp) {
+        self.accessed = Timestamp { time: Time::default(), date: timestamp.date }
+    }
+
+    /// Sets the last modification timestamp.
+    fn set_modified(&mut self, timestamp: Self::Timestamp) {
+        self.modified = timestamp
+    }
 }
 
 impl fmt::Display for Metadata {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "attributes={} created={} accessed={} modified={}",
-               self.attributes, self.created, self.accessed, self.modified)
+        write!(f, "attributes={} size={} created={} accessed={} modified={}",
+               self.attributes, self.size, self.created, self.accessed, self.modified)
     }
 }
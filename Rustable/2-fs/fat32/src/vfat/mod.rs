@@ -9,15 +9,22 @@ pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
 pub(crate) mod shared;
+pub(crate) mod cp437;
+pub(crate) mod fsinfo;
+pub mod case;
+pub(crate) mod index;
 
 pub use self::ebpb::BiosParameterBlock;
+pub use self::fsinfo::FsInfo;
 pub use self::file::File;
-pub use self::dir::Dir;
+pub use self::dir::{Dir, DeletedEntry, DeletedEntries};
 pub use self::error::Error;
 pub use self::vfat::VFat;
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::cache::CacheStats;
+pub use self::fat::FatType;
 
 pub(crate) use self::cache::{CachedDevice, Partition};
 pub(crate) use self::fat::{Status, FatEntry};
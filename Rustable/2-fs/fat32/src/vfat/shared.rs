@@ -1,5 +1,8 @@
+use std::io;
 use std::ops::{Deref, DerefMut};
 
+use traits::BlockDevice;
+
 /// A smart pointer to a shared instance of type `T`.
 ///
 /// The inner `T` can be borrowed immutably with `.borrow()` and mutably with
@@ -70,3 +73,27 @@ impl<T> Clone for Shared<T> {
         Shared(self.0.clone())
     }
 }
+
+/// A shared block device, so that views of several of its partitions can be
+/// mounted side by side.
+impl<T: BlockDevice> BlockDevice for Shared<T> {
+    fn sector_size(&self) -> u64 {
+        self.borrow().sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.borrow_mut().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.borrow_mut().write_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.borrow_mut().read_sectors(n, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.borrow_mut().write_sectors(n, buf)
+    }
+}
@@ -1,12 +1,11 @@
 use std::io;
 use std::path::Path;
-use std::mem::size_of;
 use std::cmp::min;
 
-use util::SliceExt;
-use mbr::MasterBootRecord;
-use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use partition::{partitions, PartitionDevice};
+use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, FatType, Error, Status, Timestamp};
+use vfat::{BiosParameterBlock, CachedDevice, CacheStats, Partition, FsInfo};
+use vfat::index::DirIndexes;
 use traits::{FileSystem, BlockDevice};
 
 #[derive(Debug)]
@@ -18,50 +17,153 @@ pub struct VFat {
     fat_start_sector: u64,
     data_start_sector: u64,
     root_dir_cluster: Cluster,
+    root_dir_sector: u64,
+    root_dir_sectors: u64,
+    cluster_count: u32,
+    fat_type: FatType,
+    fat_count: u8,
+    mirror_fats: bool,
+    active_fat: u8,
+    fsinfo_sector: Option<u64>,
+    free_clusters: Option<u32>,
+    next_free: u32,
+    mounted_dirty: bool,
+    hard_error: bool,
+    dirty: bool,
+    dir_indexes: DirIndexes,
+    clock: fn() -> Timestamp,
 }
 
 impl VFat {
+    /// Mounts the first partition of `device` that holds a FAT file system.
     pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
-        let mbr = MasterBootRecord::from(&mut device)?;
-        
+        // GPT basic data partitions may as well hold NTFS, so skip partitions
+        // whose boot sector does not describe a FAT file system.
+        let mut error = Error::Io(io::Error::new(io::ErrorKind::NotFound, "vfat not found"));
+        for info in partitions(&mut device)? {
+            if !info.may_be_fat() {
+                continue;
+            }
+            match BiosParameterBlock::from(&mut device, info.start) {
+                Ok(ref ebpb) if ebpb.is_fat() => {
+                    return VFat::mount(PartitionDevice::new(device, info), ebpb);
+                }
+                Ok(_) => error = Error::BadSignature,
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
 
-        //find the first FAT
-        for i in 0..4 {
-            match mbr.partition_table[i].partition_type {
-                0x0B | 0x0C => {  
-                    // let bpb = match BiosParameterBlock::from(&mut device, mbr.partition_table[i].relative_sector as u64) {
-                    //     Ok( bpb ) => { bpb },
-                    //     Err( e ) => { return Err( e )}
-                    // };
-                    let ebpb = BiosParameterBlock::from(&mut device, mbr.partition_table[i].relative_sector as u64)?;
-                    // if bpb.num_bytes_per_sector == 0 {
-                    //     return Err( Error::Io( io::Error::new( io::ErrorKind::Other, "logic sector size invalid" ) ) )
-                    // }
-
-                    let partition_start = mbr.partition_table[i].relative_sector as u64;
-
-                    let bytes_per_sector = ebpb.bytes_per_sector();
-
-                    let cache = CachedDevice::new(device, Partition { start: partition_start,
-                                                                      sector_size: bytes_per_sector as u64 });
-                    let vfat = VFat {
-                        device: cache,
-                        bytes_per_sector,
-                        sectors_per_cluster: ebpb.sectors_per_cluster(),
-                        sectors_per_fat: ebpb.sectors_per_fat(),
-                        fat_start_sector: partition_start + ebpb.fat_start_sector(),
-                        data_start_sector: partition_start + ebpb.data_start_sector(),
-                        root_dir_cluster: Cluster::from(ebpb.root_cluster()),
-                    };
-                    return Ok( Shared::new( vfat ) )
+    /// Mounts the FAT file system in entry `index` of the MBR partition table
+    /// or GPT partition array of `device`, whatever its partition type.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if entry `index` is unused and `BadSignature` if the
+    /// partition does not hold a FAT file system.
+    pub fn from_partition<T>(device: T, index: usize) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let mut partition = PartitionDevice::open(device, index)?.ok_or(Error::NotFound)?;
+        let ebpb = BiosParameterBlock::from(&mut partition, 0)?;
+        if !ebpb.is_fat() {
+            return Err(Error::BadSignature);
+        }
+        VFat::mount(partition, &ebpb)
+    }
 
-                },
-                _ => {}
-            };
+    /// Mounts the FAT file system that starts at sector 0 of `device`, which
+    /// has no partition table, such as an image of a single partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if sector 0 does not describe a FAT file system.
+    pub fn from_volume<T>(mut device: T) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let ebpb = BiosParameterBlock::from(&mut device, 0)?;
+        if !ebpb.is_fat() {
+            return Err(Error::BadSignature);
+        }
+        VFat::mount(device, &ebpb)
+    }
+
+    /// Mounts the FAT file system described by `ebpb` on `volume`, whose
+    /// sector 0 is the boot sector.
+    fn mount<T>(volume: T, ebpb: &BiosParameterBlock) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let bytes_per_sector = ebpb.bytes_per_sector();
+
+        // The FAT type follows from the number of clusters alone;
+        // the FATs may be too short to address all of them.
+        let data_sectors = ebpb.total_sectors().saturating_sub(ebpb.data_start_sector());
+        let clusters = min(data_sectors / ebpb.sectors_per_cluster() as u64,
+                           ::std::u32::MAX as u64);
+        let fat_type = FatType::from_cluster_count(clusters as u32);
+        let fat_entries = fat_type.entries(ebpb.sectors_per_fat() as u64
+                                           * bytes_per_sector as u64);
+        let cluster_count = min(clusters, fat_entries.saturating_sub(2)) as u32;
+
+        // Only FAT32 has an extended BPB with a root directory
+        // cluster, FAT mirroring flags and an FSInfo sector.
+        let fat32 = fat_type == FatType::Fat32;
+        let cache = CachedDevice::new(volume, Partition { start: 0,
+                                                       sector_size: bytes_per_sector as u64 });
+        let mirror_fats = !fat32 || ebpb.fats_mirrored();
+        let mut vfat = VFat {
+            device: cache,
+            bytes_per_sector,
+            sectors_per_cluster: ebpb.sectors_per_cluster(),
+            sectors_per_fat: ebpb.sectors_per_fat(),
+            fat_start_sector: ebpb.fat_start_sector(),
+            data_start_sector: ebpb.data_start_sector(),
+            root_dir_cluster: if fat32 {
+                Cluster::from(ebpb.root_cluster())
+            } else {
+                Cluster::root_region()
+            },
+            root_dir_sector: ebpb.root_dir_start_sector(),
+            root_dir_sectors: if fat32 { 0 } else { ebpb.root_dir_sectors() },
+            cluster_count,
+            fat_type,
+            fat_count: ebpb.fat_count(),
+            mirror_fats,
+            active_fat: if mirror_fats { 0 } else { ebpb.active_fat() },
+            fsinfo_sector: None,
+            free_clusters: None,
+            next_free: 2,
+            mounted_dirty: false,
+            hard_error: false,
+            dirty: false,
+            dir_indexes: DirIndexes::default(),
+            clock: || Timestamp::EPOCH,
         };
-        Err( Error::Io( io::Error::new( io::ErrorKind::NotFound, "vfat not found") ) )
+
+        let flags = vfat.fat_entry(Cluster::from(1))?.0;
+        vfat.mounted_dirty = fat_type.clean_shutdown_bit()
+            .map_or(false, |bit| flags & bit == 0);
+        vfat.hard_error = fat_type.no_hard_error_bit()
+            .map_or(false, |bit| flags & bit == 0);
+
+        // A missing or corrupt FSInfo sector only costs a scan of
+        // the FAT when the free cluster count is first needed.
+        if let (true, Some(offset)) = (fat32, ebpb.fsinfo_sector()) {
+            let sector = offset;
+            if let Ok(fsinfo) = FsInfo::from(&mut vfat.device, sector) {
+                let next_free = fsinfo.next_free()
+                    .and_then(|n| if vfat.is_data_cluster(n) { Some(n) } else { None });
+                vfat.fsinfo_sector = Some(sector);
+                vfat.free_clusters = fsinfo.free_count()
+                    .and_then(|n| if n <= cluster_count { Some(n) } else { None });
+                vfat.next_free = next_free.unwrap_or(2);
+            }
+        }
+
+        Ok(Shared::new(vfat))
     }
 
     // TODO: The following methods may be useful here:
@@ -74,43 +176,78 @@ impl VFat {
         offset: usize,
         buf: &mut [u8]
     ) -> io::Result<usize> {
-        let cluster_start_sector = self.data_start_sector as usize + 
-                                   cluster.data_index() * self.sectors_per_cluster as usize;
-        let mut sector_index = offset / self.bytes_per_sector as usize;
-        let sector_size = self.device.sector_size() as usize;
-        let cluster_bytes = sector_size as usize * self.sectors_per_cluster as usize;
-
-        // bytes of data to read
-        let bytes_to_read = if buf.len() < cluster_bytes - offset {
-            buf.len()
-        } else {
-            cluster_bytes - offset
-        };
+        let start = self.cluster_start_sector(cluster);
+        let cluster_size = self.cluster_size();
+        self.read_region(start, cluster_size, offset, buf)
+    }
 
-        let mut bytes_offset = offset % self.bytes_per_sector as usize;
-        let mut bytes_read = 0;
-        loop {
-            if bytes_read >= bytes_to_read {
-                break;
-            }
-            let sector_data : &[u8] = self.device.get( (cluster_start_sector + sector_index) as u64 )?;
+    /// The first sector of the data cluster `cluster`.
+    fn cluster_start_sector(&self, cluster: Cluster) -> u64 {
+        self.data_start_sector + cluster.data_index() as u64 * self.sectors_per_cluster as u64
+    }
 
-            // calculate the bytes read in the current sector
-            let bytes_copy = if bytes_to_read - bytes_read < sector_size - bytes_offset {
-                bytes_to_read - bytes_read
-            } else {
-                sector_size - bytes_offset
-            };
-            buf[bytes_read..bytes_read+bytes_copy].copy_from_slice(&sector_data[bytes_offset..bytes_offset+bytes_copy]);
+    /// Reads from byte `offset` of the `len` bytes of consecutive sectors
+    /// starting at sector `start` into `buf`. Returns the number of bytes
+    /// read, which is less than `buf.len()` when the region ends first.
+    fn read_region(
+        &mut self,
+        start: u64,
+        len: usize,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        let sector_size = self.bytes_per_sector as usize;
+        let bytes_to_read = min(buf.len(), len.saturating_sub(offset));
 
-            bytes_offset = 0;
-            sector_index += 1;
+        let mut bytes_read = 0;
+        while bytes_read < bytes_to_read {
+            let position = offset + bytes_read;
+            let sector = start + (position / sector_size) as u64;
+            let bytes_offset = position % sector_size;
+            let bytes_copy = min(bytes_to_read - bytes_read, sector_size - bytes_offset);
+
+            let sector_data = self.device.get(sector)?;
+            buf[bytes_read..bytes_read + bytes_copy]
+                .copy_from_slice(&sector_data[bytes_offset..bytes_offset + bytes_copy]);
             bytes_read += bytes_copy;
         }
 
         Ok(bytes_read)
+    }
+
+    /// Writes `buf` into the `len` bytes of consecutive sectors starting at
+    /// sector `start`, beginning at byte `offset`. Returns the number of bytes
+    /// written, which is less than `buf.len()` when the region ends first.
+    fn write_region(
+        &mut self,
+        start: u64,
+        len: usize,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        let sector_size = self.bytes_per_sector as usize;
+        let bytes_to_write = min(buf.len(), len.saturating_sub(offset));
+
+        let mut bytes_written = 0;
+        while bytes_written < bytes_to_write {
+            let position = offset + bytes_written;
+            let sector = start + (position / sector_size) as u64;
+            let bytes_offset = position % sector_size;
+            let bytes_copy = min(bytes_to_write - bytes_written, sector_size - bytes_offset);
+
+            let sector_data = self.device.get_mut(sector)?;
+            sector_data[bytes_offset..bytes_offset + bytes_copy]
+                .copy_from_slice(&buf[bytes_written..bytes_written + bytes_copy]);
+            bytes_written += bytes_copy;
+        }
 
+        Ok(bytes_written)
+    }
 
+    /// The size in bytes of the fixed-size root directory region of a FAT12
+    /// or FAT16 volume.
+    fn root_region_size(&self) -> usize {
+        self.root_dir_sectors as usize * self.bytes_per_sector as usize
     }
 
     fn append_cluster_data(
@@ -146,6 +283,14 @@ impl VFat {
         start: Cluster,
         buf: &mut Vec<u8>
     ) -> io::Result<usize> {
+        if start.is_root_region() {
+            let len_before = buf.len();
+            let size = self.root_region_size();
+            buf.resize(len_before + size, 0);
+            let root_dir_sector = self.root_dir_sector;
+            return self.read_region(root_dir_sector, size, 0, &mut buf[len_before..]);
+        }
+
         let mut cur_cluster = start;
         let mut bytes_read = 0;
 
@@ -166,65 +311,502 @@ impl VFat {
         }
         Ok(bytes_read)
     }
-    //
-    //  * A method to return a reference to a `FatEntry` for a cluster where the
-    //    reference points directly into a cached sector.
-    //
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        use std::mem;
-        const size : usize = mem::size_of::<FatEntry>();
-        let fat_sector = cluster.fat_index() * size / self.bytes_per_sector as usize;
-        let fat_index = cluster.fat_index() * size % self.bytes_per_sector as usize;
-        // if fat_sector >= self.sectors_per_fat {
-        //     return Err(io::Error::new(io::ErrorKind::NotFound,
-        //                               "Invalid cluster index"));
-        // }
-        let data = self.device.get(self.fat_start_sector + fat_sector as u64)?;
-        Ok(unsafe { &data[fat_index..fat_index + size].cast()[0] })
-        // use std::mem;
-        // use std::slice;
-        
-        // const s : usize = mem::size_of::<FatEntry>();
-        // let sector_whole = cluster.fat_index() * s / self.bytes_per_sector as usize;
-        // let bytes_remainder = cluster.fat_index() * s % self.bytes_per_sector as usize;
-        // let sector_offset = self.fat_start_sector + sector_whole as u64;
-        // let cached_sector_slice : &[u8] = self.device.get( sector_offset )?;
-        // let fat_entry = unsafe { slice::from_raw_parts( & cached_sector_slice[bytes_remainder] as * const u8 as * const FatEntry, 1 ) };
-        
-        // Ok( &fat_entry[0] )
+    /// Returns the entry for `cluster` in the FAT in use.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        let active_fat = self.active_fat;
+        self.read_fat_entry(active_fat, cluster)
     }
 
-    pub fn find_sector(&mut self, start: Cluster, offset: usize)
-        -> io::Result<(Cluster, usize)>
-    {
-        let cluster_size = self.bytes_per_sector as usize
-                            * self.sectors_per_cluster as usize;
+    /// Returns the sector holding the entry for `cluster` in FAT number `fat`
+    /// and the byte offset of the entry within that sector. FAT12 entries may
+    /// continue into the following sector.
+    fn fat_entry_position(&self, fat: u8, cluster: Cluster) -> (u64, usize) {
+        let offset = self.fat_type.entry_offset(cluster);
+        let sector = self.fat_start_sector
+            + fat as u64 * self.sectors_per_fat as u64
+            + (offset / self.bytes_per_sector as usize) as u64;
+        (sector, offset % self.bytes_per_sector as usize)
+    }
+
+    /// The number of bytes holding a FAT entry. Consecutive FAT12 entries
+    /// share a byte.
+    fn fat_entry_width(&self) -> usize {
+        match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    /// The bits of the bytes holding the entry for `cluster` that belong to
+    /// the neighbouring FAT12 entry.
+    fn fat_entry_neighbour_bits(&self, cluster: Cluster) -> u32 {
+        match (self.fat_type, cluster.fat_index() % 2) {
+            (FatType::Fat12, 0) => 0xF000,
+            (FatType::Fat12, _) => 0x000F,
+            _ => 0,
+        }
+    }
+
+    /// Reads the entry for `cluster` from FAT number `fat`.
+    fn read_fat_entry(&mut self, fat: u8, cluster: Cluster) -> io::Result<FatEntry> {
+        let (sector, offset) = self.fat_entry_position(fat, cluster);
+        let sector_size = self.bytes_per_sector as usize;
+
+        let mut raw = 0;
+        for i in 0..self.fat_entry_width() {
+            let position = offset + i;
+            let data = self.device.get(sector + (position / sector_size) as u64)?;
+            raw |= (data[position % sector_size] as u32) << (8 * i);
+        }
+
+        raw &= !self.fat_entry_neighbour_bits(cluster);
+        if self.fat_type == FatType::Fat12 && cluster.fat_index() % 2 == 1 {
+            raw >>= 4;
+        }
+        Ok(FatEntry::from_raw(self.fat_type, raw))
+    }
+
+    /// Writes `entry` as the entry for `cluster` in FAT number `fat`. The
+    /// sectors holding the entry are marked dirty.
+    fn write_fat_entry(&mut self, fat: u8, cluster: Cluster, entry: FatEntry) -> io::Result<()> {
+        let (sector, offset) = self.fat_entry_position(fat, cluster);
+        let sector_size = self.bytes_per_sector as usize;
+        let neighbour_bits = self.fat_entry_neighbour_bits(cluster);
+
+        let mut raw = entry.raw(self.fat_type);
+        if self.fat_type == FatType::Fat12 && cluster.fat_index() % 2 == 1 {
+            raw <<= 4;
+        }
+
+        for i in 0..self.fat_entry_width() {
+            let position = offset + i;
+            let keep = (neighbour_bits >> (8 * i)) as u8;
+            let data = self.device.get_mut(sector + (position / sector_size) as u64)?;
+            let byte = &mut data[position % sector_size];
+            *byte = (*byte & keep) | ((raw >> (8 * i)) as u8 & !keep);
+        }
+
+        Ok(())
+    }
+
+    /// The FATs that are kept up to date.
+    fn fats_in_use(&self) -> ::std::ops::Range<u8> {
+        if self.mirror_fats {
+            0..self.fat_count
+        } else {
+            self.active_fat..self.active_fat + 1
+        }
+    }
+
+    /// Writes every modified sector back to the underlying device, then marks
+    /// the volume clean unless it was already dirty when it was mounted.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.device.sync()?;
+        if self.dirty && !self.mounted_dirty {
+            self.set_clean_shutdown(true)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Sets the clock that stamps created and modified entries. Without one,
+    /// every entry is stamped `Timestamp::EPOCH`.
+    pub fn set_clock(&mut self, clock: fn() -> Timestamp) {
+        self.clock = clock;
+    }
+
+    /// Returns the current time according to the volume's clock.
+    pub fn now(&self) -> Timestamp {
+        (self.clock)()
+    }
+
+    /// Returns `true` if the volume was not cleanly unmounted before it was
+    /// mounted, so that its file system may be inconsistent.
+    pub fn mounted_dirty(&self) -> bool {
+        self.mounted_dirty
+    }
+
+    /// Returns `true` if the volume records a disk I/O error.
+    pub fn hard_error(&self) -> bool {
+        self.hard_error
+    }
+
+    /// Records that the volume has been checked and repaired, so that the
+    /// next `sync()` marks it clean even though it was dirty when mounted.
+    pub fn mark_checked(&mut self) {
+        if self.mounted_dirty {
+            // The clean shutdown bit is already clear on disk.
+            self.mounted_dirty = false;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets or clears the clean shutdown bit of FAT[1] in every FAT in use
+    /// and writes it to the device immediately. FAT12 volumes have no such
+    /// bit.
+    fn set_clean_shutdown(&mut self, clean: bool) -> io::Result<()> {
+        let bit = match self.fat_type.clean_shutdown_bit() {
+            Some(bit) => bit,
+            None => return Ok(()),
+        };
+
+        for fat in self.fats_in_use() {
+            let mut entry = self.read_fat_entry(fat, Cluster::from(1))?;
+            entry.0 = if clean { entry.0 | bit } else { entry.0 & !bit };
+            self.write_fat_entry(fat, Cluster::from(1), entry)?;
+            let (sector, _) = self.fat_entry_position(fat, Cluster::from(1));
+            self.device.flush(sector)?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the volume dirty on disk before its first modification.
+    fn mark_dirty(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            self.set_clean_shutdown(false)?;
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Returns the sector cache's hit, miss, and write-back counters.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// The first cluster of the root directory.
+    pub fn root_dir_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
+    /// Writes `buf` into `cluster` starting at byte `offset` of the cluster.
+    /// Returns the number of bytes written, which is less than `buf.len()`
+    /// when `buf` does not fit in the rest of the cluster.
+    pub fn write_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        self.mark_dirty()?;
+        let start = self.cluster_start_sector(cluster);
+        let cluster_size = self.cluster_size();
+        self.write_region(start, cluster_size, offset, buf)
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    pub fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid cluster entry")),
+        }
+    }
 
-        let cluster_index = offset / cluster_size;
+    /// Returns the `index`th (0-indexed) cluster of the chain starting at
+    /// `start`, or `None` if the chain is shorter than that.
+    fn nth_cluster(&mut self, start: Cluster, index: usize) -> io::Result<Option<Cluster>> {
         let mut cluster = start;
+        for _ in 0..index {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(cluster))
+    }
 
-        for i in 0..cluster_index {
-            let fat_entry = self.fat_entry(cluster)?.status();
+    /// Reads from the chain starting at `start`, beginning at byte `offset` of
+    /// the chain, into `buf`. Returns the number of bytes read, which is less
+    /// than `buf.len()` if the chain ends first. `start` may also be the fixed
+    /// root directory region.
+    pub fn read_chain_at(
+        &mut self,
+        start: Cluster,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        if start.is_root_region() {
+            let (root_dir_sector, size) = (self.root_dir_sector, self.root_region_size());
+            return self.read_region(root_dir_sector, size, offset, buf);
+        }
 
-            match fat_entry {
-                Status::Data(next) => {
-                    cluster = next;
-                },
-                Status::Eoc(_) => {
-                    if i + 1 != cluster_index {
-                        return Err(io::Error::new(
-                                                io::ErrorKind::UnexpectedEof,
-                                                "Data does not match size"));
-                    }
+        let cluster_size = self.cluster_size();
+        let mut cluster = match self.nth_cluster(start, offset / cluster_size)? {
+            Some(cluster) => cluster,
+            None => return Ok(0),
+        };
 
-                    cluster = Cluster::from(0xFFFFFFFF);
-                },
-                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
-                                               "Invalid cluster entry")),
+        let mut cluster_offset = offset % cluster_size;
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            bytes_read += self.read_cluster(cluster, cluster_offset, &mut buf[bytes_read..])?;
+            cluster_offset = 0;
+            if bytes_read < buf.len() {
+                cluster = match self.next_cluster(cluster)? {
+                    Some(next) => next,
+                    None => break,
+                };
             }
         }
 
-        Ok((cluster, cluster_index * cluster_size))
+        Ok(bytes_read)
+    }
+
+    /// Writes `buf` into the chain starting at `start`, beginning at byte
+    /// `offset` of the chain. The chain must already be long enough to hold
+    /// the data; see `resize_chain()`. `start` may also be the fixed root
+    /// directory region.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `UnexpectedEof` if the chain ends before all of
+    /// `buf` is written.
+    pub fn write_chain_at(
+        &mut self,
+        start: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        let eof = || io::Error::new(io::ErrorKind::UnexpectedEof, "cluster chain too short");
+
+        if start.is_root_region() {
+            self.mark_dirty()?;
+            let (root_dir_sector, size) = (self.root_dir_sector, self.root_region_size());
+            let written = self.write_region(root_dir_sector, size, offset, buf)?;
+            return if written < buf.len() { Err(eof()) } else { Ok(written) };
+        }
+
+        let cluster_size = self.cluster_size();
+        let mut cluster = self.nth_cluster(start, offset / cluster_size)?.ok_or_else(&eof)?;
+
+        let mut cluster_offset = offset % cluster_size;
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            bytes_written += self.write_cluster(cluster, cluster_offset, &buf[bytes_written..])?;
+            cluster_offset = 0;
+            if bytes_written < buf.len() {
+                cluster = self.next_cluster(cluster)?.ok_or_else(&eof)?;
+            }
+        }
+
+        Ok(bytes_written)
+    }
+
+    /// Sets the FAT entry of `cluster` to `status` in every FAT in use, and
+    /// keeps the free cluster count up to date.
+    pub fn set_fat_entry(&mut self, cluster: Cluster, status: Status) -> io::Result<()> {
+        self.mark_dirty()?;
+        let was_free = self.fat_entry(cluster)?.status() == Status::Free;
+
+        for fat in self.fats_in_use() {
+            let mut entry = self.read_fat_entry(fat, cluster)?;
+            entry.set(status);
+            self.write_fat_entry(fat, cluster, entry)?;
+        }
+
+        let is_free = status == Status::Free;
+        if was_free != is_free {
+            if let Some(free) = self.free_clusters {
+                self.free_clusters = Some(if is_free { free + 1 } else { free.saturating_sub(1) });
+                self.update_fsinfo()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if `raw` is the number of a cluster in the data region.
+    fn is_data_cluster(&self, raw: u32) -> bool {
+        raw >= 2 && raw < self.cluster_count + 2
+    }
+
+    /// Writes the free cluster count and next free cluster hint to the
+    /// FSInfo sector, if the volume has one.
+    fn update_fsinfo(&mut self) -> io::Result<()> {
+        if let Some(sector) = self.fsinfo_sector {
+            let (free_clusters, next_free) = (self.free_clusters, self.next_free);
+            FsInfo::update(self.device.get_mut(sector)?, free_clusters, Some(next_free));
+        }
+        Ok(())
+    }
+
+    /// The name lookup indexes of large directories.
+    pub(crate) fn dir_indexes(&mut self) -> &mut DirIndexes {
+        &mut self.dir_indexes
+    }
+
+    /// The FAT type of the volume.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// The number of clusters in the data region.
+    pub fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// Returns the number of free clusters. The count is taken from the
+    /// FSInfo sector and kept up to date as clusters are allocated and freed;
+    /// the FAT is scanned only if the FSInfo sector holds no valid count.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free) = self.free_clusters {
+            return Ok(free);
+        }
+
+        let mut free = 0;
+        for raw in 2..self.cluster_count + 2 {
+            if self.fat_entry(Cluster::from(raw))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+
+        self.free_clusters = Some(free);
+        self.update_fsinfo()?;
+        Ok(free)
+    }
+
+    /// Allocates a free cluster, zeroes it, and marks it as the end of its
+    /// chain. If `prev` is `Some`, the new cluster is linked after `prev`.
+    /// The search for a free cluster starts at the FSInfo next free hint.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are no free clusters left.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let no_space = || io::Error::new(io::ErrorKind::Other, "no free clusters");
+        if self.free_clusters == Some(0) {
+            return Err(no_space());
+        }
+
+        let mut free = None;
+        let first = self.next_free - 2;
+        for i in 0..self.cluster_count {
+            let cluster = Cluster::from((first + i) % self.cluster_count + 2);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                free = Some(cluster);
+                break;
+            }
+        }
+
+        let cluster = free.ok_or_else(no_space)?;
+        self.next_free = if self.is_data_cluster(cluster.raw() + 1) { cluster.raw() + 1 } else { 2 };
+        self.set_fat_entry(cluster, Status::Eoc(0x0FFFFFFF))?;
+        self.update_fsinfo()?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, Status::Data(cluster))?;
+        }
+
+        let zeroes = vec![0u8; self.cluster_size()];
+        self.write_cluster(cluster, 0, &zeroes)?;
+        Ok(cluster)
+    }
+
+    /// Links the `count` consecutive clusters starting at `start` into a
+    /// single chain, as they were before the file holding them was deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the clusters are not all in
+    /// the data region, and of kind `Other` if any of them is in use.
+    pub fn relink_chain(&mut self, start: Cluster, count: usize) -> io::Result<()> {
+        let end = start.raw() as u64 + count as u64;
+        if count == 0 || !self.is_data_cluster(start.raw())
+            || end > self.cluster_count as u64 + 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "clusters out of the data region"));
+        }
+
+        for raw in start.raw()..end as u32 {
+            if self.fat_entry(Cluster::from(raw))?.status() != Status::Free {
+                return Err(io::Error::new(io::ErrorKind::Other, "cluster in use"));
+            }
+        }
+
+        for raw in start.raw()..end as u32 - 1 {
+            self.set_fat_entry(Cluster::from(raw), Status::Data(Cluster::from(raw + 1)))?;
+        }
+        self.set_fat_entry(Cluster::from(end as u32 - 1), Status::Eoc(0x0FFFFFFF))
+    }
+
+    /// Returns the clusters of the chain starting at `start`, in order.
+    pub(crate) fn chain_clusters(&mut self, start: Cluster) -> io::Result<Vec<Cluster>> {
+        let mut clusters = vec![start];
+        while let Some(next) = self.next_cluster(*clusters.last().unwrap())? {
+            clusters.push(next);
+        }
+        Ok(clusters)
+    }
+
+    /// Marks every cluster of the chain starting at `start` as free. Invalid
+    /// start clusters, such as that of an empty file, are ignored.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        // A directory later created at `start` must not use a stale index.
+        self.dir_indexes.invalidate(start);
+        let mut cluster = start;
+        while cluster.is_valid() {
+            let next = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, Status::Free)?;
+            cluster = match next {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    /// Grows or shrinks the chain starting at `start` to exactly the number of
+    /// clusters needed to hold `len` bytes. A new chain is allocated if
+    /// `start` is not a valid cluster. Returns the first cluster of the
+    /// resulting chain, which is cluster 0 if `len` is 0.
+    ///
+    /// The fixed root directory region of FAT12 and FAT16 volumes cannot be
+    /// resized; an error of kind `Other` is returned if it is too small.
+    pub fn resize_chain(&mut self, start: Cluster, len: usize) -> io::Result<Cluster> {
+        if start.is_root_region() {
+            return if len <= self.root_region_size() {
+                Ok(start)
+            } else {
+                Err(io::Error::new(io::ErrorKind::Other, "root directory full"))
+            };
+        }
+
+        let cluster_size = self.cluster_size();
+        let wanted = (len + cluster_size - 1) / cluster_size;
+        if wanted == 0 {
+            self.free_chain(start)?;
+            return Ok(Cluster::from(0));
+        }
+
+        let start = if start.is_valid() { start } else { self.alloc_cluster(None)? };
+        let mut last = start;
+        for _ in 1..wanted {
+            last = match self.next_cluster(last)? {
+                Some(next) => next,
+                None => self.alloc_cluster(Some(last))?,
+            };
+        }
+
+        if let Some(rest) = self.next_cluster(last)? {
+            self.set_fat_entry(last, Status::Eoc(0x0FFFFFFF))?;
+            self.free_chain(rest)?;
+        }
+
+        Ok(start)
+    }
+}
+
+impl Drop for VFat {
+    /// Writes back all changes and marks the volume clean.
+    fn drop(&mut self) {
+        let _ = self.sync();
     }
 }
 
@@ -239,8 +821,9 @@ impl<'a> FileSystem for &'a Shared<VFat> {
 
         let root_cluster = self.borrow().root_dir_cluster;
         let mut dir = Entry::new_dir("".to_string(),
-                                     Metadata::default(),
-                                     Dir::new(root_cluster, self.clone()));
+                                     Metadata::root(),
+                                     Dir::new(root_cluster, self.clone()),
+                                     None);
 
         for component in path.as_ref().components() {
             match component {
@@ -265,23 +848,71 @@ impl<'a> FileSystem for &'a Shared<VFat> {
 
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn case_sensitive(&self) -> bool {
+        false
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (dir, name) = parent_dir(self, path.as_ref())?;
+        dir.create_file(name)
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let path = path.as_ref();
+        if parents {
+            if let Some(parent) = path.parent() {
+                match self.open(parent) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        self.create_dir(parent, true)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let (dir, name) = parent_dir(self, path)?;
+        dir.create_dir(name)
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let (from_dir, from_name) = parent_dir(self, from.as_ref())?;
+        let (to_dir, to_name) = parent_dir(self, to.as_ref())?;
+        from_dir.rename(from_name, &to_dir, to_name)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let (dir, name) = parent_dir(self, path.as_ref())?;
+        dir.remove(name, children)
     }
+}
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+/// Splits the absolute path `path` into the directory holding its last
+/// component and the name of that component.
+///
+/// # Errors
+///
+/// If `path` is not absolute, has no last component, or any component but the
+/// last does not refer to an existing directory, an error kind of
+/// `InvalidInput` is returned.
+fn parent_dir<'p>(vfat: &Shared<VFat>, path: &'p Path) -> io::Result<(Dir, &'p str)> {
+    let invalid = |msg: &'static str| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+    if !path.is_absolute() {
+        return Err(invalid("path is not absolute"));
     }
+
+    let name = path.file_name()
+        .ok_or(invalid("path has no file name"))?
+        .to_str()
+        .ok_or(invalid("Invalid UTF-8"))?;
+
+    let parent = path.parent().ok_or(invalid("path has no parent"))?;
+    let dir = vfat.open_dir(parent)
+        .map_err(|_| invalid("parent is not an existing directory"))?;
+
+    Ok((dir, name))
 }
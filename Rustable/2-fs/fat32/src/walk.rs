@@ -0,0 +1,285 @@
+//! Recursive traversal and glob matching over any `traits::FileSystem`.
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use traits::{Dir, Entry};
+use vfat::case;
+
+/// The order in which `Walk` visits entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// Every directory is followed by its contents, before its next sibling.
+    DepthFirst,
+    /// All entries at one depth are visited before any entry below them.
+    BreadthFirst,
+}
+
+/// An entry found by `Walk` or `Glob`.
+#[derive(Debug)]
+pub struct WalkEntry<E> {
+    /// The absolute path of the entry.
+    pub path: PathBuf,
+    /// The number of components between the directory walked and the entry.
+    /// The entries of the walked directory itself have depth 1.
+    pub depth: usize,
+    pub entry: E,
+}
+
+/// The directories that remain to be read, with their paths and depths.
+struct Pending<D: Dir> {
+    dirs: VecDeque<(PathBuf, usize, D::Iter)>,
+    order: Order,
+}
+
+impl<D> Pending<D> where D: Dir, D::Entry: Entry<Dir = D> {
+    fn new(path: PathBuf, dir: &D, order: Order) -> io::Result<Pending<D>> {
+        let mut dirs = VecDeque::new();
+        dirs.push_back((path, 1, dir.entries()?));
+        Ok(Pending { dirs, order })
+    }
+
+    /// Returns the next entry. Its contents are visited later unless it is not
+    /// a directory or `prune` returns `true` for it.
+    fn advance<F>(&mut self, mut prune: F) -> Option<io::Result<WalkEntry<D::Entry>>>
+        where F: FnMut(&WalkEntry<D::Entry>) -> bool
+    {
+        loop {
+            let next = {
+                let current = match self.order {
+                    Order::DepthFirst => self.dirs.back_mut(),
+                    Order::BreadthFirst => self.dirs.front_mut(),
+                }?;
+                current.2.next().map(|entry| WalkEntry {
+                    path: current.0.join(entry.name()),
+                    depth: current.1,
+                    entry,
+                })
+            };
+
+            let next = match next {
+                Some(next) => next,
+                None => {
+                    match self.order {
+                        Order::DepthFirst => self.dirs.pop_back(),
+                        Order::BreadthFirst => self.dirs.pop_front(),
+                    };
+                    continue;
+                }
+            };
+
+            if next.entry.name() == "." || next.entry.name() == ".." {
+                continue;
+            }
+
+            if next.entry.is_dir() && !prune(&next) {
+                let entries = match next.entry.as_dir() {
+                    Some(dir) => dir.entries(),
+                    None => unreachable!(),
+                };
+                match entries {
+                    Ok(entries) => self.dirs.push_back((next.path.clone(), next.depth + 1, entries)),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+
+            return Some(Ok(next));
+        }
+    }
+}
+
+/// An iterator over every entry below a directory, except `.` and `..`. The
+/// directory itself is not included. Created by `FileSystem::walk()`.
+///
+/// A directory that cannot be read is reported as an error in place of the
+/// entry for it; the walk continues with the next entry.
+pub struct Walk<D: Dir, P> {
+    pending: Pending<D>,
+    prune: P,
+}
+
+impl<D> Walk<D, fn(&WalkEntry<D::Entry>) -> bool> where D: Dir, D::Entry: Entry<Dir = D> {
+    pub(crate) fn new(path: PathBuf, dir: D, order: Order)
+        -> io::Result<Walk<D, fn(&WalkEntry<D::Entry>) -> bool>>
+    {
+        fn never<E>(_: &WalkEntry<E>) -> bool {
+            false
+        }
+
+        Ok(Walk { pending: Pending::new(path, &dir, order)?, prune: never })
+    }
+}
+
+impl<D: Dir, P> Walk<D, P> {
+    /// Calls `prune` on every directory found, and does not descend into
+    /// those it returns `true` for. The directories themselves are still
+    /// returned.
+    pub fn prune<F>(self, prune: F) -> Walk<D, F>
+        where F: FnMut(&WalkEntry<D::Entry>) -> bool
+    {
+        Walk { pending: self.pending, prune }
+    }
+}
+
+impl<D, P> Iterator for Walk<D, P>
+    where D: Dir, D::Entry: Entry<Dir = D>, P: FnMut(&WalkEntry<D::Entry>) -> bool
+{
+    type Item = io::Result<WalkEntry<D::Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pending.advance(&mut self.prune)
+    }
+}
+
+/// A glob pattern over absolute paths.
+///
+/// Each component of the pattern is matched against one path component, in
+/// which `*` matches any run of characters and `?` any single character. A
+/// component that is exactly `**` matches any number of path components,
+/// including none. Matching is case-sensitive unless the pattern is made
+/// case-insensitive with `case_sensitive(false)`, in which case names are
+/// compared under the same case folding FAT uses for lookups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    components: Vec<String>,
+    fold_case: bool,
+}
+
+impl Pattern {
+    /// Parses `pattern`. Relative patterns are taken relative to the root.
+    pub fn new(pattern: &str) -> Pattern {
+        Pattern {
+            components: pattern.split('/')
+                .filter(|component| !component.is_empty())
+                .map(|component| component.to_string())
+                .collect(),
+            fold_case: false,
+        }
+    }
+
+    /// Returns this pattern, matching case-sensitively if `sensitive` is
+    /// `true` and case-insensitively otherwise.
+    pub fn case_sensitive(mut self, sensitive: bool) -> Pattern {
+        self.fold_case = !sensitive;
+        self
+    }
+
+    /// Returns `true` if `path` matches the pattern.
+    pub fn matches<P: AsRef<Path>>(&self, path: P) -> bool {
+        match normal_components(path.as_ref()) {
+            Some(path) => self.match_from(&self.components, &path, false),
+            None => false,
+        }
+    }
+
+    /// Returns `true` if some path below the directory `path` may match the
+    /// pattern.
+    pub fn may_match_below<P: AsRef<Path>>(&self, path: P) -> bool {
+        match normal_components(path.as_ref()) {
+            Some(path) => self.match_from(&self.components, &path, true),
+            None => false,
+        }
+    }
+
+    /// Matches `path` against `pattern`. If `prefix` is `true`, `path` only
+    /// has to match the start of the pattern.
+    fn match_from(&self, pattern: &[String], path: &[&str], prefix: bool) -> bool {
+        if path.is_empty() {
+            return prefix || pattern.iter().all(|component| component == "**");
+        }
+
+        match pattern.split_first() {
+            None => false,
+            Some((first, rest)) if first == "**" => {
+                self.match_from(rest, path, prefix)
+                    || self.match_from(pattern, &path[1..], prefix)
+            }
+            Some((first, rest)) => {
+                let fold = |c| if self.fold_case { case::fold_char(c) } else { c };
+                let first: Vec<char> = first.chars().map(&fold).collect();
+                let name: Vec<char> = path[0].chars().map(&fold).collect();
+                match_component(&first, &name) && self.match_from(rest, &path[1..], prefix)
+            }
+        }
+    }
+}
+
+/// Returns the names in `path`, or `None` if it contains `.`, `..` or
+/// components that are not valid UTF-8.
+fn normal_components(path: &Path) -> Option<Vec<&str>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => {}
+            Component::Normal(name) => names.push(name.to_str()?),
+            _ => return None,
+        }
+    }
+    Some(names)
+}
+
+/// Matches `name` against the single component `pattern`.
+///
+/// A mismatch only ever backtracks to the most recent `*`, letting it match
+/// one more character: any earlier `*` could only absorb characters the later
+/// one can absorb as well. This bounds matching by the product of the lengths
+/// instead of growing exponentially with the number of `*`s.
+fn match_component(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The position just after the last `*` seen, and the name position it
+    // was last retried from.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(&'*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// An iterator over the entries whose paths match a `Pattern`, in depth-first
+/// order. Only directories that may contain matches are read. Created by
+/// `FileSystem::glob()`.
+pub struct Glob<D: Dir> {
+    pending: Pending<D>,
+    pattern: Pattern,
+}
+
+impl<D> Glob<D> where D: Dir, D::Entry: Entry<Dir = D> {
+    pub(crate) fn new(root: D, pattern: Pattern) -> io::Result<Glob<D>> {
+        let pending = Pending::new(PathBuf::from("/"), &root, Order::DepthFirst)?;
+        Ok(Glob { pending, pattern })
+    }
+}
+
+impl<D> Iterator for Glob<D> where D: Dir, D::Entry: Entry<Dir = D> {
+    type Item = io::Result<WalkEntry<D::Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pattern = &self.pattern;
+        loop {
+            match self.pending.advance(|entry| !pattern.may_match_below(&entry.path))? {
+                Ok(entry) => if pattern.matches(&entry.path) {
+                    return Some(Ok(entry));
+                },
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Runs `mkfs` and `fat32-tool` against a scratch disk image.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+/// Returns the path of the binary `name` built alongside this test.
+fn binary(name: &str) -> PathBuf {
+    let mut path = env::current_exe().expect("test executable path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join(name)
+}
+
+fn run(name: &str, args: &[&str]) -> Output {
+    let output = Command::new(binary(name)).args(args).output().expect("run binary");
+    assert!(output.status.success(), "{} {:?} failed: {}", name, args,
+            String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn stdout(output: Output) -> String {
+    String::from_utf8(output.stdout).expect("UTF-8 output")
+}
+
+/// A scratch file removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let file = format!("fat32-tool-test-{}-{}", process::id(), name);
+        Scratch(env::temp_dir().join(file))
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().expect("UTF-8 temp path")
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_fat32_tool() {
+    let image = Scratch::new("disk.img");
+    let input = Scratch::new("input.txt");
+    let output = Scratch::new("output.txt");
+    let image = image.path();
+
+    run("mkfs", &[image, "40M"]);
+    File::create(input.path()).and_then(|mut file| file.write_all(b"hello from the host\n"))
+        .expect("write input");
+
+    run("fat32-tool", &[image, "mkdir", "-p", "/a/b"]);
+    run("fat32-tool", &[image, "put", input.path(), "/a/b"]);
+    run("fat32-tool", &[image, "put", input.path(), "/a/Notes.TXT"]);
+
+    let name = Path::new(input.path()).file_name().unwrap().to_str().unwrap();
+    assert_eq!(stdout(run("fat32-tool", &[image, "ls", "/a/b"])), format!("./\n../\n{}\n", name));
+    let listing = stdout(run("fat32-tool", &[image, "ls", "/a"]));
+    assert!(listing.contains("b/\n") && listing.contains("Notes.TXT\n"), "{}", listing);
+
+    let copied = format!("/a/b/{}", name);
+    assert_eq!(stdout(run("fat32-tool", &[image, "cat", &copied])), "hello from the host\n");
+    run("fat32-tool", &[image, "get", "/a/Notes.TXT", output.path()]);
+    let mut copied_out = String::new();
+    File::open(output.path()).and_then(|mut file| file.read_to_string(&mut copied_out))
+        .expect("read output");
+    assert_eq!(copied_out, "hello from the host\n");
+
+    run("fat32-tool", &[image, "rm", "-r", "/a"]);
+    assert_eq!(stdout(run("fat32-tool", &[image, "ls"])), "");
+    run("fsck", &[image]);
+
+    let missing = Command::new(binary("fat32-tool")).args(&[image, "cat", "/a/Notes.TXT"])
+        .output().expect("run binary");
+    assert_eq!(missing.status.code(), Some(1));
+}
@@ -12,11 +12,25 @@ pub mod mm_init;
 const BINARY_START_ADDR: usize = 0xffffff0000800000;
 const BOOTLOADER_START_ADDR: usize = 0xffffff0004000000;
 
+/// Start address of the initial ramdisk, received after the binary.
+const INITRD_START_ADDR: usize = 0xffffff0003000000;
+
 /// Pointer to where the loaded binary expects to be laoded.
 const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 
-/// Free space between the bootloader and the loaded binary's start address.
-const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
+/// Pointer to where the initial ramdisk is received.
+const INITRD_START: *mut u8 = INITRD_START_ADDR as *mut u8;
+
+/// Free space between the initial ramdisk and the loaded binary's start
+/// address.
+const MAX_BINARY_SIZE: usize = INITRD_START_ADDR - BINARY_START_ADDR;
+
+/// Free space between the bootloader and the initial ramdisk.
+const MAX_INITRD_SIZE: usize = BOOTLOADER_START_ADDR - INITRD_START_ADDR;
+
+/// How many read timeouts to wait for an initial ramdisk to be sent after the
+/// binary before booting without one.
+const INITRD_ATTEMPTS: usize = 4;
 
 /// Branches to the address `addr` unconditionally.
 fn jump_to(addr: *mut u8) -> ! {
@@ -26,6 +40,31 @@ fn jump_to(addr: *mut u8) -> ! {
     }
 }
 
+/// Receives an initial ramdisk, such as a tar or cpio archive, and records it in
+/// the ATAGS for the loaded binary. Gives up if no transfer starts within
+/// `INITRD_ATTEMPTS` read timeouts, leaving any initial ramdisk the firmware
+/// loaded from the card in place.
+fn receive_initrd(uart: &mut pi::uart::MiniUart) {
+    for _ in 0..INITRD_ATTEMPTS {
+        let initrd = unsafe { std::slice::from_raw_parts_mut(INITRD_START, MAX_INITRD_SIZE) };
+        match xmodem::Xmodem::receive(&mut *uart, std::io::Cursor::new(initrd)) {
+            Ok(size) => {
+                let start = INITRD_START_ADDR - mm_init::KADDR(0);
+                unsafe { pi::atags::set_initrd2(start as u32, size as u32) };
+                return;
+            }
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::TimedOut => continue,
+                std::io::ErrorKind::InvalidData => continue,
+                _ => {
+                    uart.write_fmt(format_args!("Error: {:?}\r\n", err)).unwrap();
+                    return;
+                }
+            },
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn kmain() {
     // FIXME: Implement the bootloader.
@@ -35,7 +74,10 @@ pub extern "C" fn kmain() {
         let mut addr = unsafe { std::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE) };
         // addr[0] = 0x1;
         match xmodem::Xmodem::receive(&mut uart, std::io::Cursor::new(addr)) {
-            Ok(_) => { jump_to(BINARY_START); },
+            Ok(_) => {
+                receive_initrd(&mut uart);
+                jump_to(BINARY_START);
+            },
             Err(err) => match err.kind() {
                 std::io::ErrorKind::TimedOut => continue,
                 std::io::ErrorKind::InvalidData => continue,
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Writes to standard output an ATAG list, as the firmware leaves at 0x100,
+# describing `mem_size` bytes of memory from address 0 and, if given, an
+# initial ramdisk of `initrd_size` bytes at `initrd_start`. QEMU boots ELF
+# kernels without ATAGS, so `make qemu` loads this list in their place.
+#
+# usage: atags.sh <mem_size> [<initrd_start> <initrd_size>]
+set -e
+
+# Writes the 32-bit little-endian word $1.
+word() {
+    printf "$(printf '\\%03o\\%03o\\%03o\\%03o' \
+        $(($1 & 255)) $(($1 >> 8 & 255)) $(($1 >> 16 & 255)) $(($1 >> 24 & 255)))"
+}
+
+# CORE, without the optional fields. The kernel skips the first ATAG.
+word 2; word 0x54410001
+# MEM: size, start.
+word 4; word 0x54410002; word "$1"; word 0
+if [ $# -eq 3 ]; then
+    # INITRD2: start, size.
+    word 4; word 0x54420005; word "$2"; word "$3"
+fi
+# NONE
+word 0; word 0
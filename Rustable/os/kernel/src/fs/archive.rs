@@ -0,0 +1,201 @@
+//! Unpacks the initial ramdisk: a ustar (POSIX tar) or new ASCII (`newc`)
+//! cpio archive, such as one made by `tar --format=ustar -cf initrd.tar -C
+//! root .` or `find . | cpio -o -H newc > initrd.cpio`.
+//!
+//! Regular files and directories are unpacked. Other entries, such as
+//! symbolic links and device files, are skipped.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use fat32::traits::{File, FileSystem};
+
+/// The size of a tar header and the unit tar pads data to.
+const TAR_BLOCK: usize = 512;
+/// The size of a `newc` cpio header.
+const CPIO_HEADER: usize = 110;
+/// The name of the entry that ends a cpio archive.
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// An entry of an archive.
+enum Kind {
+    File,
+    Dir,
+    Other,
+}
+
+/// Unpacks the archive `data` into `fs` and returns the number of files and
+/// directories unpacked. Entries replace files of the same name in `fs`, and
+/// missing parent directories are created.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidData` if `data` is not a ustar or `newc` cpio
+/// archive or is malformed. Errors from `fs` are returned as they are; the
+/// entries before the failing one stay unpacked.
+pub fn unpack<F: FileSystem + Copy>(data: &[u8], fs: F) -> io::Result<usize> {
+    if data.starts_with(b"070701") || data.starts_with(b"070702") {
+        unpack_cpio(data, fs)
+    } else if data.len() >= TAR_BLOCK && &data[257..262] == b"ustar" {
+        unpack_tar(data, fs)
+    } else {
+        Err(invalid("not a tar or cpio archive"))
+    }
+}
+
+/// Returns `data[start..start + len]`, or an error if `data` is too short.
+fn field(data: &[u8], start: usize, len: usize) -> io::Result<&[u8]> {
+    start.checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| invalid("truncated archive"))
+}
+
+/// Parses the number in `field`, written in `radix` and padded with NULs or
+/// spaces.
+fn number(field: &[u8], radix: u32) -> io::Result<usize> {
+    let text = str::from_utf8(field).map_err(|_| invalid("malformed number"))?
+        .trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(text, radix).map_err(|_| invalid("malformed number"))
+}
+
+/// Returns the `\0`-terminated string at the start of `field`.
+fn string(field: &[u8]) -> io::Result<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).map_err(|_| invalid("name is not UTF-8"))
+}
+
+fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) / align * align
+}
+
+fn unpack_tar<F: FileSystem + Copy>(data: &[u8], fs: F) -> io::Result<usize> {
+    let mut offset = 0;
+    let mut unpacked = 0;
+    loop {
+        let header = field(data, offset, TAR_BLOCK)?;
+        // The archive ends with two zero blocks; stop at the first.
+        if header.iter().all(|&b| b == 0) {
+            return Ok(unpacked);
+        }
+
+        // The checksum is the sum of the header's bytes, with those of the
+        // checksum itself taken as spaces.
+        let expected = number(&header[148..156], 8)?;
+        let checksum: usize = header.iter().enumerate()
+            .map(|(i, &b)| if i >= 148 && i < 156 { b' ' as usize } else { b as usize })
+            .sum();
+        if checksum != expected {
+            return Err(invalid("bad tar header checksum"));
+        }
+
+        let size = number(&header[124..136], 8)?;
+        let name = string(&header[0..100])?;
+        let prefix = string(&header[345..500])?;
+        let path = if prefix.is_empty() {
+            PathBuf::from(name)
+        } else {
+            Path::new(prefix).join(name)
+        };
+        let kind = match header[156] {
+            b'0' | b'\0' | b'7' => Kind::File,
+            b'5' => Kind::Dir,
+            _ => Kind::Other,
+        };
+
+        let contents = field(data, offset + TAR_BLOCK, size)?;
+        if create(fs, &path, kind, contents)? {
+            unpacked += 1;
+        }
+        offset += TAR_BLOCK + align_up(size, TAR_BLOCK);
+    }
+}
+
+fn unpack_cpio<F: FileSystem + Copy>(data: &[u8], fs: F) -> io::Result<usize> {
+    let mut offset = 0;
+    let mut unpacked = 0;
+    loop {
+        let header = field(data, offset, CPIO_HEADER)?;
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err(invalid("bad cpio header magic"));
+        }
+
+        // Thirteen 8-digit hexadecimal fields follow the magic.
+        let hex = |index: usize| number(&header[6 + index * 8..14 + index * 8], 16);
+        let mode = hex(1)? as u32;
+        let size = hex(6)?;
+        let name_size = hex(11)?;
+
+        let name = string(field(data, offset + CPIO_HEADER, name_size)?)?;
+        if name == CPIO_TRAILER {
+            return Ok(unpacked);
+        }
+
+        let start = align_up(offset + CPIO_HEADER + name_size, 4);
+        let kind = match mode & S_IFMT {
+            S_IFREG => Kind::File,
+            S_IFDIR => Kind::Dir,
+            _ => Kind::Other,
+        };
+
+        let contents = field(data, start, size)?;
+        if create(fs, Path::new(name), kind, contents)? {
+            unpacked += 1;
+        }
+        offset = align_up(start + size, 4);
+    }
+}
+
+/// Creates the entry `path`, relative to the root of `fs`, with `contents`.
+/// Returns whether an entry was created.
+fn create<F: FileSystem + Copy>(fs: F, path: &Path, kind: Kind, contents: &[u8])
+    -> io::Result<bool>
+{
+    let path = Path::new("/").join(path.strip_prefix("/").unwrap_or(path));
+    if path == Path::new("/") || path.file_name().is_none() {
+        // The archive's root, such as `.`.
+        return Ok(false);
+    }
+
+    match kind {
+        Kind::Dir => match fs.create_dir(&path, true) {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(true),
+            Err(e) => Err(e),
+        },
+        Kind::File => {
+            if let Some(parent) = path.parent() {
+                if parent != Path::new("/") {
+                    if let Err(e) = fs.create_dir(parent, true) {
+                        if e.kind() != io::ErrorKind::AlreadyExists {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+
+            let mut file = match fs.create_file(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let mut file = fs.open_file(&path)?;
+                    file.set_len(0)?;
+                    file
+                }
+                result => result?,
+            };
+            file.write_all(contents)?;
+            Ok(true)
+        }
+        Kind::Other => Ok(false),
+    }
+}
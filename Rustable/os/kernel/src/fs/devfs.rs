@@ -0,0 +1,534 @@
+//! A file system whose files are devices.
+//!
+//! Devices are registered by name with `DevFs::add()` and appear as files in
+//! its root directory, which is the only directory. Opening a device returns a
+//! handle that reads and writes the device itself. Devices cannot be created,
+//! renamed or removed through the `FileSystem` interface.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path};
+use std::rc::Rc;
+use std::vec;
+
+use fat32::traits::{self, BlockDevice, Metadata as MetadataTrait};
+use super::vfs::{self, DirHandle, FileHandle, Inode, Metadata, Mount};
+
+/// A device that can be opened through a `DevFs`.
+pub trait Device {
+    /// Reads from the device into `buf` and returns the number of bytes read,
+    /// which is `0` at the end of the device. `offset` is the position of the
+    /// handle reading, which devices that are not `seekable()` ignore.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes `buf` to the device and returns the number of bytes written.
+    /// `offset` is as for `read_at()`.
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Whether the device is addressed by offset, like a disk, rather than
+    /// being a stream of bytes. Defaults to `false`.
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    /// The size of the device in bytes, or `0` if it has no known size.
+    fn size(&self) -> u64 {
+        0
+    }
+
+    /// Writes any buffered data to the device.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Discards writes and is always at its end.
+#[derive(Debug, Default)]
+pub struct Null;
+
+impl Device for Null {
+    fn read_at(&mut self, _offset: u64, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn write_at(&mut self, _offset: u64, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Discards writes and reads as an endless stream of zeroes.
+#[derive(Debug, Default)]
+pub struct Zero;
+
+impl Device for Zero {
+    fn read_at(&mut self, _offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&mut self, _offset: u64, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Reads as an endless stream of pseudo-random bytes from an xorshift64*
+/// generator. The bytes are not suitable for cryptography. Writes are mixed
+/// into the generator's state.
+#[derive(Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Returns a generator seeded with `seed`, such as the current time.
+    pub fn new(seed: u64) -> Random {
+        // xorshift never leaves the all-zero state.
+        Random { state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed } }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+}
+
+impl Device for Random {
+    fn read_at(&mut self, _offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        for chunk in buf.chunks_mut(8) {
+            let value = self.next();
+            for (i, b) in chunk.iter_mut().enumerate() {
+                *b = (value >> (i * 8)) as u8;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&mut self, _offset: u64, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.chunks(8) {
+            let value = chunk.iter().enumerate()
+                .fold(0, |value, (i, &b)| value | (b as u64) << (i * 8));
+            self.state ^= value;
+            if self.state == 0 {
+                self.state = Random::new(0).state;
+            }
+            self.next();
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Exposes the sectors of a block device as a seekable sequence of bytes.
+///
+/// Reads and writes go straight to the device. A FAT volume mounted from the
+/// same device caches sectors, so while one is, the device should be exposed
+/// with `read_only()`, and reads may miss changes not yet synced.
+#[derive(Debug)]
+pub struct Block<T: BlockDevice> {
+    device: T,
+    sectors: u64,
+    writable: bool,
+}
+
+impl<T: BlockDevice> Block<T> {
+    /// Exposes the `sectors` sectors of `device` for reading and writing.
+    pub fn new(device: T, sectors: u64) -> Block<T> {
+        Block { device, sectors, writable: true }
+    }
+
+    /// Exposes the `sectors` sectors of `device` for reading only. Writes
+    /// fail with `PermissionDenied`.
+    pub fn read_only(device: T, sectors: u64) -> Block<T> {
+        Block { device, sectors, writable: false }
+    }
+
+    /// Returns `len` limited to the bytes left on the device at `offset`.
+    fn clamp(&self, offset: u64, len: usize) -> usize {
+        let left = self.size().saturating_sub(offset);
+        ::std::cmp::min(len as u64, left) as usize
+    }
+}
+
+impl<T: BlockDevice> Device for Block<T> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.clamp(offset, buf.len());
+        let buf = &mut buf[..len];
+        let sector_size = self.device.sector_size();
+        let mut sector = vec![0u8; sector_size as usize];
+        let mut read = 0;
+        while read < buf.len() {
+            let position = offset + read as u64;
+            let start = (position % sector_size) as usize;
+            if let Err(e) = self.device.read_sector(position / sector_size, &mut sector) {
+                // A failure after some bytes were read: report what was read.
+                return if read > 0 { Ok(read) } else { Err(e) };
+            }
+            let len = ::std::cmp::min(buf.len() - read, sector.len() - start);
+            buf[read..read + len].copy_from_slice(&sector[start..start + len]);
+            read += len;
+        }
+        Ok(read)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read-only"));
+        }
+
+        let len = self.clamp(offset, buf.len());
+        let buf = &buf[..len];
+        let sector_size = self.device.sector_size();
+        let mut sector = vec![0u8; sector_size as usize];
+        let mut written = 0;
+        while written < buf.len() {
+            let position = offset + written as u64;
+            let n = position / sector_size;
+            let start = (position % sector_size) as usize;
+            let len = ::std::cmp::min(buf.len() - written, sector.len() - start);
+            let result = if len == sector.len() {
+                self.device.write_sector(n, &buf[written..written + len])
+            } else {
+                // Only part of the sector changes; keep the rest of it.
+                self.device.read_sector(n, &mut sector).and_then(|_| {
+                    sector[start..start + len].copy_from_slice(&buf[written..written + len]);
+                    self.device.write_sector(n, &sector)
+                })
+            };
+            if let Err(e) = result {
+                return if written > 0 { Ok(written) } else { Err(e) };
+            }
+            written += len;
+        }
+        Ok(written)
+    }
+
+    fn seekable(&self) -> bool {
+        true
+    }
+
+    fn size(&self) -> u64 {
+        self.sectors * self.device.sector_size()
+    }
+}
+
+type DeviceRef = Rc<RefCell<Box<Device>>>;
+
+/// The device file system.
+///
+/// Cloning a `DevFs` is cheap, and devices added to a clone appear in all of
+/// them.
+#[derive(Clone, Default)]
+pub struct DevFs {
+    devices: Rc<RefCell<BTreeMap<String, DeviceRef>>>,
+}
+
+impl fmt::Debug for DevFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.devices.borrow().keys().cloned().collect();
+        f.debug_struct("DevFs").field("devices", &names).finish()
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "devices cannot be changed")
+}
+
+impl DevFs {
+    /// Returns a file system with no devices.
+    pub fn new() -> DevFs {
+        DevFs::default()
+    }
+
+    /// Adds `device` under `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `name` is empty or contains `/`,
+    /// or `AlreadyExists` if a device is already named `name`.
+    pub fn add<D: Device + 'static>(&self, name: &str, device: D) -> io::Result<()> {
+        if name.is_empty() || name.contains('/') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid device name"));
+        }
+
+        let mut devices = self.devices.borrow_mut();
+        if devices.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "device exists"));
+        }
+        devices.insert(name.to_string(), Rc::new(RefCell::new(Box::new(device))));
+        Ok(())
+    }
+
+    fn entry(&self, name: &str, device: DeviceRef) -> Entry {
+        let mut metadata = Metadata::file(device.borrow().size());
+        metadata.set_system(true);
+        Entry { name: name.to_string(), metadata, node: EntryData::File(File::new(device)) }
+    }
+}
+
+/// A device open in a `DevFs`.
+pub struct File {
+    device: DeviceRef,
+    pointer: u64,
+}
+
+impl File {
+    fn new(device: DeviceRef) -> File {
+        File { device, pointer: 0 }
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File").field("pointer", &self.pointer).finish()
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut device = self.device.borrow_mut();
+        let read = device.read_at(self.pointer, buf)?;
+        if device.seekable() {
+            self.pointer += read as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut device = self.device.borrow_mut();
+        let written = device.write_at(self.pointer, buf)?;
+        if device.seekable() {
+            self.pointer += written as u64;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.borrow_mut().flush()
+    }
+}
+
+impl io::Seek for File {
+    /// Seeks to `pos` on a seekable device. Offsets from the end are relative
+    /// to the device's size. Seeking a stream device does nothing and returns
+    /// `0`.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let device = self.device.borrow();
+        if !device.seekable() {
+            return Ok(0);
+        }
+
+        let pointer = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => device.size() as i64 + offset,
+            SeekFrom::Current(offset) => self.pointer as i64 + offset,
+        };
+        if pointer < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Out of bounds"));
+        }
+        self.pointer = pointer as u64;
+        Ok(self.pointer)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.device.borrow_mut().flush()
+    }
+
+    fn size(&self) -> u64 {
+        self.device.borrow().size()
+    }
+
+    /// Does nothing, so that devices can be opened with `O_TRUNC`.
+    fn set_len(&mut self, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The root directory of a `DevFs`.
+#[derive(Debug)]
+pub struct Dir {
+    fs: DevFs,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    /// Returns the devices in the order of their names.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let entries: Vec<Entry> = self.fs.devices.borrow().iter()
+            .map(|(name, device)| self.fs.entry(name, device.clone()))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
+
+#[derive(Debug)]
+enum EntryData {
+    File(File),
+    Dir(Dir),
+}
+
+/// A device, or the root directory, of a `DevFs`.
+#[derive(Debug)]
+pub struct Entry {
+    name: String,
+    metadata: Metadata,
+    node: EntryData,
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Always returns an error of `PermissionDenied`.
+    fn set_metadata(&mut self, _metadata: Metadata) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match self.node {
+            EntryData::File(ref file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(ref dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            EntryData::File(file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(dir) => Some(dir),
+        }
+    }
+}
+
+/// `create_file()`, `create_dir()`, `rename()` and `remove()` return an error
+/// of `PermissionDenied` once their paths are found to be valid.
+impl<'a> traits::FileSystem for &'a DevFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+        }
+
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::ParentDir => {
+                    names.pop();
+                }
+                Component::Normal(name) => names.push(name),
+                _ => {}
+            }
+        }
+
+        match names.len() {
+            0 => Ok(Entry { name: String::new(), metadata: Metadata::dir(),
+                            node: EntryData::Dir(Dir { fs: self.clone() }) }),
+            1 => {
+                let name = names[0].to_str()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such device"))?;
+                let device = self.devices.borrow().get(name).cloned()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such device"))?;
+                Ok(self.entry(name, device))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "devices are not directories")),
+        }
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        match traits::FileSystem::open(self, path) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "device exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(read_only()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P, _parents: bool) -> io::Result<Dir> {
+        match traits::FileSystem::open(self, path) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "device exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(read_only()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        traits::FileSystem::open(self, from)?;
+        if traits::FileSystem::open(self, to).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "device exists"));
+        }
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, _children: bool) -> io::Result<()> {
+        traits::FileSystem::open(self, path)?;
+        Err(read_only())
+    }
+}
+
+impl Mount for DevFs {
+    fn open(&self, path: &Path) -> io::Result<Inode> {
+        vfs::open(self, path)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<Box<FileHandle>> {
+        vfs::create_file(self, path)
+    }
+
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirHandle>> {
+        vfs::create_dir(self, path, parents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+        traits::FileSystem::remove(self, path, children)
+    }
+
+    fn set_metadata(&self, path: &Path, metadata: &Metadata) -> io::Result<()> {
+        vfs::set_metadata(self, path, metadata)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        for device in self.devices.borrow().values() {
+            device.borrow_mut().flush()?;
+        }
+        Ok(())
+    }
+}
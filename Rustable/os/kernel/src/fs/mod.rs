@@ -1,17 +1,150 @@
+pub mod archive;
+pub mod devfs;
 pub mod sd;
+pub mod tmpfs;
+pub mod vfs;
 
-use std::io;
-use std::path::Path;
+#[cfg(test)]
+mod tests;
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::slice;
 
 use fat32::vfat::{self, Shared, VFat};
 pub use fat32::traits;
 
+use allocator::page::KADDR;
+use mm::pmm::{initrd_region, release_initrd};
 use mutex::Mutex;
+use self::devfs::{DevFs, Device};
 use self::sd::Sd;
+use self::tmpfs::TmpFs;
+use self::vfs::{Mount, Vfs};
+
+use console::{kprintln, CONSOLE};
+use pi::timer::current_time;
+
+/// The path the SD card's FAT volume is mounted at when there is no initial
+/// ramdisk.
+pub const SD_MOUNT_POINT: &str = "/";
+
+/// The path the SD card's FAT volume is mounted at when an initial ramdisk is
+/// the root.
+pub const SD_RAMDISK_MOUNT_POINT: &str = "/sd";
+
+/// The path an empty `TmpFs` is mounted at for scratch files.
+pub const TMP_MOUNT_POINT: &str = "/tmp";
+
+/// The path the `DevFs` holding the kernel's devices is mounted at.
+pub const DEV_MOUNT_POINT: &str = "/dev";
+
+pub struct FileSystem {
+    vfs: Mutex<Option<Vfs>>,
+    /// The SD card's volume and its mount point, which the shell can use
+    /// directly for FAT-only operations such as undeleting files.
+    sd: Mutex<Option<(&'static str, Shared<VFat>)>>,
+}
+
+/// Initializes the SD card. Returns it shared, for its volume and its device.
+fn init_sd() -> io::Result<Shared<Sd>> {
+    let sd = Sd::new().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+    kprintln!("sd initialized");
+    Ok(Shared::new(sd))
+}
+
+/// Mounts the FAT volume on the SD card `sd`.
+fn sd_volume(sd: &Shared<Sd>) -> io::Result<Shared<VFat>> {
+    let vfat = VFat::from(sd.clone())?;
+    kprintln!("vfat initialized");
+    if vfat.borrow().mounted_dirty() {
+        kprintln!("warning: file system was not cleanly unmounted and may be inconsistent");
+    }
+    Ok(vfat)
+}
 
-use console::kprintln;
+/// The byte that ends input from the console, typed as Ctrl-D.
+const EOT: u8 = 0x04;
 
-pub struct FileSystem(Mutex<Option<Shared<VFat>>>);
+/// The UART console as a device.
+///
+/// A read blocks until a line has been typed, echoing it, and returns it with
+/// a `\n` at the end. Ctrl-D ends the read early, and so reads nothing when
+/// typed at the start of a line.
+///
+/// Known limitation: a read spins on the UART inside the `read` system call
+/// rather than putting the process to sleep. Interrupts stay masked while a
+/// system call is handled, so nothing else runs, the timer included, until
+/// the line is complete.
+struct ConsoleDevice;
+
+impl Device for ConsoleDevice {
+    fn read_at(&mut self, _offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let byte = match CONSOLE.lock().read_byte() {
+                b'\r' => b'\n',
+                byte => byte,
+            };
+            if byte == EOT {
+                break;
+            }
+
+            if byte == b'\n' {
+                CONSOLE.lock().write_byte(b'\r');
+            }
+            CONSOLE.lock().write_byte(byte);
+            buf[read] = byte;
+            read += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_at(&mut self, _offset: u64, buf: &[u8]) -> io::Result<usize> {
+        CONSOLE.lock().write(buf)
+    }
+}
+
+/// Returns the devices mounted at `DEV_MOUNT_POINT`: `console`, `null`,
+/// `zero`, `random`, and `sd` if the SD card is available. While the card's
+/// volume is mounted (`mounted`), `sd` is read-only to keep it from bypassing
+/// the volume's sector cache.
+fn devices(sd: Option<Shared<Sd>>, mounted: bool) -> DevFs {
+    let dev = DevFs::new();
+    dev.add("console", ConsoleDevice).expect("Add console device");
+    dev.add("null", devfs::Null).expect("Add null device");
+    dev.add("zero", devfs::Zero).expect("Add zero device");
+    dev.add("random", devfs::Random::new(current_time())).expect("Add random device");
+    if let Some(sd) = sd {
+        let sectors = sd.borrow().sectors();
+        let block = if mounted {
+            devfs::Block::read_only(sd, sectors)
+        } else {
+            devfs::Block::new(sd, sectors)
+        };
+        dev.add("sd", block).expect("Add SD card device");
+    }
+    dev
+}
+
+/// Unpacks the initial ramdisk the bootloader or firmware loaded, if there is
+/// one, into a new `TmpFs`, then frees the memory it was loaded in.
+fn ramdisk() -> Option<TmpFs> {
+    let (start, end) = initrd_region()?;
+    let root = TmpFs::new();
+    {
+        let data = unsafe { slice::from_raw_parts(KADDR(start) as *const u8, end - start) };
+        match archive::unpack(data, &root) {
+            Ok(entries) => kprintln!("initrd: unpacked {} entries", entries),
+            Err(e) => kprintln!("warning: failed to unpack initrd: {:?}", e),
+        }
+    }
+    release_initrd();
+    Some(root)
+}
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -19,60 +152,140 @@ impl FileSystem {
     /// The file system must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem { vfs: Mutex::new(None), sd: Mutex::new(None) }
     }
 
-    /// Initializes the file system.
+    /// Initializes the file system, mounts an empty `TmpFs` at
+    /// `TMP_MOUNT_POINT` and the devices at `DEV_MOUNT_POINT`.
+    ///
+    /// If the bootloader or firmware loaded an initial ramdisk, it is unpacked
+    /// into a `TmpFs` mounted at `/`, and the SD card is mounted at
+    /// `SD_RAMDISK_MOUNT_POINT` if it can be. Otherwise the SD card is mounted
+    /// at `SD_MOUNT_POINT`. The card is added to the devices whenever it
+    /// initialized, even if its volume could not be mounted.
     ///
     /// # Panics
     ///
-    /// Panics if the underlying disk or file sytem failed to initialize.
+    /// Panics if there is no initial ramdisk and the underlying disk or file
+    /// sytem failed to initialize.
     pub fn initialize(&self) {
         kprintln!("fs initializing...");
-        let sd = Sd::new().expect("Init Sd");
-        kprintln!("sd initialized");
-        let vfat = VFat::from(sd).expect("Create VFat");
-        kprintln!("vfat initialized");
-        *self.0.lock() = Some(vfat);
-        // kprintln!("haha");
-    }
-
-    fn get_vfat(&self) -> io::Result<Shared<VFat>> {
-        match *self.0.lock() {
-            Some(ref vfat) => Ok(vfat.clone()),
-            None => Err(io::Error::new(io::ErrorKind::NotConnected,
-                                       "Not initialized")),
+        let mut vfs = Vfs::new();
+        let (mount_point, sd, vfat) = match ramdisk() {
+            Some(root) => {
+                vfs.mount("/", root).expect("Mount initrd");
+                let sd = match init_sd() {
+                    Ok(sd) => Some(sd),
+                    Err(e) => {
+                        kprintln!("warning: no SD card: {:?}", e);
+                        None
+                    }
+                };
+                let vfat = match sd.as_ref().map(sd_volume) {
+                    Some(Ok(vfat)) => Some(vfat),
+                    Some(Err(e)) => {
+                        kprintln!("warning: no SD card file system: {:?}", e);
+                        None
+                    }
+                    None => None,
+                };
+                (SD_RAMDISK_MOUNT_POINT, sd, vfat)
+            }
+            None => {
+                let sd = init_sd().expect("Init SD card");
+                let vfat = sd_volume(&sd).expect("Init SD card file system");
+                (SD_MOUNT_POINT, Some(sd), Some(vfat))
+            }
+        };
+
+        let sd_mount = vfat.map(|vfat| (mount_point, vfat));
+        if let Some((mount_point, ref vfat)) = sd_mount {
+            vfs.mount(mount_point, vfat.clone()).expect("Mount SD card");
+        }
+        vfs.mount(TMP_MOUNT_POINT, TmpFs::new()).expect("Mount tmpfs");
+        vfs.mount(DEV_MOUNT_POINT, devices(sd, sd_mount.is_some())).expect("Mount devfs");
+        *self.vfs.lock() = Some(vfs);
+        *self.sd.lock() = sd_mount;
+    }
+
+    /// Writes every change back to the mounted file systems, and marks the
+    /// SD card clean.
+    pub fn sync(&self) -> io::Result<()> {
+        self.vfs()?.sync()
+    }
+
+    /// Mounts `fs` at the absolute path `path`. See `Vfs::mount()`.
+    pub fn mount<P: AsRef<Path>, F: Mount + 'static>(&self, path: P, fs: F) -> io::Result<()> {
+        match *self.vfs.lock() {
+            Some(ref mut vfs) => vfs.mount(path, fs),
+            None => Err(FileSystem::not_initialized()),
+        }
+    }
+
+    /// Unmounts the file system mounted at `path`. See `Vfs::unmount()`.
+    pub fn unmount<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        match *self.vfs.lock() {
+            Some(ref mut vfs) => vfs.unmount(path),
+            None => Err(FileSystem::not_initialized()),
         }
     }
+
+    /// Returns the paths file systems are mounted at, in sorted order.
+    pub fn mount_points(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self.vfs()?.mount_points())
+    }
+
+    /// Opens the directory at `path` on the SD card's volume.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not on the SD card, in
+    /// addition to the errors of `open_dir()`.
+    pub fn open_sd_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<vfat::Dir> {
+        let (mount_point, path) = self.vfs()?.resolve(path)?;
+        let (sd_mount_point, sd) = self.sd.lock().clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no SD card"))?;
+        if mount_point != Path::new(sd_mount_point) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not on the SD card"));
+        }
+        traits::FileSystem::open_dir(&sd, path)
+    }
+
+    fn vfs(&self) -> io::Result<Vfs> {
+        self.vfs.lock().clone().ok_or_else(FileSystem::not_initialized)
+    }
+
+    fn not_initialized() -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, "Not initialized")
+    }
 }
 
-// FIXME: Implement `fat32::traits::FileSystem` for a useful type.
 impl<'a> traits::FileSystem for &'a FileSystem {
-    type File = vfat::File;
-    type Dir = vfat::Dir;
-    type Entry = vfat::Entry;
+    type File = vfs::File;
+    type Dir = vfs::Dir;
+    type Entry = vfs::Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        self.get_vfat()?.open(path)
+        traits::FileSystem::open(&self.vfs()?, path)
     }
 
     fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
-        self.get_vfat()?.create_file(path)
+        traits::FileSystem::create_file(&self.vfs()?, path)
     }
 
     fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        self.get_vfat()?.create_dir(path, parents)
+        traits::FileSystem::create_dir(&self.vfs()?, path, parents)
     }
 
     fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        self.get_vfat()?.rename(from, to)
+        traits::FileSystem::rename(&self.vfs()?, from, to)
     }
 
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
-        self.get_vfat()?.remove(path, children)
+        traits::FileSystem::remove(&self.vfs()?, path, children)
     }
-}
\ No newline at end of file
+}
@@ -1,65 +1,45 @@
-use std::{i32, io};
+use std::{u32, io};
 use fat32::traits::BlockDevice;
-use pi::timer::spin_sleep_us;
+use pi::emmc::{Emmc, BLOCK_SIZE};
 
-extern "C" {
-    /// A global representing the last SD controller error that occured.
-    static sd_err: i64;
-
-    /// Initializes the SD card controller.
-    ///
-    /// Returns 0 if initialization is successful. If initialization fails,
-    /// returns -1 if a timeout occured, or -2 if an error sending commands to
-    /// the SD controller occured.
-    fn sd_init() -> i32;
-
-    /// Reads sector `n` (512 bytes) from the SD card and writes it to `buffer`.
-    /// It is undefined behavior if `buffer` does not point to at least 512
-    /// bytes of memory.
-    ///
-    /// On success, returns the number of bytes read: a positive number.
-    ///
-    /// On error, returns 0. The true error code is stored in the `sd_err`
-    /// global. `sd_err` will be set to -1 if a timeout occured or -2 if an
-    /// error sending commands to the SD controller occured. Other error codes
-    /// are also possible but defined only as being less than zero.
-    fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
-}
-
-// FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
-// The `wait_micros` C signature is: `void wait_micros(unsigned int);`
-
-#[no_mangle]
-pub fn wait_micros(us: u32) {
-    spin_sleep_us(us as u64 * 100);
-}
-
-#[derive(Debug)]
-pub enum Error {
-    TimeOut,
-    CommandError,
-    Unknown
-}
+pub use pi::emmc::Error;
 
 /// A handle to an SD card controller.
 #[derive(Debug)]
-pub struct Sd;
+pub struct Sd {
+    emmc: Emmc,
+}
 
 impl Sd {
-    /// Initializes the SD card controller and returns a handle to it.
+    /// Initializes the SD card controller and the card and returns a handle
+    /// to it.
     pub fn new() -> Result<Sd, Error> {
-        match unsafe { sd_init() } {
-            0 => Ok(Sd{}),
-            err => Err(Sd::handle_error(err as i64))
+        Ok(Sd { emmc: Emmc::new()? })
+    }
+
+    /// The number of sectors on the card, or `0` if it is unknown.
+    pub fn sectors(&self) -> u64 {
+        self.emmc.blocks()
+    }
+
+    pub(super) fn io_error(error: Error) -> io::Error {
+        match error {
+            Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, "SD timeout"),
+            Error::InvalidBuffer => {
+                io::Error::new(io::ErrorKind::InvalidInput, "buf not a multiple of 512")
+            }
+            Error::AddressOutOfRange => {
+                io::Error::new(io::ErrorKind::InvalidInput, "n out of range")
+            }
+            _ => io::Error::new(io::ErrorKind::Other, "Driver error"),
         }
     }
 
-    fn handle_error(code: i64) -> Error {
-        match code {
-            -1 => Error::TimeOut,
-            -2 => Error::CommandError,
-            -3 => Error::Unknown,
-            _ => {panic!("Unexpected SD error")}
+    fn block(n: u64) -> io::Result<u32> {
+        if n > u32::MAX as u64 {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "n out of range"))
+        } else {
+            Ok(n as u32)
         }
     }
 }
@@ -71,35 +51,73 @@ impl BlockDevice for Sd {
     /// # Errors
     ///
     /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    /// sector `n` cannot be addressed.
     ///
     /// An error of kind `TimedOut` is returned if a timeout occurs while
     /// reading from the SD card.
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() < 512 {
+        if buf.len() < BLOCK_SIZE {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "buf too small"))
-        } else if n > i32::MAX as u64 {
-            Err(io::Error::new(io::ErrorKind::InvalidInput, "n out of range"))
         } else {
-            let bytes = unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) };
+            self.read_sectors(n, &mut buf[..BLOCK_SIZE])
+        }
+    }
 
-            if bytes == 0 {
-                let error = Sd::handle_error(unsafe { sd_err });
-                match error {
-                    Error::TimeOut => Err(io::Error::new(
-                        io::ErrorKind::TimedOut, "Read timeout")),
-                    _ => Err(io::Error::new(io::ErrorKind::Other,
-                                            "Driver error")),
-                }
-            } else {
-                Ok(bytes as usize)
-            }
+    /// Writes sector `n` on the SD card from the first 512 bytes of `buf`. On
+    /// success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// sector `n` cannot be addressed.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < BLOCK_SIZE {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "buf too small"))
+        } else {
+            self.write_sectors(n, &buf[..BLOCK_SIZE])
         }
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Reads `buf.len() / 512` consecutive sectors starting at sector `n`
+    /// into `buf` with a single multiple block read (CMD18). On success, the
+    /// number of bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len()` is not a
+    /// non-zero multiple of 512 or if the sectors cannot be addressed.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// reading from the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.emmc.read_blocks(Sd::block(n)?, buf).map_err(Sd::io_error)?;
+        Ok(buf.len())
+    }
+
+    /// Writes `buf.len() / 512` consecutive sectors starting at sector `n`
+    /// from `buf` with a single multiple block write (CMD25). On success, the
+    /// number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len()` is not a
+    /// non-zero multiple of 512 or if the sectors cannot be addressed.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.emmc.write_blocks(Sd::block(n)?, buf).map_err(Sd::io_error)?;
+        Ok(buf.len())
     }
 }
@@ -0,0 +1,387 @@
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use fat32::traits::{self, Dir, Entry, File, FileSystem, Metadata, Timestamp as TimestampTrait};
+use fat32::vfat::{Shared, VFat};
+use fs::archive::unpack;
+use fs::devfs::{Block, DevFs, Null, Random, Zero};
+use fs::tmpfs::TmpFs;
+use fs::vfs::{Timestamp, Vfs};
+
+/// Returns the empty FAT32 volume of a freshly formatted in-memory disk.
+fn formatted_vfat() -> Shared<VFat> {
+    let sectors = 40 * 2048;
+    let device = Shared::new(Cursor::new(vec![0u8; sectors * 512]));
+    ::fat32::format::format(device.clone(), sectors as u64).unwrap();
+    VFat::from(device).unwrap()
+}
+
+/// Returns the sorted names in the directory at `path`.
+fn names(vfs: &Vfs, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = vfs.open_dir(path).unwrap().entries().unwrap()
+        .map(|e| e.name().to_string()).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_vfs_mounts() {
+    let root = formatted_vfat();
+    let other = formatted_vfat();
+    root.create_file("/a.txt").unwrap().write_all(b"root").unwrap();
+    other.create_file("/b.txt").unwrap().write_all(b"other").unwrap();
+
+    let mut vfs = Vfs::new();
+    assert!(vfs.open("/").is_err());
+    vfs.mount("/", root.clone()).unwrap();
+    vfs.mount("/mnt/disk", other.clone()).unwrap();
+    assert!(vfs.mount("/mnt/disk/", other.clone()).is_err());
+
+    let mut s = String::new();
+    vfs.open_file("/a.txt").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "root");
+    s.clear();
+    vfs.open_file("/mnt/./disk/../disk/b.txt").unwrap().read_to_string(&mut s).unwrap();
+    assert_eq!(s, "other");
+
+    assert_eq!(names(&vfs, "/"), vec!["a.txt", "mnt"]);
+    assert_eq!(names(&vfs, "/mnt"), vec!["disk"]);
+    assert_eq!(names(&vfs, "/mnt/disk"), vec!["b.txt"]);
+    assert_eq!(vfs.open("/mnt/disk").unwrap().name(), "disk");
+    assert!(vfs.open("/mnt/disk").unwrap().is_dir());
+
+    let mut f = vfs.create_file("/mnt/disk/c.txt").unwrap();
+    f.write_all(b"hello").unwrap();
+    assert_eq!(f.size(), 5);
+    f.set_len(2).unwrap();
+    assert!(other.open_file("/c.txt").is_ok());
+    assert!(root.open_file("/mnt/disk/c.txt").is_err());
+
+    vfs.create_dir("/mnt/disk/sub", false).unwrap();
+    assert!(vfs.rename("/mnt/disk/c.txt", "/c.txt").is_err());
+    vfs.rename("/mnt/disk/c.txt", "/mnt/disk/sub/d.txt").unwrap();
+    assert_eq!(names(&vfs, "/mnt/disk/sub"), vec![".", "..", "d.txt"]);
+    assert!(vfs.remove("/mnt/disk", true).is_err());
+
+    let mut e = vfs.open("/mnt/disk/sub/d.txt").unwrap();
+    let mut m = e.metadata().clone();
+    m.set_read_only(true);
+    e.set_metadata(m).unwrap();
+    assert!(e.metadata().read_only());
+    assert!(other.open("/sub/d.txt").unwrap().metadata().read_only());
+
+    // Timestamps are converted back to the mounted file system's.
+    let noon = |year| traits::Timestamp::new(year, 6, 15, 12, 30, 10).unwrap();
+    let mut m = e.metadata().clone();
+    m.set_modified(noon(2018));
+    e.set_metadata(m).unwrap();
+    let modified = other.open("/sub/d.txt").unwrap().metadata().modified();
+    assert_eq!(Timestamp::from_timestamp(modified), noon(2018));
+    let mut m = e.metadata().clone();
+    m.set_created(noon(1970));
+    assert_eq!(e.set_metadata(m).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(other.open("/sub/d.txt").unwrap().metadata().created().year(), 1980);
+
+    let found: Vec<_> = vfs.glob("/**/*.txt").unwrap().map(|e| e.unwrap().path).collect();
+    assert!(found.contains(&Path::new("/mnt/disk/sub/d.txt").to_path_buf()), "{:?}", found);
+
+    vfs.sync().unwrap();
+    assert_eq!(vfs.resolve("/mnt/disk/x").unwrap(), (Path::new("/mnt/disk").into(), Path::new("/x").into()));
+    assert!(vfs.unmount("/").is_err());
+    vfs.unmount("/mnt/disk").unwrap();
+    assert!(vfs.open("/mnt").is_err());
+    assert_eq!(vfs.mount_points(), vec![Path::new("/").to_path_buf()]);
+}
+
+#[test]
+fn test_tmpfs() {
+    let tmp = TmpFs::new();
+    let fs = &tmp;
+
+    fs.create_dir("/a/b", true).unwrap();
+    let mut file = fs.create_file("/a/b/hello.txt").unwrap();
+    file.write_all(b"hello, world").unwrap();
+    assert_eq!(file.size(), 12);
+    assert_eq!(fs.create_file("/a/b/hello.txt").unwrap_err().kind(),
+               io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.create_file("/missing/x").unwrap_err().kind(),
+               io::ErrorKind::InvalidInput);
+    assert_eq!(fs.create_file("relative").unwrap_err().kind(),
+               io::ErrorKind::InvalidInput);
+
+    // Names are case-sensitive, and `.` and `..` resolve lexically.
+    assert_eq!(fs.open("/a/b/HELLO.TXT").unwrap_err().kind(), io::ErrorKind::NotFound);
+    let entry = fs.open("/a/./b/../b/hello.txt").unwrap();
+    assert_eq!(entry.name(), "hello.txt");
+    assert_eq!(entry.metadata().size(), 12);
+
+    // Writes at an offset overwrite and extend, and seeking past the end fails.
+    let mut file = entry.into_file().unwrap();
+    file.seek(SeekFrom::Start(7)).unwrap();
+    file.write_all(b"there!").unwrap();
+    assert!(file.seek(SeekFrom::End(1)).is_err());
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello, there!");
+
+    file.set_len(5).unwrap();
+    file.set_len(7).unwrap();
+    let mut bytes = Vec::new();
+    fs.open_file("/a/b/hello.txt").unwrap().read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, b"hello\0\0");
+
+    // Listings are sorted by name.
+    fs.create_file("/a/b/Z").unwrap();
+    fs.create_dir("/a/b/c", false).unwrap();
+    let names: Vec<String> = fs.open_dir("/a/b").unwrap().entries().unwrap()
+        .map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec!["Z", "c", "hello.txt"]);
+
+    // Renames move entries between directories, and open files follow them.
+    let mut open = fs.open_file("/a/b/hello.txt").unwrap();
+    fs.rename("/a/b/hello.txt", "/a/moved").unwrap();
+    assert_eq!(fs.open("/a/b/hello.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+    open.write_all(b"J").unwrap();
+    let mut bytes = Vec::new();
+    fs.open_file("/a/moved").unwrap().read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, b"Jello\0\0");
+    assert_eq!(fs.rename("/a/moved", "/a/b/Z").unwrap_err().kind(),
+               io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.rename("/a/nope", "/a/x").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.rename("/a", "/a/b/a").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    fs.rename("/a/b", "/b").unwrap();
+    assert!(fs.open_dir("/b/c").is_ok());
+
+    // Metadata is kept by the file system.
+    let mut entry = fs.open("/a/moved").unwrap();
+    let mut metadata = entry.metadata().clone();
+    metadata.set_read_only(true);
+    entry.set_metadata(metadata).unwrap();
+    assert!(fs.stat("/a/moved").unwrap().read_only());
+    assert!(fs.stat("/b").unwrap().directory());
+
+    // Removal.
+    assert_eq!(fs.remove("/b", false).unwrap_err().kind(), io::ErrorKind::Other);
+    fs.remove("/b/Z", false).unwrap();
+    fs.remove("/b", true).unwrap();
+    assert_eq!(fs.remove("/b", true).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(fs.remove("/", true).is_err());
+    let names: Vec<String> = fs.open_dir("/").unwrap().entries().unwrap()
+        .map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec!["a"]);
+}
+
+#[test]
+fn test_tmpfs_mounted() {
+    let mut vfs = Vfs::new();
+    vfs.mount("/", formatted_vfat()).unwrap();
+    vfs.mount("/tmp", TmpFs::new()).unwrap();
+
+    (&vfs).create_file("/tmp/scratch").unwrap().write_all(b"scratch").unwrap();
+    (&vfs).create_dir("/tmp/dir", false).unwrap();
+    assert_eq!(names(&vfs, "/tmp"), vec!["dir", "scratch"]);
+    assert!(names(&vfs, "/").contains(&"tmp".to_string()));
+    assert!((&vfs).open("/scratch").is_err());
+
+    (&vfs).rename("/tmp/scratch", "/tmp/dir/scratch").unwrap();
+    let mut contents = String::new();
+    (&vfs).open_file("/tmp/dir/scratch").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "scratch");
+
+    let mut entry = (&vfs).open("/tmp/dir/scratch").unwrap();
+    let mut metadata = entry.metadata().clone();
+    metadata.set_hidden(true);
+    entry.set_metadata(metadata).unwrap();
+    assert!(entry.metadata().hidden());
+
+    (&vfs).remove("/tmp/dir", true).unwrap();
+    assert!(names(&vfs, "/tmp").is_empty());
+    vfs.unmount("/tmp").unwrap();
+}
+
+/// Returns a ustar header and padded data for an entry named `name`.
+fn tar_entry(name: &str, typeflag: u8, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    for b in header[148..156].iter_mut() {
+        *b = b' ';
+    }
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    let padded = (entry.len() + 511) / 512 * 512;
+    entry.resize(padded, 0);
+    entry
+}
+
+/// Returns a `newc` cpio header, name and padded data for an entry.
+fn cpio_entry(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+    let mut entry = format!("070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+                            0, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name.len() + 1, 0)
+        .into_bytes();
+    entry.extend_from_slice(name.as_bytes());
+    entry.push(0);
+    let padded = (entry.len() + 3) / 4 * 4;
+    entry.resize(padded, 0);
+    entry.extend_from_slice(data);
+    let padded = (entry.len() + 3) / 4 * 4;
+    entry.resize(padded, 0);
+    entry
+}
+
+fn read_to_string(fs: &TmpFs, path: &str) -> String {
+    let mut contents = String::new();
+    fs.open_file(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn test_unpack_tar() {
+    let mut tar = Vec::new();
+    tar.extend(tar_entry("./", b'5', b""));
+    tar.extend(tar_entry("./bin/", b'5', b""));
+    tar.extend(tar_entry("./bin/init", b'0', &[0x7f; 700]));
+    tar.extend(tar_entry("./etc/motd", b'0', b"hello from the ramdisk\n"));
+    tar.extend(tar_entry("./etc/link", b'2', b""));
+    tar.extend(tar_entry("./etc/motd", b'0', b"replaced\n"));
+    tar.extend(vec![0u8; 1024]);
+    // XMODEM pads the archive it receives.
+    tar.extend(vec![0x1a; 100]);
+
+    let root = TmpFs::new();
+    assert_eq!(unpack(&tar, &root).unwrap(), 4);
+    assert_eq!((&root).stat("/bin/init").unwrap().size(), 700);
+    assert_eq!(read_to_string(&root, "/etc/motd"), "replaced\n");
+    assert!((&root).open("/etc/link").is_err());
+
+    let mut corrupt = tar.clone();
+    corrupt[600] ^= 1;
+    assert_eq!(unpack(&corrupt, &root).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(unpack(&tar[..1000], &TmpFs::new()).unwrap_err().kind(),
+               io::ErrorKind::InvalidData);
+    assert_eq!(unpack(b"not an archive", &root).unwrap_err().kind(),
+               io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_unpack_cpio() {
+    let mut cpio = Vec::new();
+    cpio.extend(cpio_entry(".", 0o040755, b""));
+    cpio.extend(cpio_entry("bin", 0o040755, b""));
+    cpio.extend(cpio_entry("bin/sh", 0o100755, b"#!"));
+    cpio.extend(cpio_entry("dev/console", 0o020600, b""));
+    cpio.extend(cpio_entry("a/b/c.txt", 0o100644, b"nested"));
+    cpio.extend(cpio_entry("TRAILER!!!", 0, b""));
+
+    let root = TmpFs::new();
+    assert_eq!(unpack(&cpio, &root).unwrap(), 3);
+    assert_eq!(read_to_string(&root, "/bin/sh"), "#!");
+    assert_eq!(read_to_string(&root, "/a/b/c.txt"), "nested");
+    assert!((&root).open("/dev/console").is_err());
+
+    let truncated = &cpio[..cpio.len() - 130];
+    assert_eq!(unpack(truncated, &TmpFs::new()).unwrap_err().kind(),
+               io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_devfs() {
+    let dev = DevFs::new();
+    dev.add("null", Null).unwrap();
+    dev.add("zero", Zero).unwrap();
+    dev.add("random", Random::new(42)).unwrap();
+    dev.add("disk", Block::new(Cursor::new(vec![0u8; 4 * 512]), 4)).unwrap();
+    dev.add("ro", Block::read_only(Cursor::new(vec![5u8; 512]), 1)).unwrap();
+    assert_eq!(dev.add("null", Null).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(dev.add("a/b", Null).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let fs = &dev;
+    let devices: Vec<String> = fs.open_dir("/").unwrap().entries().unwrap()
+        .map(|e| e.name().to_string()).collect();
+    assert_eq!(devices, vec!["disk", "null", "random", "ro", "zero"]);
+    assert!(fs.stat("/zero").unwrap().system());
+    assert_eq!(fs.open("/nope").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open("/null/x").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let mut bytes = Vec::new();
+    let mut null = fs.open_file("/null").unwrap();
+    null.write_all(b"discarded").unwrap();
+    assert_eq!(null.read_to_end(&mut bytes).unwrap(), 0);
+
+    let mut buf = [1u8; 100];
+    fs.open_file("/zero").unwrap().read_exact(&mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0));
+
+    let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+    let mut random = fs.open_file("/random").unwrap();
+    random.read_exact(&mut a).unwrap();
+    random.read_exact(&mut b).unwrap();
+    assert!(a != b && a.iter().any(|&x| x != 0));
+
+    // Block devices are addressed by offset, across sector boundaries.
+    let mut disk = fs.open_file("/disk").unwrap();
+    disk.seek(SeekFrom::Start(500)).unwrap();
+    disk.write_all(&[7u8; 30]).unwrap();
+    assert_eq!(disk.seek(SeekFrom::Current(0)).unwrap(), 530);
+    let mut buf = [0u8; 40];
+    disk.seek(SeekFrom::Start(495)).unwrap();
+    disk.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..5], &[0u8; 5]);
+    assert_eq!(&buf[5..35], &[7u8; 30][..]);
+    assert_eq!(&buf[35..], &[0u8; 5]);
+    disk.seek(SeekFrom::Start(4 * 512 - 10)).unwrap();
+    let mut rest = Vec::new();
+    assert_eq!(disk.read_to_end(&mut rest).unwrap(), 10);
+    assert_eq!(disk.seek(SeekFrom::End(-4)).unwrap(), 4 * 512 - 4);
+    assert_eq!(disk.write(&[9u8; 8]).unwrap(), 4);
+    assert_eq!(disk.write(&[9u8; 8]).unwrap(), 0);
+    assert_eq!(fs.stat("/disk").unwrap().size(), 4 * 512);
+
+    let mut ro = fs.open_file("/ro").unwrap();
+    assert_eq!(ro.write(&[1u8]).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    let mut contents = Vec::new();
+    assert_eq!(ro.read_to_end(&mut contents).unwrap(), 512);
+    assert!(contents.iter().all(|&b| b == 5));
+
+    assert_eq!(fs.create_file("/new").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.remove("/null", false).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.remove("/nope", false).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.rename("/null", "/void").unwrap_err().kind(),
+               io::ErrorKind::PermissionDenied);
+
+    // Mounted, devices are opened by path like any other file.
+    let mut vfs = Vfs::new();
+    vfs.mount("/", TmpFs::new()).unwrap();
+    vfs.mount("/dev", dev.clone()).unwrap();
+    dev.add("late", Zero).unwrap();
+    assert!(names(&vfs, "/dev").contains(&"late".to_string()));
+    let mut buf = [1u8; 8];
+    (&vfs).open_file("/dev/zero").unwrap().read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [0u8; 8]);
+    let mut entry = (&vfs).open("/dev/null").unwrap();
+    let metadata = entry.metadata().clone();
+    assert_eq!(entry.set_metadata(metadata).unwrap_err().kind(),
+               io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_sd_error_mapping() {
+    use fs::sd::{Error, Sd};
+
+    let cases = [(Error::Timeout, io::ErrorKind::TimedOut),
+                 (Error::InvalidBuffer, io::ErrorKind::InvalidInput),
+                 (Error::AddressOutOfRange, io::ErrorKind::InvalidInput),
+                 (Error::Controller(1 << 17), io::ErrorKind::Other),
+                 (Error::CardStatus(1 << 31), io::ErrorKind::Other),
+                 (Error::UnsupportedCard, io::ErrorKind::Other)];
+    for &(error, kind) in cases.iter() {
+        assert_eq!(Sd::io_error(error).kind(), kind, "{:?}", error);
+    }
+}
@@ -0,0 +1,517 @@
+//! A file system that keeps its files in memory.
+//!
+//! Nothing is written to a device: the contents last until the last handle to
+//! the file system is dropped. This suits scratch files, files shared between
+//! processes, and tests. Unlike FAT, names are case-sensitive and may contain
+//! any character but `/`. There is no clock, so every timestamp is
+//! `Timestamp::EPOCH` unless set through `Entry::set_metadata()`.
+
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path};
+use std::rc::Rc;
+use std::vec;
+
+use fat32::traits;
+use super::vfs::{self, DirHandle, FileHandle, Inode, Metadata, Mount};
+
+type NodeRef = Rc<RefCell<Node>>;
+
+enum Data {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, NodeRef>),
+}
+
+/// A file or directory. A node stays alive while a directory or an open
+/// handle refers to it, so a removed file can still be read and written
+/// through the handles opened before.
+struct Node {
+    /// The attributes and timestamps. The size and directory flag are derived
+    /// from `data` instead.
+    attributes: Metadata,
+    data: Data,
+}
+
+impl Node {
+    fn new(data: Data) -> NodeRef {
+        Rc::new(RefCell::new(Node { attributes: Metadata::default(), data }))
+    }
+
+    fn metadata(&self) -> Metadata {
+        let mut metadata = match self.data {
+            Data::File(ref bytes) => Metadata::file(bytes.len() as u64),
+            Data::Dir(_) => Metadata::dir(),
+        };
+        copy_metadata(&self.attributes, &mut metadata);
+        metadata
+    }
+
+    fn is_dir(&self) -> bool {
+        match self.data {
+            Data::File(_) => false,
+            Data::Dir(_) => true,
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Node").field("metadata", &self.metadata()).finish()
+    }
+}
+
+/// Sets the attributes and timestamps of `to` to those of `from`.
+fn copy_metadata(from: &Metadata, to: &mut Metadata) {
+    from.apply(to).expect("a vfs::Timestamp converts to itself");
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Splits `path` into its names, resolving `.` and `..` lexically.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute or not UTF-8.
+fn components(path: &Path) -> io::Result<Vec<&str>> {
+    if !path.is_absolute() {
+        return Err(invalid("path is not absolute"));
+    }
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                names.pop();
+            }
+            Component::Normal(name) => {
+                names.push(name.to_str().ok_or_else(|| invalid("Invalid UTF-8"))?);
+            }
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+/// A RAM-backed file system.
+///
+/// Cloning a `TmpFs` is cheap and the clones share the same files.
+#[derive(Clone)]
+pub struct TmpFs {
+    root: NodeRef,
+}
+
+impl fmt::Debug for TmpFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TmpFs").finish()
+    }
+}
+
+impl TmpFs {
+    /// Returns a file system holding only an empty root directory.
+    pub fn new() -> TmpFs {
+        TmpFs { root: Node::new(Data::Dir(BTreeMap::new())) }
+    }
+
+    /// Returns the node named by `names`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if any name but the last does not
+    /// refer to an existing directory, or `NotFound` if the last does not
+    /// exist.
+    fn lookup(&self, names: &[&str]) -> io::Result<NodeRef> {
+        let mut node = self.root.clone();
+        for (i, name) in names.iter().enumerate() {
+            let child = match node.borrow().data {
+                Data::Dir(ref children) => children.get(*name).cloned(),
+                Data::File(_) => return Err(invalid("not a directory")),
+            };
+            node = match child {
+                Some(child) => child,
+                None if i + 1 == names.len() => {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "no such entry"));
+                }
+                None => return Err(invalid("parent is not an existing directory")),
+            };
+        }
+        Ok(node)
+    }
+
+    /// Splits `path` into the directory holding its last component and the
+    /// name of that component.
+    ///
+    /// # Errors
+    ///
+    /// If `path` is not absolute, names the root, or any component but the last
+    /// does not refer to an existing directory, an error kind of
+    /// `InvalidInput` is returned.
+    fn parent<'p>(&self, path: &'p Path) -> io::Result<(NodeRef, &'p str)> {
+        let mut names = components(path)?;
+        let name = names.pop().ok_or_else(|| invalid("path has no file name"))?;
+        let parent = self.lookup(&names)
+            .map_err(|_| invalid("parent is not an existing directory"))?;
+        if !parent.borrow().is_dir() {
+            return Err(invalid("parent is not an existing directory"));
+        }
+        Ok((parent, name))
+    }
+
+    /// Adds `node` to the directory at the parent of `path`.
+    fn insert(&self, path: &Path, node: NodeRef) -> io::Result<()> {
+        let (parent, name) = self.parent(path)?;
+        let mut parent = parent.borrow_mut();
+        match parent.data {
+            Data::Dir(ref mut children) => {
+                if children.contains_key(name) {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry exists"));
+                }
+                children.insert(name.to_string(), node);
+                Ok(())
+            }
+            Data::File(_) => unreachable!(),
+        }
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> TmpFs {
+        TmpFs::new()
+    }
+}
+
+/// A file open in a `TmpFs`.
+pub struct File {
+    node: NodeRef,
+    pointer: u64,
+}
+
+impl File {
+    fn new(node: NodeRef) -> File {
+        File { node, pointer: 0 }
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File")
+            .field("size", &traits::File::size(self))
+            .field("pointer", &self.pointer)
+            .finish()
+    }
+}
+
+/// Calls `f` with the bytes of the file `node`.
+fn with_bytes<T, F: FnOnce(&mut Vec<u8>) -> T>(node: &NodeRef, f: F) -> T {
+    match node.borrow_mut().data {
+        Data::File(ref mut bytes) => f(bytes),
+        Data::Dir(_) => unreachable!(),
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pointer = self.pointer as usize;
+        let read = with_bytes(&self.node, |bytes| {
+            // Another handle may have shortened the file.
+            let start = min(pointer, bytes.len());
+            let read = min(buf.len(), bytes.len() - start);
+            buf[..read].copy_from_slice(&bytes[start..start + read]);
+            read
+        });
+        self.pointer += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pointer = self.pointer as usize;
+        with_bytes(&self.node, |bytes| {
+            if bytes.len() < pointer {
+                bytes.resize(pointer, 0);
+            }
+            let overlap = min(buf.len(), bytes.len() - pointer);
+            bytes[pointer..pointer + overlap].copy_from_slice(&buf[..overlap]);
+            bytes.extend_from_slice(&buf[overlap..]);
+        });
+        self.pointer += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    /// Seeks to `pos`. Positions before the start or beyond the end of the
+    /// file are invalid.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = traits::File::size(self) as i64;
+        let pointer = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => size + offset,
+            SeekFrom::Current(offset) => self.pointer as i64 + offset,
+        };
+        if pointer < 0 || pointer > size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Out of bounds"));
+        }
+        self.pointer = pointer as u64;
+        Ok(self.pointer)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        match self.node.borrow().data {
+            Data::File(ref bytes) => bytes.len() as u64,
+            Data::Dir(_) => unreachable!(),
+        }
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        with_bytes(&self.node, |bytes| bytes.resize(len as usize, 0));
+        self.pointer = min(self.pointer, len);
+        Ok(())
+    }
+}
+
+/// A directory open in a `TmpFs`.
+#[derive(Debug)]
+pub struct Dir {
+    node: NodeRef,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    /// Returns the entries of the directory in the order of their names. `.`
+    /// and `..` are not listed.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let entries: Vec<Entry> = match self.node.borrow().data {
+            Data::Dir(ref children) => children.iter()
+                .map(|(name, node)| Entry::new(name.clone(), node.clone()))
+                .collect(),
+            Data::File(_) => unreachable!(),
+        };
+        Ok(entries.into_iter())
+    }
+}
+
+#[derive(Debug)]
+enum EntryData {
+    File(File),
+    Dir(Dir),
+}
+
+/// An entry of a `TmpFs` directory.
+#[derive(Debug)]
+pub struct Entry {
+    name: String,
+    metadata: Metadata,
+    node: EntryData,
+}
+
+impl Entry {
+    fn new(name: String, node: NodeRef) -> Entry {
+        let (metadata, is_dir) = {
+            let node = node.borrow();
+            (node.metadata(), node.is_dir())
+        };
+        let node = if is_dir {
+            EntryData::Dir(Dir { node })
+        } else {
+            EntryData::File(File::new(node))
+        };
+        Entry { name, metadata, node }
+    }
+
+    fn node(&self) -> &NodeRef {
+        match self.node {
+            EntryData::File(ref file) => &file.node,
+            EntryData::Dir(ref dir) => &dir.node,
+        }
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn set_metadata(&mut self, metadata: Metadata) -> io::Result<()> {
+        let metadata = {
+            let mut node = self.node().borrow_mut();
+            copy_metadata(&metadata, &mut node.attributes);
+            node.metadata()
+        };
+        self.metadata = metadata;
+        Ok(())
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match self.node {
+            EntryData::File(ref file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(ref dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            EntryData::File(file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(dir) => Some(dir),
+        }
+    }
+}
+
+impl<'a> traits::FileSystem for &'a TmpFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let names = components(path.as_ref())?;
+        let node = self.lookup(&names)?;
+        let name = names.last().map_or(String::new(), |name| name.to_string());
+        Ok(Entry::new(name, node))
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        let node = Node::new(Data::File(Vec::new()));
+        self.insert(path.as_ref(), node.clone())?;
+        Ok(File::new(node))
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P, parents: bool) -> io::Result<Dir> {
+        let path = path.as_ref();
+        if parents {
+            if let Some(parent) = path.parent() {
+                match traits::FileSystem::open(self, parent) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        traits::FileSystem::create_dir(self, parent, true)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let node = Node::new(Data::Dir(BTreeMap::new()));
+        self.insert(path, node.clone())?;
+        Ok(Dir { node })
+    }
+
+    /// Moves the entry at `from` to `to`, which may be in another directory.
+    /// Files open at `from` stay open.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of `traits::FileSystem::rename()`, returns an
+    /// error of `InvalidInput` if `to` lies inside the directory at `from`.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let from_names = components(from.as_ref())?;
+        let to_names = components(to.as_ref())?;
+        if from_names.is_empty() || to_names.starts_with(&from_names) {
+            return Err(invalid("cannot move an entry into itself"));
+        }
+
+        let node = self.lookup(&from_names)?;
+        match self.lookup(&to_names) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.insert(to.as_ref(), node)?;
+        let (parent, name) = self.parent(from.as_ref())?;
+        let mut parent = parent.borrow_mut();
+        if let Data::Dir(ref mut children) = parent.data {
+            children.remove(name);
+        }
+        Ok(())
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let (parent, name) = self.parent(path.as_ref())?;
+        let mut parent = parent.borrow_mut();
+        let entries = match parent.data {
+            Data::Dir(ref mut entries) => entries,
+            Data::File(_) => unreachable!(),
+        };
+
+        let empty = match entries.get(name) {
+            Some(node) => match node.borrow().data {
+                Data::Dir(ref grandchildren) => grandchildren.is_empty(),
+                Data::File(_) => true,
+            },
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such entry")),
+        };
+        if !empty && !children {
+            return Err(io::Error::new(io::ErrorKind::Other, "directory is not empty"));
+        }
+
+        entries.remove(name);
+        Ok(())
+    }
+}
+
+impl Mount for TmpFs {
+    fn open(&self, path: &Path) -> io::Result<Inode> {
+        vfs::open(self, path)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<Box<FileHandle>> {
+        vfs::create_file(self, path)
+    }
+
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirHandle>> {
+        vfs::create_dir(self, path, parents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+        traits::FileSystem::remove(self, path, children)
+    }
+
+    fn set_metadata(&self, path: &Path, metadata: &Metadata) -> io::Result<()> {
+        vfs::set_metadata(self, path, metadata)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,690 @@
+//! A virtual file system that joins mounted file systems into one namespace.
+//!
+//! Each file system is mounted at an absolute path and sees the paths below
+//! it as absolute paths of its own. A path is resolved by the file system
+//! mounted at its longest prefix, so a mount hides whatever the file system
+//! above it has at the same path.
+
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::vec;
+
+use fat32::traits;
+use fat32::vfat::{Shared, VFat};
+
+/// A point in time, copied from a mounted file system's timestamps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    year: usize,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl Timestamp {
+    /// 1980-01-01 00:00:00, the earliest time FAT can record. File systems
+    /// without a clock use it for every timestamp.
+    pub const EPOCH: Timestamp = Timestamp { year: 1980, month: 1, day: 1,
+                                             hour: 0, minute: 0, second: 0 };
+
+    /// Copies `timestamp`.
+    pub fn from_timestamp<T: traits::Timestamp>(timestamp: T) -> Timestamp {
+        Timestamp {
+            year: timestamp.year(),
+            month: timestamp.month(),
+            day: timestamp.day(),
+            hour: timestamp.hour(),
+            minute: timestamp.minute(),
+            second: timestamp.second(),
+        }
+    }
+
+    /// Converts `self` to the timestamp type of a mounted file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `T` cannot represent `self`.
+    pub fn to_timestamp<T: traits::Timestamp>(&self) -> io::Result<T> {
+        T::new(self.year, self.month, self.day, self.hour, self.minute, self.second)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                          "timestamp cannot be represented"))
+    }
+}
+
+impl Default for Timestamp {
+    fn default() -> Timestamp {
+        Timestamp::EPOCH
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize { self.year }
+    fn month(&self) -> u8 { self.month }
+    fn day(&self) -> u8 { self.day }
+    fn hour(&self) -> u8 { self.hour }
+    fn minute(&self) -> u8 { self.minute }
+    fn second(&self) -> u8 { self.second }
+
+    fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8)
+        -> Option<Timestamp>
+    {
+        if month < 1 || month > 12 || day < 1 || day > 31
+            || hour > 23 || minute > 59 || second > 59
+        {
+            return None;
+        }
+        Some(Timestamp { year, month, day, hour, minute, second })
+    }
+}
+
+/// The metadata of an entry in any mounted file system.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    read_only: bool,
+    hidden: bool,
+    system: bool,
+    volume_id: bool,
+    directory: bool,
+    archive: bool,
+    size: u64,
+    created: Timestamp,
+    accessed: Timestamp,
+    modified: Timestamp,
+}
+
+impl Metadata {
+    /// Metadata for a file of `size` bytes, with every timestamp at the epoch.
+    pub fn file(size: u64) -> Metadata {
+        Metadata { size, ..Metadata::default() }
+    }
+
+    /// Metadata for a directory, with every timestamp at the epoch.
+    pub fn dir() -> Metadata {
+        Metadata { directory: true, ..Metadata::default() }
+    }
+
+    /// Copies `metadata`.
+    pub fn from_metadata<M: traits::Metadata>(metadata: &M) -> Metadata {
+        Metadata {
+            read_only: metadata.read_only(),
+            hidden: metadata.hidden(),
+            system: metadata.system(),
+            volume_id: metadata.volume_id(),
+            directory: metadata.directory(),
+            archive: metadata.archive(),
+            size: metadata.size(),
+            created: Timestamp::from_timestamp(metadata.created()),
+            accessed: Timestamp::from_timestamp(metadata.accessed()),
+            modified: Timestamp::from_timestamp(metadata.modified()),
+        }
+    }
+
+    /// Sets the attributes and timestamps of `metadata` to those of `self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput`, leaving `metadata` unchanged, if one of the
+    /// timestamps cannot be represented by `M`.
+    pub fn apply<M: traits::Metadata>(&self, metadata: &mut M) -> io::Result<()> {
+        let created = self.created.to_timestamp()?;
+        let accessed = self.accessed.to_timestamp()?;
+        let modified = self.modified.to_timestamp()?;
+        metadata.set_read_only(self.read_only);
+        metadata.set_hidden(self.hidden);
+        metadata.set_system(self.system);
+        metadata.set_archive(self.archive);
+        metadata.set_created(created);
+        metadata.set_accessed(accessed);
+        metadata.set_modified(modified);
+        Ok(())
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool { self.read_only }
+    fn hidden(&self) -> bool { self.hidden }
+    fn system(&self) -> bool { self.system }
+    fn volume_id(&self) -> bool { self.volume_id }
+    fn directory(&self) -> bool { self.directory }
+    fn archive(&self) -> bool { self.archive }
+    fn size(&self) -> u64 { self.size }
+    fn created(&self) -> Timestamp { self.created }
+    fn accessed(&self) -> Timestamp { self.accessed }
+    fn modified(&self) -> Timestamp { self.modified }
+
+    fn set_read_only(&mut self, read_only: bool) { self.read_only = read_only }
+    fn set_hidden(&mut self, hidden: bool) { self.hidden = hidden }
+    fn set_system(&mut self, system: bool) { self.system = system }
+    fn set_archive(&mut self, archive: bool) { self.archive = archive }
+    fn set_created(&mut self, timestamp: Timestamp) { self.created = timestamp }
+    fn set_accessed(&mut self, timestamp: Timestamp) { self.accessed = timestamp }
+    fn set_modified(&mut self, timestamp: Timestamp) { self.modified = timestamp }
+}
+
+/// A file open on a mounted file system.
+pub trait FileHandle: io::Read + io::Write + io::Seek {
+    fn sync(&mut self) -> io::Result<()>;
+    fn size(&self) -> u64;
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl<T: traits::File> FileHandle for T {
+    fn sync(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+
+    fn size(&self) -> u64 {
+        traits::File::size(self)
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        traits::File::set_len(self, len)
+    }
+}
+
+/// A directory open on a mounted file system.
+pub trait DirHandle {
+    fn entries(&self) -> io::Result<Vec<Inode>>;
+}
+
+impl<D> DirHandle for D
+    where D: traits::Dir + 'static,
+          D::Entry: traits::Entry<Dir = D>,
+          <D::Entry as traits::Entry>::File: 'static
+{
+    fn entries(&self) -> io::Result<Vec<Inode>> {
+        Ok(traits::Dir::entries(self)?.map(Inode::from_entry).collect())
+    }
+}
+
+/// A directory with no entries, standing in for the directories that lead to
+/// a mount point but do not exist in the file system above it.
+struct EmptyDir;
+
+impl DirHandle for EmptyDir {
+    fn entries(&self) -> io::Result<Vec<Inode>> {
+        Ok(Vec::new())
+    }
+}
+
+/// What an `Inode` refers to.
+pub enum Node {
+    File(Box<FileHandle>),
+    Dir(Box<DirHandle>),
+}
+
+/// An entry of a mounted file system, in a form common to all of them.
+pub struct Inode {
+    pub name: String,
+    pub metadata: Metadata,
+    pub node: Node,
+}
+
+impl Inode {
+    /// Converts `entry` of a `traits::FileSystem`.
+    pub fn from_entry<E>(entry: E) -> Inode
+        where E: traits::Entry,
+              E::Dir: traits::Dir<Entry = E> + 'static,
+              E::File: 'static
+    {
+        let name = entry.name().to_string();
+        let metadata = Metadata::from_metadata(entry.metadata());
+        let node = if entry.is_dir() {
+            match entry.into_dir() {
+                Some(dir) => Node::Dir(Box::new(dir)),
+                None => unreachable!(),
+            }
+        } else {
+            match entry.into_file() {
+                Some(file) => Node::File(Box::new(file)),
+                None => unreachable!(),
+            }
+        };
+        Inode { name, metadata, node }
+    }
+}
+
+/// A file system mounted in a `Vfs`. Every path passed to it is absolute and
+/// relative to its mount point. The functions below implement each method for
+/// any `traits::FileSystem`.
+pub trait Mount {
+    fn open(&self, path: &Path) -> io::Result<Inode>;
+    fn create_file(&self, path: &Path) -> io::Result<Box<FileHandle>>;
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirHandle>>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()>;
+    /// Applies the attributes of `metadata` to the entry at `path`.
+    fn set_metadata(&self, path: &Path, metadata: &Metadata) -> io::Result<()>;
+    /// Writes any buffered changes back to the underlying storage.
+    fn sync(&self) -> io::Result<()>;
+}
+
+pub fn open<F>(fs: F, path: &Path) -> io::Result<Inode>
+    where F: traits::FileSystem, F::Dir: 'static, F::File: 'static
+{
+    Ok(Inode::from_entry(fs.open(path)?))
+}
+
+pub fn create_file<F>(fs: F, path: &Path) -> io::Result<Box<FileHandle>>
+    where F: traits::FileSystem, F::File: 'static
+{
+    Ok(Box::new(fs.create_file(path)?))
+}
+
+pub fn create_dir<F>(fs: F, path: &Path, parents: bool) -> io::Result<Box<DirHandle>>
+    where F: traits::FileSystem, F::Dir: 'static, F::File: 'static
+{
+    Ok(Box::new(fs.create_dir(path, parents)?))
+}
+
+pub fn set_metadata<F>(fs: F, path: &Path, metadata: &Metadata) -> io::Result<()>
+    where F: traits::FileSystem
+{
+    let mut entry = fs.open(path)?;
+    let mut new = traits::Entry::metadata(&entry).clone();
+    metadata.apply(&mut new)?;
+    traits::Entry::set_metadata(&mut entry, new)
+}
+
+#[derive(Clone)]
+struct MountPoint {
+    path: PathBuf,
+    fs: Rc<Mount>,
+}
+
+/// The mount table, and the namespace it forms.
+///
+/// Cloning a `Vfs` is cheap. Directories opened through it keep seeing the
+/// mounts that existed when they were opened.
+#[derive(Clone, Default)]
+pub struct Vfs {
+    mounts: Rc<Vec<MountPoint>>,
+}
+
+// The kernel runs on a single core, as for `fat32::vfat::Shared`.
+unsafe impl Send for Vfs {}
+
+impl fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Vfs").field("mount_points", &self.mount_points()).finish()
+    }
+}
+
+/// Returns `path` with `.` and `..` removed, or an error of `InvalidInput` if
+/// it is not absolute. `..` at the root is the root.
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+    }
+
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::Normal(name) => normal.push(name),
+            _ => {}
+        }
+    }
+    Ok(normal)
+}
+
+impl Vfs {
+    /// Returns an empty mount table. Nothing can be opened until a file
+    /// system is mounted at `/`.
+    pub fn new() -> Vfs {
+        Vfs::default()
+    }
+
+    /// Mounts `fs` at the absolute path `path`. The path need not exist in
+    /// the file system above it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not absolute, or
+    /// `AlreadyExists` if a file system is already mounted at `path`.
+    pub fn mount<P: AsRef<Path>, F: Mount + 'static>(&mut self, path: P, fs: F) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        if self.mounts.iter().any(|mount| mount.path == path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already mounted"));
+        }
+
+        Rc::make_mut(&mut self.mounts).push(MountPoint { path, fs: Rc::new(fs) });
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `path`, after syncing it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if nothing is mounted at `path`, or
+    /// `Other` if other file systems are mounted below it.
+    pub fn unmount<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        let index = self.mounts.iter().position(|mount| mount.path == path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not mounted"))?;
+        if self.mounts.iter().any(|mount| mount.path != path && mount.path.starts_with(&path)) {
+            return Err(io::Error::new(io::ErrorKind::Other, "file systems are mounted below"));
+        }
+
+        self.mounts[index].fs.sync()?;
+        Rc::make_mut(&mut self.mounts).remove(index);
+        Ok(())
+    }
+
+    /// Returns the paths file systems are mounted at, in sorted order.
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.mounts.iter().map(|mount| mount.path.clone()).collect();
+        paths.sort();
+        paths
+    }
+
+    /// Returns the mount point `path` lies under and the path relative to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not absolute, or
+    /// `NotFound` if no file system is mounted at `/`.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> io::Result<(PathBuf, PathBuf)> {
+        let (mount, path) = self.resolve_mount(path.as_ref())?;
+        Ok((mount.path.clone(), path))
+    }
+
+    fn resolve_mount(&self, path: &Path) -> io::Result<(&MountPoint, PathBuf)> {
+        let path = normalize(path)?;
+        let mount = self.mounts.iter()
+            .filter(|mount| path.starts_with(&mount.path))
+            .max_by_key(|mount| mount.path.components().count())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing mounted"))?;
+
+        let relative = match path.strip_prefix(&mount.path) {
+            Ok(relative) => Path::new("/").join(relative),
+            Err(_) => unreachable!(),
+        };
+        Ok((mount, relative))
+    }
+
+    /// Syncs every mounted file system. Returns the first error, if any,
+    /// after trying all of them.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for mount in self.mounts.iter() {
+            let synced = mount.fs.sync();
+            if result.is_ok() {
+                result = synced;
+            }
+        }
+        result
+    }
+
+    fn entry(&self, path: PathBuf, fs: Rc<Mount>, fs_path: PathBuf, inode: Inode) -> Entry {
+        let node = match inode.node {
+            Node::File(file) => EntryData::File(File(file)),
+            Node::Dir(dir) => EntryData::Dir(Dir {
+                vfs: self.clone(),
+                path,
+                fs: fs.clone(),
+                fs_path: fs_path.clone(),
+                handle: dir,
+            }),
+        };
+        Entry { name: inode.name, metadata: inode.metadata, node, fs, fs_path }
+    }
+}
+
+/// A file open through a `Vfs`.
+pub struct File(Box<FileHandle>);
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File").field("size", &self.0.size()).finish()
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.0.set_len(len)
+    }
+}
+
+/// A directory open through a `Vfs`. Its entries include the roots of the
+/// file systems mounted directly below it.
+pub struct Dir {
+    vfs: Vfs,
+    /// The path of the directory in the `Vfs`.
+    path: PathBuf,
+    fs: Rc<Mount>,
+    /// The path of the directory in `fs`.
+    fs_path: PathBuf,
+    handle: Box<DirHandle>,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let mut entries: Vec<Entry> = self.handle.entries()?.into_iter()
+            .map(|inode| {
+                let path = self.path.join(&inode.name);
+                let fs_path = self.fs_path.join(&inode.name);
+                self.vfs.entry(path, self.fs.clone(), fs_path, inode)
+            })
+            .collect();
+
+        // Mount points below this directory appear in it, along with the
+        // directories leading to them that the file system here lacks.
+        for mount in self.vfs.mounts.iter() {
+            let name = match mount.path.strip_prefix(&self.path).ok()
+                .and_then(|below| below.components().next())
+            {
+                Some(Component::Normal(name)) => name.to_string_lossy().into_owned(),
+                _ => continue,
+            };
+
+            let path = self.path.join(&name);
+            if path == mount.path {
+                let mut inode = mount.fs.open(Path::new("/"))?;
+                inode.name = name;
+                entries.retain(|entry| entry.name != inode.name);
+                entries.push(self.vfs.entry(path, mount.fs.clone(), PathBuf::from("/"), inode));
+            } else if !entries.iter().any(|entry| entry.name == name) {
+                let fs_path = self.fs_path.join(&name);
+                let inode = Inode { name, metadata: Metadata::dir(),
+                                    node: Node::Dir(Box::new(EmptyDir)) };
+                entries.push(self.vfs.entry(path, self.fs.clone(), fs_path, inode));
+            }
+        }
+
+        Ok(entries.into_iter())
+    }
+}
+
+enum EntryData {
+    File(File),
+    Dir(Dir),
+}
+
+/// An entry opened through a `Vfs`.
+pub struct Entry {
+    name: String,
+    metadata: Metadata,
+    node: EntryData,
+    fs: Rc<Mount>,
+    /// The path of the entry in `fs`.
+    fs_path: PathBuf,
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn set_metadata(&mut self, metadata: Metadata) -> io::Result<()> {
+        self.fs.set_metadata(&self.fs_path, &metadata)?;
+        self.metadata = self.fs.open(&self.fs_path)?.metadata;
+        Ok(())
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match self.node {
+            EntryData::File(ref file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(ref dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            EntryData::File(file) => Some(file),
+            EntryData::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            EntryData::File(_) => None,
+            EntryData::Dir(dir) => Some(dir),
+        }
+    }
+}
+
+impl<'a> traits::FileSystem for &'a Vfs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let path = normalize(path.as_ref())?;
+        let (mount, fs_path) = self.resolve_mount(&path)?;
+        let mut inode = match mount.fs.open(&fs_path) {
+            Ok(inode) => inode,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound
+                && self.mounts.iter().any(|below| below.path.starts_with(&path)) =>
+            {
+                Inode { name: String::new(), metadata: Metadata::dir(),
+                        node: Node::Dir(Box::new(EmptyDir)) }
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The root of a mounted file system is named after its mount point.
+        if let Some(name) = path.file_name() {
+            inode.name = name.to_string_lossy().into_owned();
+        }
+        Ok(self.entry(path.clone(), mount.fs.clone(), fs_path, inode))
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        let (mount, fs_path) = self.resolve_mount(path.as_ref())?;
+        Ok(File(mount.fs.create_file(&fs_path)?))
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P, parents: bool) -> io::Result<Dir> {
+        let path = normalize(path.as_ref())?;
+        let (mount, fs_path) = self.resolve_mount(&path)?;
+        let handle = mount.fs.create_dir(&fs_path, parents)?;
+        Ok(Dir { vfs: self.clone(), path, fs: mount.fs.clone(), fs_path, handle })
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_mount, from_path) = self.resolve_mount(from.as_ref())?;
+        let (to_mount, to_path) = self.resolve_mount(to.as_ref())?;
+        if from_path == Path::new("/") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rename a mount point"));
+        }
+        if from_mount.path != to_mount.path {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rename across mounts"));
+        }
+        from_mount.fs.rename(&from_path, &to_path)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let (mount, fs_path) = self.resolve_mount(path.as_ref())?;
+        if fs_path == Path::new("/") {
+            return Err(io::Error::new(io::ErrorKind::Other, "cannot remove a mount point"));
+        }
+        mount.fs.remove(&fs_path, children)
+    }
+}
+
+impl Mount for Shared<VFat> {
+    fn open(&self, path: &Path) -> io::Result<Inode> {
+        open(self, path)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<Box<FileHandle>> {
+        create_file(self, path)
+    }
+
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<DirHandle>> {
+        create_dir(self, path, parents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+        traits::FileSystem::remove(self, path, children)
+    }
+
+    fn set_metadata(&self, path: &Path, metadata: &Metadata) -> io::Result<()> {
+        set_metadata(self, path, metadata)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.borrow_mut().sync()
+    }
+}
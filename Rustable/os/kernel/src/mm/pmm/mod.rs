@@ -1,7 +1,8 @@
 use ALLOCATOR;
 use std;
 use std::mem;
-use allocator::{alloc_page, dealloc_page};
+use std::cmp::{max, min};
+use allocator::{alloc_page, dealloc_page, dealloc_pages};
 use allocator::util::{align_down, align_up};
 use alloc::heap::{AllocErr, Layout};
 use alloc::allocator::Alloc;
@@ -15,13 +16,18 @@ use mm::vm::get_pte;
 use aarch64::tlb_invalidate;
 use console::kprintln;
 use allocator::imp::{Allocator, alloc_page_at};
+use mutex::Mutex;
 
 
 // use pi::atags;
-use pi::atags::Atags;
+use pi::atags::{self, Atags};
 
 pub struct Pmm;
 
+/// The physical pages `[begin, end)` holding the initial ramdisk, which
+/// `page_init` keeps out of the free page list until `release_initrd`.
+static INITRD_PAGES: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+
 impl Pmm {
     pub fn init(&self) {
         // to alloc/dealloc physical memory
@@ -96,7 +102,7 @@ fn page_init() {
         match atag.mem() {
             Some(mem) => {
                 let mut begin = mem.start as usize;
-                let mut end = mem.size as usize;
+                let end = mem.size as usize;
                 // kprintln!("mem2: {:x} {:x}", begin, end);
                 if begin < PADDR(FREEMEM) {
                     begin = PADDR(FREEMEM);
@@ -108,18 +114,22 @@ fn page_init() {
                 //     end = PMEMSIZE;
                 // }
                 // kprintln!("mem3: {:x} {:x}", begin, end);
-                if begin < end {
-                    begin = align_up(begin, PGSIZE);
-                    end = align_down(end, PGSIZE);
-                    // kprintln!("mem4: {:x} {:x}", begin, end);
-                    let page_addr = &page[PPN(begin)] as *const Page as *mut usize as usize;
-                    // kprintln!("page addr {:x}", page_addr);
-                    if begin < end {
-                        ALLOCATOR.init_memmap(page_addr, (end - begin) / PGSIZE, begin);
-                        // init_memmap(struct Page *base, size_t n) {
-                        //     pmm_manager->init_memmap(base, n);
-                        // }
+                // The initial ramdisk stays reserved until the file system
+                // has unpacked it.
+                match initrd_region() {
+                    Some((initrd_begin, initrd_end)) => {
+                        let initrd_begin = align_down(initrd_begin, PGSIZE);
+                        let initrd_end = align_up(initrd_end, PGSIZE);
+                        free_range(page, begin, min(end, initrd_begin));
+                        free_range(page, max(begin, initrd_end), end);
+
+                        let reserved_begin = max(align_up(begin, PGSIZE), initrd_begin);
+                        let reserved_end = min(align_down(end, PGSIZE), initrd_end);
+                        if reserved_begin < reserved_end {
+                            *INITRD_PAGES.lock() = Some((reserved_begin, reserved_end));
+                        }
                     }
+                    None => free_range(page, begin, end),
                 }
             }
 
@@ -129,6 +139,46 @@ fn page_init() {
 
 }
 
+/// Adds the pages in the physical address range `[begin, end)` to the free
+/// page list.
+fn free_range(page: &mut [Page], mut begin: usize, mut end: usize) {
+    if begin < end {
+        begin = align_up(begin, PGSIZE);
+        end = align_down(end, PGSIZE);
+        // kprintln!("mem4: {:x} {:x}", begin, end);
+        let page_addr = &page[PPN(begin)] as *const Page as *mut usize as usize;
+        // kprintln!("page addr {:x}", page_addr);
+        if begin < end {
+            ALLOCATOR.init_memmap(page_addr, (end - begin) / PGSIZE, begin);
+            // init_memmap(struct Page *base, size_t n) {
+            //     pmm_manager->init_memmap(base, n);
+            // }
+        }
+    }
+}
+
+/// Returns the physical address range `[begin, end)` of the initial ramdisk
+/// the bootloader or firmware loaded, if there is one.
+pub fn initrd_region() -> Option<(usize, usize)> {
+    Atags::get()
+        .filter_map(|atag| atag.initrd2())
+        .filter(|initrd| initrd.size > 0)
+        .next()
+        .map(|initrd| (initrd.start as usize, initrd.start as usize + initrd.size as usize))
+}
+
+/// Gives the pages of the initial ramdisk back to the page allocator once its
+/// contents are no longer needed. `initrd_region()` returns `None` afterwards.
+pub fn release_initrd() {
+    if let Some((begin, end)) = INITRD_PAGES.lock().take() {
+        dealloc_pages(begin as *mut u8, (end - begin) / PGSIZE);
+    }
+    if initrd_region().is_some() {
+        // Replaces the INITRD2 ATAG in place with an empty one.
+        unsafe { atags::set_initrd2(0, 0) };
+    }
+}
+
 pub fn page_insert(pgdir: *const usize, page: *mut Page, va: usize, perm: usize) -> Result<i32, i32>{
     // kprintln!("page_insert: pgidr {:x}, pa {:x}, va {:x}", pgdir as usize, page2pa(page), va);
     let PERM = perm | PTE_V | ATTRINDX_NORMAL | ATTRIB_SH_INNER_SHAREABLE | AF;
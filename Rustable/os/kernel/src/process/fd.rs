@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use fs::vfs;
+
+/// The most descriptors a process may have open at once.
+pub const MAX_FILES: usize = 32;
+
+/// A file opened by `open`, with the access it was opened for.
+#[derive(Debug)]
+pub struct OpenFile {
+    pub file: vfs::File,
+    pub readable: bool,
+    pub writable: bool,
+    /// Every write goes to the end of the file.
+    pub append: bool,
+}
+
+/// A process's open files, indexed by descriptor.
+///
+/// Descriptors copied by `fork` refer to the same `OpenFile`, and so share
+/// its position, as in Unix.
+#[derive(Debug, Default, Clone)]
+pub struct FdTable {
+    files: Vec<Option<Rc<RefCell<OpenFile>>>>,
+}
+
+impl FdTable {
+    pub fn new() -> FdTable {
+        FdTable { files: Vec::new() }
+    }
+
+    /// Stores `file` under the lowest free descriptor and returns it, or
+    /// returns `None` if `MAX_FILES` descriptors are already open.
+    pub fn insert(&mut self, file: OpenFile) -> Option<usize> {
+        let file = Some(Rc::new(RefCell::new(file)));
+        match self.files.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                self.files[fd] = file;
+                Some(fd)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(file);
+                Some(self.files.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the file open under `fd`, if any.
+    pub fn get(&self, fd: usize) -> Option<Rc<RefCell<OpenFile>>> {
+        match self.files.get(fd) {
+            Some(&Some(ref file)) => Some(file.clone()),
+            _ => None,
+        }
+    }
+
+    /// Closes `fd`. Returns `false` if it was not open.
+    pub fn remove(&mut self, fd: usize) -> bool {
+        match self.files.get_mut(fd) {
+            Some(slot) => slot.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Closes every descriptor.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
@@ -4,10 +4,15 @@ mod scheduler;
 mod stack;
 mod elf;
 pub mod syscall;
+pub mod fd;
+
+#[cfg(test)]
+mod tests;
 
 pub use self::process::{Process, Id};
 pub use self::state::State;
 pub use self::scheduler::{GlobalScheduler, TICK};
 pub use self::stack::Stack;
+pub use self::fd::{FdTable, OpenFile};
 
 
@@ -1,7 +1,7 @@
 pub mod utils;
 
 use traps::TrapFrame;
-use process::State;
+use process::{State, FdTable};
 use std::string::String;
 
 use allocator::imp::Allocator;
@@ -41,6 +41,8 @@ pub struct Process {
     pub proc_name: String,
     pub allocator: Allocator,
     pub parent: Option<*const Process>,
+    /// The files the process has open.
+    pub files: FdTable,
 }
 
 unsafe impl Send for Process {}
@@ -59,6 +61,7 @@ impl Process {
             allocator: Allocator::new(),
             proc_name: String::from("idle"),
             parent: None,
+            files: FdTable::new(),
         }
     }
 
@@ -0,0 +1,71 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use fat32::traits::FileSystem;
+use fs::tmpfs::TmpFs;
+use fs::vfs::Vfs;
+use process::fd::{FdTable, OpenFile, MAX_FILES};
+
+/// Creates the file at `path` in `vfs` and opens it for reading and writing.
+fn open_file(vfs: &Vfs, path: &str) -> OpenFile {
+    let file = vfs.create_file(path).unwrap();
+    OpenFile { file, readable: true, writable: true, append: false }
+}
+
+fn vfs() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mount("/", TmpFs::new()).unwrap();
+    vfs
+}
+
+#[test]
+fn test_fd_table_reuses_lowest_descriptor() {
+    let vfs = vfs();
+    let mut files = FdTable::new();
+    assert!(files.get(0).is_none());
+
+    assert_eq!(files.insert(open_file(&vfs, "/a")), Some(0));
+    assert_eq!(files.insert(open_file(&vfs, "/b")), Some(1));
+    assert_eq!(files.insert(open_file(&vfs, "/c")), Some(2));
+
+    assert!(files.remove(1));
+    assert!(!files.remove(1));
+    assert!(!files.remove(7));
+    assert!(files.get(1).is_none());
+    assert!(files.get(2).is_some());
+
+    assert_eq!(files.insert(open_file(&vfs, "/d")), Some(1));
+    assert_eq!(files.insert(open_file(&vfs, "/e")), Some(3));
+
+    files.clear();
+    assert!(files.get(0).is_none());
+    assert_eq!(files.insert(open_file(&vfs, "/f")), Some(0));
+}
+
+#[test]
+fn test_fd_table_limit() {
+    let vfs = vfs();
+    let mut files = FdTable::new();
+    for fd in 0..MAX_FILES {
+        assert_eq!(files.insert(open_file(&vfs, &format!("/{}", fd))), Some(fd));
+    }
+    assert_eq!(files.insert(open_file(&vfs, "/full")), None);
+
+    assert!(files.remove(5));
+    assert_eq!(files.insert(open_file(&vfs, "/again")), Some(5));
+}
+
+#[test]
+fn test_fd_table_clones_share_position() {
+    let vfs = vfs();
+    let mut parent = FdTable::new();
+    let fd = parent.insert(open_file(&vfs, "/shared")).unwrap();
+    let mut child = parent.clone();
+
+    parent.get(fd).unwrap().borrow_mut().file.write_all(b"hello").unwrap();
+    let position = child.get(fd).unwrap().borrow_mut().file.seek(SeekFrom::Current(0)).unwrap();
+    assert_eq!(position, 5);
+
+    // Closing a descriptor in one table leaves the other open.
+    assert!(child.remove(fd));
+    assert!(parent.get(fd).is_some());
+}
@@ -5,6 +5,8 @@ use std::path::{Path, PathBuf};
 use pi;
 use FILE_SYSTEM;
 use fat32::traits::{Dir, Entry, FileSystem, Timestamp, Metadata};
+use fat32::vfat::case;
+use fat32::walk::Pattern;
 use allocator::alloc_page;
 use SCHEDULER;
 use mutex::Mutex;
@@ -153,7 +155,11 @@ pub fn shell(prefix: &str) -> ! {
                     "cd" => handle_cd(&command.args[1..], &mut working_dir),
                     "ls" => handle_ls(&command.args[1..], &mut working_dir),
                     "cat" => handle_cat(&command.args[1..], &mut working_dir),
+                    "find" => handle_find(&command.args[1..], &working_dir),
+                    "undelete" => handle_undelete(&command.args[1..], &working_dir),
                     "exec" => handle_exec(&command.args[1..], &mut working_dir),
+                    "sync" => handle_sync(&command.args[1..]),
+                    "mount" => handle_mount(&command.args[1..]),
                     // "cpy" => handle_cpy(&command.args[1..], &mut working_dir),
                     // "v" => handle_v(),
                     "exit" => exit(),
@@ -253,8 +259,12 @@ fn print_entry<E: Entry>(entry: &E) {
     write_bool(entry.is_file(), 'f');
     write_bool(entry.metadata().read_only(), 'r');
     write_bool(entry.metadata().hidden(), 'h');
+    write_bool(entry.metadata().system(), 's');
+    write_bool(entry.metadata().archive(), 'a');
     kprint!("\t");
 
+    kprint!("{:>10}\t", entry.metadata().size());
+
     write_timestamp(entry.metadata().created());
     write_timestamp(entry.metadata().modified());
     write_timestamp(entry.metadata().accessed());
@@ -312,11 +322,112 @@ fn handle_ls(mut args: &[&str], working_dir: &PathBuf) {
     // kprintln!("8");
 }
 
+fn handle_find(args: &[&str], working_dir: &PathBuf) {
+    let (sensitive, args) = match args.first() {
+        Some(&"-i") => (false, &args[1..]),
+        _ => (true, args),
+    };
+    if args.len() != 1 {
+        kprintln!("Usage:");
+        kprintln!("find [-i] <pattern>");
+        kprintln!();
+        return;
+    }
+
+    let pattern = if args[0].starts_with('/') {
+        args[0].to_string()
+    } else {
+        format!("{}/{}", working_dir.display(), args[0])
+    };
+
+    match FILE_SYSTEM.glob_pattern(Pattern::new(&pattern).case_sensitive(sensitive)) {
+        Ok(matches) => for found in matches {
+            match found {
+                Ok(found) => kprintln!("{}", found.path.display()),
+                Err(e) => kprintln!("Failed to read directory: {:?}", e),
+            }
+        },
+        Err(e) => kprintln!("Failed to read root directory: {:?}", e),
+    }
+}
+
+fn handle_undelete(args: &[&str], working_dir: &PathBuf) {
+    let (restore, args) = match args.first() {
+        Some(&"-r") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    if args.len() > 1 || (restore && args.is_empty()) {
+        kprintln!("Usage:");
+        kprintln!("undelete [directory]");
+        kprintln!("undelete -r <file>");
+        kprintln!();
+        return;
+    }
+
+    let mut path = working_dir.clone();
+    if let Some(arg) = args.first() {
+        path.push(arg);
+    }
+    let (dir, name) = if restore {
+        match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+            (Some(dir), Some(name)) => (dir.to_path_buf(), Some(name.to_string())),
+            _ => {
+                kprintln!("Invalid path.");
+                return;
+            }
+        }
+    } else {
+        (path, None)
+    };
+
+    let dir = match FILE_SYSTEM.open_sd_dir(dir.as_path()) {
+        Ok(dir) => dir,
+        Err(_) => {
+            kprintln!("Directory not found.");
+            return;
+        }
+    };
+    let deleted = match dir.deleted() {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            kprintln!("Failed to read directory: {:?}", e);
+            return;
+        }
+    };
+
+    match name {
+        None => for entry in deleted {
+            kprintln!("{:>10}\t{}", entry.size, entry.name);
+        },
+        Some(name) => {
+            let mut deleted = deleted;
+            let folded = case::fold(&name);
+            match deleted.find(|entry| case::fold(&entry.name) == folded) {
+                Some(entry) => match dir.undelete(&entry) {
+                    Ok(_) => kprintln!("Restored {}.", entry.name),
+                    Err(e) => kprintln!("Failed to restore {}: {:?}", entry.name, e),
+                },
+                None => kprintln!("No deleted file named {}.", name),
+            }
+        }
+    }
+}
+
 fn handle_cat(args: &[&str], working_dir: &PathBuf) {
+    // `-c <bytes>` stops after that many bytes, for endless devices such as
+    // `/dev/zero`.
+    let (limit, args) = match args.first() {
+        Some(&"-c") if args.len() == 3 => match args[1].parse::<usize>() {
+            Ok(limit) => (limit, &args[2..]),
+            Err(_) => (0, &args[..0]),
+        },
+        _ => (std::usize::MAX, args),
+    };
+
     // kprintln!("cat");
     if args.len() != 1 {
         kprintln!("Usage:");
-        kprintln!("cat <file>");
+        kprintln!("cat [-c <bytes>] <file>");
         kprintln!();
         return;
     }
@@ -335,14 +446,22 @@ fn handle_cat(args: &[&str], working_dir: &PathBuf) {
 
     let entry = entry_result.unwrap();
     if let Some(ref mut file) = entry.into_file() {
-        loop {
+        let mut remaining = limit;
+        while remaining > 0 {
             use std::io::Read;
 
             let mut buffer = [0u8; 512];
-            match file.read(&mut buffer) {
+            let len = std::cmp::min(buffer.len(), remaining);
+            match file.read(&mut buffer[..len]) {
                 Ok(0) => break,
-                Ok(_) => kprint!("{}", String::from_utf8_lossy(&buffer)),
-                Err(e) => kprint!("Failed to read file: {:?}", e)
+                Ok(n) => {
+                    kprint!("{}", String::from_utf8_lossy(&buffer[..n]));
+                    remaining -= n;
+                }
+                Err(e) => {
+                    kprint!("Failed to read file: {:?}", e);
+                    break;
+                }
             }
         }
 
@@ -352,9 +471,41 @@ fn handle_cat(args: &[&str], working_dir: &PathBuf) {
     }
 }
 
+fn handle_sync(args: &[&str]) {
+    if args.len() > 0 {
+        kprintln!("Usage:");
+        kprintln!("sync");
+        kprintln!();
+        return;
+    }
+
+    if let Err(e) = FILE_SYSTEM.sync() {
+        kprintln!("Failed to sync file system: {:?}", e);
+    }
+}
+
+fn handle_mount(args: &[&str]) {
+    if args.len() > 0 {
+        kprintln!("Usage:");
+        kprintln!("mount");
+        kprintln!();
+        return;
+    }
+
+    match FILE_SYSTEM.mount_points() {
+        Ok(mount_points) => for mount_point in mount_points {
+            kprintln!("{}", mount_point.display());
+        },
+        Err(e) => kprintln!("Failed to list mount points: {:?}", e),
+    }
+}
+
 const BOOTLOADER_START_ADDR: usize = 0x4000000;
 
 fn exit() {
+    if let Err(e) = FILE_SYSTEM.sync() {
+        kprintln!("Failed to sync file system: {:?}", e);
+    }
     kprintln!("You will exit to write a new kernel");
     jump_to(BOOTLOADER_START_ADDR as *mut u8);
 }
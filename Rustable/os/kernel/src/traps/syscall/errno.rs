@@ -0,0 +1,43 @@
+use std::io;
+
+/// The error codes system calls return in `x7`. A successful call sets `x7`
+/// to `0`.
+///
+/// These values are part of the user ABI and are mirrored in the user
+/// library's `syscall` module.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Errno {
+    /// The system call does not exist.
+    NoSys = 1,
+    /// The descriptor is not open, or not open for the requested access.
+    BadFd = 2,
+    /// No file exists at the path.
+    NoEnt = 3,
+    /// A file already exists at the path.
+    Exist = 4,
+    /// An argument is invalid, such as a relative path or unknown flags.
+    Inval = 5,
+    /// A buffer or path lies outside the process's mapped memory.
+    Fault = 6,
+    /// The process has too many descriptors open.
+    MFile = 7,
+    /// The path names a directory.
+    IsDir = 8,
+    /// The file is read-only.
+    Access = 9,
+    /// The file system failed to complete the request.
+    Io = 10,
+}
+
+impl From<io::Error> for Errno {
+    fn from(error: io::Error) -> Errno {
+        match error.kind() {
+            io::ErrorKind::NotFound => Errno::NoEnt,
+            io::ErrorKind::AlreadyExists => Errno::Exist,
+            io::ErrorKind::InvalidInput => Errno::Inval,
+            io::ErrorKind::PermissionDenied => Errno::Access,
+            _ => Errno::Io,
+        }
+    }
+}
@@ -8,6 +8,7 @@ pub fn do_exit(tf: &mut TrapFrame) {
     kprintln!("exit");
 
     let mut current = SCHEDULER.pop_current();
+    current.files.clear();
 
     let pgdir = current.trap_frame.ttbr0;
     current.allocator.clear_page(pgdir as *const usize);
@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::slice;
+use std::str;
+
+use fat32::traits::{Entry, File, FileSystem, Metadata};
+
+use FILE_SYSTEM;
+use SCHEDULER;
+use allocator::page::{ATTRIB_AP_RO_ALL, ATTRIB_AP_RW_ALL, KADDR, PGSIZE, PTE_V, USTACKTOP};
+use allocator::util::align_down;
+use mm::vm::get_pte;
+use process::OpenFile;
+use traps::TrapFrame;
+use super::errno::Errno;
+
+/// `open` flag: the descriptor may be read from.
+pub const O_READ: u64 = 1 << 0;
+/// `open` flag: the descriptor may be written to.
+pub const O_WRITE: u64 = 1 << 1;
+/// `open` flag: create the file if it does not exist.
+pub const O_CREATE: u64 = 1 << 2;
+/// `open` flag: truncate the file to length 0. Requires `O_WRITE`.
+pub const O_TRUNC: u64 = 1 << 3;
+/// `open` flag: every write goes to the end of the file. Requires `O_WRITE`.
+pub const O_APPEND: u64 = 1 << 4;
+
+const O_ALL: u64 = O_READ | O_WRITE | O_CREATE | O_TRUNC | O_APPEND;
+
+/// `lseek` origin: the start of the file.
+pub const SEEK_SET: u64 = 0;
+/// `lseek` origin: the current position.
+pub const SEEK_CUR: u64 = 1;
+/// `lseek` origin: the end of the file.
+pub const SEEK_END: u64 = 2;
+
+/// Stores the outcome of a system call in `tf`: the value in `x0` and `0` in
+/// `x7` on success, otherwise the error number in `x7`.
+fn complete(tf: &mut TrapFrame, result: Result<u64, Errno>) {
+    match result {
+        Ok(value) => {
+            tf.x0 = value;
+            tf.x1to29[6] = 0;
+        }
+        Err(errno) => tf.x1to29[6] = errno as u64,
+    }
+}
+
+/// Returns `true` if the page table entry `pte` maps a page that EL0 may read,
+/// and also write if `writable` is `true`.
+fn user_accessible(pte: usize, writable: bool) -> bool {
+    if pte & PTE_V == 0 {
+        return false;
+    }
+    match pte & ATTRIB_AP_RO_ALL {
+        ATTRIB_AP_RW_ALL => true,
+        ATTRIB_AP_RO_ALL => !writable,
+        _ => false,
+    }
+}
+
+/// Returns `Ok` if the `len` bytes at `addr` in the address space of the
+/// process that trapped with `tf` all lie in pages mapped for the user, and
+/// writable by it if `writable` is `true`, and `Fault` otherwise.
+fn check_user_range(tf: &TrapFrame, addr: usize, len: usize, writable: bool)
+    -> Result<(), Errno>
+{
+    if len == 0 {
+        return Ok(());
+    }
+
+    let end = addr.checked_add(len).ok_or(Errno::Fault)?;
+    if addr == 0 || end > USTACKTOP {
+        return Err(Errno::Fault);
+    }
+
+    let pgdir = KADDR(tf.ttbr0 as usize) as *const usize;
+    let mut page = align_down(addr, PGSIZE);
+    while page < end {
+        match get_pte(pgdir, page, false) {
+            Ok(pte) if user_accessible(unsafe { *pte }, writable) => {}
+            _ => return Err(Errno::Fault),
+        }
+        page += PGSIZE;
+    }
+
+    Ok(())
+}
+
+/// Returns the `len` bytes at `addr` in the address space of the process that
+/// trapped with `tf`, or `Fault` unless they all lie in pages the user may
+/// read.
+fn user_slice<'a>(tf: &TrapFrame, addr: u64, len: u64) -> Result<&'a [u8], Errno> {
+    let (addr, len) = (addr as usize, len as usize);
+    check_user_range(tf, addr, len, false)?;
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { slice::from_raw_parts(addr as *const u8, len) })
+}
+
+/// Returns the `len` bytes at `addr` in the address space of the process that
+/// trapped with `tf` for the kernel to write to, or `Fault` unless they all
+/// lie in pages the user may write.
+fn user_slice_mut<'a>(tf: &TrapFrame, addr: u64, len: u64) -> Result<&'a mut [u8], Errno> {
+    let (addr, len) = (addr as usize, len as usize);
+    check_user_range(tf, addr, len, true)?;
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// Returns the file the current process has open under `fd`.
+fn descriptor(fd: u64) -> Result<Rc<RefCell<OpenFile>>, Errno> {
+    let current = SCHEDULER.pop_current();
+    let file = current.files.get(fd as usize);
+    SCHEDULER.push_current_front(current);
+    file.ok_or(Errno::BadFd)
+}
+
+/// Opens the file at the absolute path `path` as described by `flags`.
+fn open_file(path: &str, flags: u64) -> Result<OpenFile, Errno> {
+    let readable = flags & O_READ != 0;
+    let writable = flags & O_WRITE != 0;
+    if flags & !O_ALL != 0 || !(readable || writable)
+        || (!writable && flags & (O_TRUNC | O_APPEND) != 0)
+        || !path.starts_with('/')
+    {
+        return Err(Errno::Inval);
+    }
+
+    let mut file = match FILE_SYSTEM.open(path) {
+        Ok(entry) => {
+            if writable && entry.metadata().read_only() {
+                return Err(Errno::Access);
+            }
+            entry.into_file().ok_or(Errno::IsDir)?
+        }
+        Err(error) => {
+            if error.kind() != io::ErrorKind::NotFound || flags & O_CREATE == 0 {
+                return Err(error.into());
+            }
+            FILE_SYSTEM.create_file(path)?
+        }
+    };
+
+    if flags & O_TRUNC != 0 {
+        file.set_len(0)?;
+    }
+
+    Ok(OpenFile { file, readable, writable, append: flags & O_APPEND != 0 })
+}
+
+/// Opens a file.
+///
+/// `x0` and `x1` hold the address and length of the UTF-8 absolute path, and
+/// `x2` holds the `O_*` flags. Returns the new descriptor in `x0`.
+pub fn do_open(tf: &mut TrapFrame) {
+    let result = user_slice(tf, tf.x0, tf.x1)
+        .and_then(|path| str::from_utf8(path).map_err(|_| Errno::Inval))
+        .and_then(|path| open_file(path, tf.x2))
+        .and_then(|file| {
+            let mut current = SCHEDULER.pop_current();
+            let fd = current.files.insert(file);
+            SCHEDULER.push_current_front(current);
+            fd.map(|fd| fd as u64).ok_or(Errno::MFile)
+        });
+    complete(tf, result);
+}
+
+/// Reads from a descriptor at its position.
+///
+/// `x0` holds the descriptor and `x1` and `x2` the address and length of the
+/// buffer. Returns the number of bytes read in `x0`, which is `0` at the end
+/// of the file. Reading `/dev/console` holds up the whole kernel until a line
+/// is typed; see `fs::ConsoleDevice`.
+pub fn do_read(tf: &mut TrapFrame) {
+    let result = descriptor(tf.x0).and_then(|open| {
+        let mut open = open.borrow_mut();
+        if !open.readable {
+            return Err(Errno::BadFd);
+        }
+        let buf = user_slice_mut(tf, tf.x1, tf.x2)?;
+        Ok(open.file.read(buf)? as u64)
+    });
+    complete(tf, result);
+}
+
+/// Writes to a descriptor at its position, or at the end of the file if it
+/// was opened with `O_APPEND`.
+///
+/// `x0` holds the descriptor and `x1` and `x2` the address and length of the
+/// buffer. Returns the number of bytes written in `x0`.
+pub fn do_write(tf: &mut TrapFrame) {
+    let result = descriptor(tf.x0).and_then(|open| {
+        let mut open = open.borrow_mut();
+        if !open.writable {
+            return Err(Errno::BadFd);
+        }
+        let buf = user_slice(tf, tf.x1, tf.x2)?;
+        if open.append {
+            open.file.seek(SeekFrom::End(0))?;
+        }
+        Ok(open.file.write(buf)? as u64)
+    });
+    complete(tf, result);
+}
+
+/// Closes the descriptor in `x0`.
+pub fn do_close(tf: &mut TrapFrame) {
+    let mut current = SCHEDULER.pop_current();
+    let closed = current.files.remove(tf.x0 as usize);
+    SCHEDULER.push_current_front(current);
+    complete(tf, if closed { Ok(0) } else { Err(Errno::BadFd) });
+}
+
+/// Moves the position of a descriptor.
+///
+/// `x0` holds the descriptor, `x1` the signed offset and `x2` the `SEEK_*`
+/// origin it is relative to. Positions beyond the end of the file are
+/// invalid. Returns the new position in `x0`.
+pub fn do_lseek(tf: &mut TrapFrame) {
+    let result = descriptor(tf.x0).and_then(|open| {
+        let pos = match tf.x2 {
+            SEEK_SET if (tf.x1 as i64) >= 0 => SeekFrom::Start(tf.x1),
+            SEEK_CUR => SeekFrom::Current(tf.x1 as i64),
+            SEEK_END => SeekFrom::End(tf.x1 as i64),
+            _ => return Err(Errno::Inval),
+        };
+        let mut open = open.borrow_mut();
+        Ok(open.file.seek(pos)?)
+    });
+    complete(tf, result);
+}
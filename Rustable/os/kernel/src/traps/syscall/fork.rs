@@ -14,6 +14,7 @@ fn alloc_proc(father: &Process, tf: &mut TrapFrame) -> Process {
     
     process.state = State::Ready;
     process.parent = Some(father as *const Process);
+    process.files = father.files.clone();
     
     process.proc_name = String::from("child");
     
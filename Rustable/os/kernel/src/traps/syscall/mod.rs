@@ -2,6 +2,8 @@ mod wait;
 mod sleep;
 mod exit;
 mod fork;
+mod file;
+pub mod errno;
 
 use traps::TrapFrame;
 
@@ -9,37 +11,57 @@ use self::wait::do_wait;
 use self::sleep::do_sleep;
 use self::exit::do_exit;
 use self::fork::do_fork;
+use self::file::{do_open, do_read, do_write, do_close, do_lseek};
+use self::errno::Errno;
 use console::kprintln;
 
-/// Sleep for `ms` milliseconds.
+/// Handles the system call `num` made by the process that trapped with `tf`.
 ///
-/// This system call takes one parameter: the number of milliseconds to sleep.
+/// System calls report failure by setting `x7` to an `Errno` and success by
+/// setting it to `0`. The numbers are:
 ///
-/// In addition to the usual status value, this system call returns one
-/// parameter: the approximate true elapsed time from when `sleep` was called to
-/// when `sleep` returned.
-
-
+///   * 1: `sleep(ms)`
+///   * 2: `wait(pid)`
+///   * 3: `print(num)`
+///   * 4: `fork()`
+///   * 5: `exit()`
+///   * 6: `open(path, path_len, flags) -> fd`
+///   * 7: `read(fd, buf, len) -> bytes read`
+///   * 8: `write(fd, buf, len) -> bytes written`
+///   * 9: `close(fd)`
+///   * 10: `lseek(fd, offset, whence) -> position`
+///
+/// Unknown numbers fail with `NoSys`. See the `file` module for the arguments
+/// and flags of the file system calls.
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num {
         1 => {
             do_sleep(tf.x0 as u32, tf);
         },
         2 => {
+            // Set before switching away, as `tf` then holds the next process.
+            tf.x1to29[6] = 0;
             do_wait(tf.x0 as u32, tf);
         },
         3 => {
+            tf.x1to29[6] = 0;
             kprintln!("user print: {}", tf.x0);
         },
         4 => {
+            tf.x1to29[6] = 0;
             do_fork(tf);
         }
         5 => {
+            tf.x1to29[6] = 0;
             do_exit(tf);
         }
+        6 => do_open(tf),
+        7 => do_read(tf),
+        8 => do_write(tf),
+        9 => do_close(tf),
+        10 => do_lseek(tf),
         _ => {
-            // x7 = 1, syscall does not exist.
-            tf.x1to29[6] = 1;
+            tf.x1to29[6] = Errno::NoSys as u64;
         }
     }
 }
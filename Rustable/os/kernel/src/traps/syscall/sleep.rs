@@ -5,6 +5,13 @@ use traps::trap_frame::TrapFrame;
 use process::State;
 use console::kprintln;
 
+/// Sleep for `ms` milliseconds.
+///
+/// This system call takes one parameter: the number of milliseconds to sleep.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the approximate true elapsed time from when `sleep` was called to
+/// when `sleep` returned.
 pub fn do_sleep(ms: u32, tf: &mut TrapFrame) {
     kprintln!("sleep");
     let begin = current_time();
@@ -2,13 +2,15 @@ use atags::raw;
 use core::slice::from_raw_parts;
 use core::str::from_utf8;
 
-pub use atags::raw::{Core, Mem};
+pub use atags::raw::{Core, Mem, Ramdisk, Initrd2};
 
 /// An ATAG.
 #[derive(Debug, Copy, Clone)]
 pub enum Atag {
     Core(raw::Core),
     Mem(raw::Mem),
+    Ramdisk(raw::Ramdisk),
+    Initrd2(raw::Initrd2),
     Cmd(&'static str),
     Unknown(u32),
     None
@@ -31,6 +33,22 @@ impl Atag {
         }
     }
 
+    /// Returns `Some` if this is a `Ramdisk` ATAG. Otherwise returns `None`.
+    pub fn ramdisk(self) -> Option<Ramdisk> {
+        match self {
+            Atag::Ramdisk(ramdisk) => Some(ramdisk),
+            _ => None,
+        }
+    }
+
+    /// Returns `Some` if this is an `Initrd2` ATAG. Otherwise returns `None`.
+    pub fn initrd2(self) -> Option<Initrd2> {
+        match self {
+            Atag::Initrd2(initrd2) => Some(initrd2),
+            _ => None,
+        }
+    }
+
     /// Returns `Some` with the command line string if this is a `Cmd` ATAG.
     /// Otherwise returns `None`.
     pub fn cmd(self) -> Option<&'static str> {
@@ -53,6 +71,8 @@ impl<'a> From<&'a raw::Atag> for Atag {
             match (atag.tag, &atag.kind) {
                 (raw::Atag::CORE, &raw::Kind { core }) => Atag::Core(core),
                 (raw::Atag::MEM, &raw::Kind { mem }) => Atag::Mem(mem),
+                (raw::Atag::RAMDISK, &raw::Kind { ramdisk }) => Atag::Ramdisk(ramdisk),
+                (raw::Atag::INITRD2, &raw::Kind { initrd2 }) => Atag::Initrd2(initrd2),
                 (raw::Atag::CMDLINE, &raw::Kind { ref cmd }) => { 
                     Atag::Cmd(from_utf8(from_raw_parts(
                         &cmd.cmd as *const u8,
@@ -20,6 +20,30 @@ impl Atags {
     }
 }
 
+/// Records an initial ramdisk of `size` bytes at the physical address `start`
+/// in the ATAGS, replacing the `INITRD2` ATAG if there is one and appending one
+/// otherwise.
+///
+/// # Safety
+///
+/// The ATAGS must be well formed and have room for one more 4-word ATAG after
+/// the terminating `NONE` ATAG. Nothing may be reading them concurrently.
+pub unsafe fn set_initrd2(start: u32, size: u32) {
+    let mut ptr = ATAG_BASE as *mut raw::Atag;
+    while (*ptr).tag != raw::Atag::NONE && (*ptr).tag != raw::Atag::INITRD2 {
+        ptr = (ptr as *mut u32).add((*ptr).dwords as usize) as *mut raw::Atag;
+    }
+
+    if (*ptr).tag == raw::Atag::NONE {
+        let none = (ptr as *mut u32).add(4) as *mut raw::Atag;
+        (*none).dwords = 0;
+        (*none).tag = raw::Atag::NONE;
+    }
+    (*ptr).dwords = 4;
+    (*ptr).tag = raw::Atag::INITRD2;
+    (*ptr).kind.initrd2 = Initrd2 { start, size };
+}
+
 impl Iterator for Atags {
     type Item = Atag;
 
@@ -34,6 +34,8 @@ impl Atag {
 pub union Kind {
     pub core: Core,
     pub mem: Mem,
+    pub ramdisk: Ramdisk,
+    pub initrd2: Initrd2,
     pub cmd: Cmd
 }
 
@@ -54,6 +56,28 @@ pub struct Mem {
     pub start: u32
 }
 
+/// A `RAMDISK` ATAG.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Ramdisk {
+    /// Bit 0: load the ramdisk. Bit 1: prompt before loading it.
+    pub flags: u32,
+    /// The decompressed size of the ramdisk in KiB.
+    pub size: u32,
+    /// The block the ramdisk image starts at on its device.
+    pub start: u32
+}
+
+/// An `INITRD2` ATAG.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Initrd2 {
+    /// The physical address the initial ramdisk is loaded at.
+    pub start: u32,
+    /// The size of the initial ramdisk in bytes.
+    pub size: u32
+}
+
 /// A `CMDLINE` ATAG.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
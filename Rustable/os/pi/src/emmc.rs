@@ -0,0 +1,722 @@
+use core::fmt;
+
+use common::IO_BASE;
+use timer;
+use gpio::{Gpio, Function, Pull};
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address of the EMMC (SD host controller) registers.
+const EMMC_REG_BASE: usize = IO_BASE + 0x300000;
+
+/// The size, in bytes, of a block transferred to or from the card.
+pub const BLOCK_SIZE: usize = 512;
+
+/// How long to wait, in microseconds, for the controller or card.
+const TIMEOUT_US: u64 = 1_000_000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+    IRPT_MASK: Volatile<u32>,
+    IRPT_EN: Volatile<u32>,
+    CONTROL2: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FORCE_IRPT: Volatile<u32>,
+    __r1: [Reserved<u32>; 7],
+    BOOT_TIMEOUT: Volatile<u32>,
+    DBG_SEL: Volatile<u32>,
+    __r2: [Reserved<u32>; 2],
+    EXRDFIFO_CFG: Volatile<u32>,
+    EXRDFIFO_EN: Volatile<u32>,
+    TUNE_STEP: Volatile<u32>,
+    TUNE_STEPS_STD: Volatile<u32>,
+    TUNE_STEPS_DDR: Volatile<u32>,
+    __r3: [Reserved<u32>; 23],
+    SPI_INT_SPT: Volatile<u32>,
+    __r4: [Reserved<u32>; 2],
+    SLOTISR_VER: ReadVolatile<u32>,
+}
+
+/// Bit fields of the `STATUS` register.
+mod status {
+    pub const CMD_INHIBIT: u32 = 1 << 0;
+    pub const DAT_INHIBIT: u32 = 1 << 1;
+    pub const READ_AVAILABLE: u32 = 1 << 11;
+}
+
+/// Bit fields of the `CONTROL0` register.
+mod control0 {
+    pub const HCTL_DWIDTH: u32 = 1 << 1;
+}
+
+/// Bit fields of the `CONTROL1` register.
+mod control1 {
+    pub const CLK_INTLEN: u32 = 1 << 0;
+    pub const CLK_STABLE: u32 = 1 << 1;
+    pub const CLK_EN: u32 = 1 << 2;
+    pub const CLK_FREQ_MASK: u32 = 0xFFC0;
+    pub const DATA_TOUNIT_MAX: u32 = 0xE << 16;
+    pub const SRST_HC: u32 = 1 << 24;
+    pub const SRST_CMD: u32 = 1 << 25;
+    pub const SRST_DATA: u32 = 1 << 26;
+}
+
+/// Bit fields of the `INTERRUPT` register.
+mod interrupt {
+    pub const CMD_DONE: u32 = 1 << 0;
+    pub const DATA_DONE: u32 = 1 << 1;
+    pub const WRITE_RDY: u32 = 1 << 4;
+    pub const READ_RDY: u32 = 1 << 5;
+    pub const CMD_TIMEOUT: u32 = 1 << 16;
+    pub const DATA_TIMEOUT: u32 = 1 << 20;
+    pub const ERROR_MASK: u32 = 0x017E8000 | CMD_TIMEOUT | DATA_TIMEOUT;
+}
+
+/// Fields of the `SLOTISR_VER` register.
+mod slotisr_ver {
+    pub const SDVERSION_SHIFT: u32 = 16;
+    pub const SDVERSION_MASK: u32 = 0xFF;
+    /// Host controllers newer than this use a 10-bit clock divisor.
+    pub const HOST_SPEC_V2: u32 = 1;
+}
+
+/// Bit fields of the OCR returned by `ACMD41`.
+mod ocr {
+    pub const VOLTAGE_WINDOW: u32 = 0x00FF8000;
+    pub const CCS: u32 = 1 << 30;
+    pub const POWER_UP_DONE: u32 = 1 << 31;
+    /// The argument to `ACMD41`: supports high capacity at 3.2-3.4V.
+    pub const HCS_ARGUMENT: u32 = 0x51FF8000;
+}
+
+/// Fields of an R1 card status response.
+mod card_status {
+    pub const READY_FOR_DATA: u32 = 1 << 8;
+    pub const CURRENT_STATE_SHIFT: u32 = 9;
+    pub const CURRENT_STATE_MASK: u32 = 0xF;
+    /// The state of a selected card that is not transferring data.
+    pub const STATE_TRAN: u32 = 4;
+}
+
+/// Fields of the card specific data register returned by `CMD9`, as
+/// `(first bit, width)`.
+mod csd {
+    pub const STRUCTURE: (usize, usize) = (126, 2);
+    pub const READ_BL_LEN: (usize, usize) = (80, 4);
+    pub const C_SIZE_V1: (usize, usize) = (62, 12);
+    pub const C_SIZE_MULT_V1: (usize, usize) = (47, 3);
+    pub const C_SIZE_V2: (usize, usize) = (48, 22);
+}
+
+/// The check pattern and voltage argument to `CMD8`.
+const IF_COND_ARGUMENT: u32 = 0x1AA;
+
+/// The 4-bit bus width bit of the first SCR word as read from `DATA`.
+const SCR_BUS_WIDTH_4: u32 = 1 << 10;
+
+/// The base clock of the EMMC controller in Hz.
+const BASE_CLOCK: u32 = 41_666_666;
+
+/// The clock frequency, in Hz, used while identifying the card.
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+
+/// The clock frequency, in Hz, used for data transfer.
+const TRANSFER_CLOCK: u32 = 25_000_000;
+
+/// How many times `ACMD41` is retried before the card is declared dead.
+const OP_COND_RETRIES: usize = 100;
+
+/// Error bits of an R1 card status response.
+const R1_ERRORS_MASK: u32 = 0xFFF9C004;
+
+/// SD commands as written to the `CMDTM` register: the command index along
+/// with the expected response type and data transfer flags.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    GoIdleState = 0x00000000,
+    AllSendCid = 0x02010000,
+    SendRelativeAddr = 0x03020000,
+    SendCsd = 0x09010000,
+    SelectCard = 0x07030000,
+    SendIfCond = 0x08020000,
+    StopTransmission = 0x0C030000,
+    SendStatus = 0x0D020000,
+    SetBlocklen = 0x10020000,
+    ReadSingleBlock = 0x11220010,
+    ReadMultipleBlock = 0x12220032,
+    WriteSingleBlock = 0x18220000,
+    WriteMultipleBlock = 0x19220022,
+    AppCmd = 0x37020000,
+    /// `ACMD6`.
+    SetBusWidth = 0x06020000,
+    /// `ACMD41`.
+    SdSendOpCond = 0x29020000,
+    /// `ACMD51`.
+    SendScr = 0x33220010,
+}
+
+impl Command {
+    /// Returns `true` if this is an application specific command, which must
+    /// be preceded by `AppCmd`.
+    fn is_app(&self) -> bool {
+        match *self {
+            Command::SetBusWidth | Command::SdSendOpCond | Command::SendScr => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the card answers with an R1 card status.
+    fn has_card_status(&self) -> bool {
+        match *self {
+            Command::GoIdleState | Command::AllSendCid | Command::SendRelativeAddr
+                | Command::SendCsd | Command::SendIfCond | Command::SdSendOpCond => false,
+            _ => true,
+        }
+    }
+}
+
+/// Errors reported by the EMMC controller or the card.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or the card did not respond in time.
+    Timeout,
+    /// The controller flagged an error. Holds the `INTERRUPT` register bits.
+    Controller(u32),
+    /// The card reported an error in its status response. Holds the response.
+    CardStatus(u32),
+    /// The card does not accept the host voltage.
+    UnsupportedCard,
+    /// The buffer length is not a non-zero multiple of `BLOCK_SIZE`.
+    InvalidBuffer,
+    /// The block address does not fit in a command argument.
+    AddressOutOfRange,
+}
+
+/// The EMMC SD host controller, driving an initialized and selected SD card.
+pub struct Emmc {
+    registers: &'static mut Registers,
+    host_version: u32,
+    rca: u32,
+    block_addressing: bool,
+    blocks: u64,
+}
+
+impl Emmc {
+    /// Resets the EMMC controller, identifies the SD card, selects it and
+    /// switches to the transfer clock and, if the card supports it, the 4-bit
+    /// bus.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnsupportedCard` if the card does not accept the host voltage.
+    /// Returns `Timeout`, `Controller` or `CardStatus` if a command fails.
+    pub fn new() -> Result<Emmc, Error> {
+        route_pins();
+
+        let registers = unsafe { &mut *(EMMC_REG_BASE as *mut Registers) };
+        let host_version = (registers.SLOTISR_VER.read() >> slotisr_ver::SDVERSION_SHIFT)
+            & slotisr_ver::SDVERSION_MASK;
+
+        let mut emmc = Emmc { registers, host_version, rca: 0, block_addressing: false,
+                              blocks: 0 };
+        emmc.reset()?;
+        emmc.identify()?;
+        emmc.select()?;
+        Ok(emmc)
+    }
+
+    /// Resets the host controller and enables the identification clock.
+    fn reset(&mut self) -> Result<(), Error> {
+        self.registers.CONTROL0.write(0);
+        self.registers.CONTROL1.or_mask(control1::SRST_HC);
+        self.wait_until(|r| r.CONTROL1.read() & control1::SRST_HC == 0)?;
+
+        self.registers.CONTROL1.or_mask(control1::CLK_INTLEN | control1::DATA_TOUNIT_MAX);
+        timer::spin_sleep_ms(10);
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+
+        self.registers.IRPT_EN.write(0xFFFFFFFF);
+        self.registers.IRPT_MASK.write(0xFFFFFFFF);
+        Ok(())
+    }
+
+    /// Sets the SD clock to at most `frequency` Hz.
+    fn set_clock(&mut self, frequency: u32) -> Result<(), Error> {
+        self.wait_until(|r| r.STATUS.read() & (status::CMD_INHIBIT | status::DAT_INHIBIT) == 0)?;
+        self.registers.CONTROL1.and_mask(!control1::CLK_EN);
+        timer::spin_sleep_ms(10);
+
+        let field = clock_divisor_field(self.host_version, frequency);
+        let control = self.registers.CONTROL1.read() & !control1::CLK_FREQ_MASK;
+        self.registers.CONTROL1.write(control | field);
+        timer::spin_sleep_ms(10);
+
+        self.registers.CONTROL1.or_mask(control1::CLK_EN);
+        self.wait_until(|r| r.CONTROL1.read() & control1::CLK_STABLE != 0)
+    }
+
+    /// Brings the card from the idle state to the stand-by state, learning
+    /// its addressing mode, relative card address and capacity.
+    ///
+    /// Cards older than SD 2.0 do not answer `CMD8`. They are identified
+    /// without claiming high capacity support and are addressed in bytes.
+    fn identify(&mut self) -> Result<(), Error> {
+        self.send_command(Command::GoIdleState, 0)?;
+
+        let version_2 = match self.send_command(Command::SendIfCond, IF_COND_ARGUMENT) {
+            Ok(echo) if echo & 0xFFF == IF_COND_ARGUMENT => true,
+            Ok(_) => return Err(Error::UnsupportedCard),
+            Err(Error::Timeout) => {
+                self.reset_lines();
+                false
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut response = 0;
+        for _ in 0..OP_COND_RETRIES {
+            response = self.send_command(Command::SdSendOpCond, op_cond_argument(version_2))?;
+            if response & ocr::POWER_UP_DONE != 0 {
+                break;
+            }
+            timer::spin_sleep_ms(10);
+        }
+
+        if response & ocr::POWER_UP_DONE == 0 {
+            return Err(Error::Timeout);
+        } else if response & ocr::VOLTAGE_WINDOW == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+
+        self.block_addressing = version_2 && response & ocr::CCS != 0;
+        self.send_command(Command::AllSendCid, 0)?;
+        self.rca = self.send_command(Command::SendRelativeAddr, 0)? & 0xFFFF0000;
+
+        let rca = self.rca;
+        self.send_command(Command::SendCsd, rca)?;
+        let mut response = [0u32; 4];
+        for (word, register) in response.iter_mut().zip(self.registers.RESP.iter()) {
+            *word = register.read();
+        }
+        self.blocks = csd_blocks(&response);
+        Ok(())
+    }
+
+    /// Selects the card, switches to the transfer clock and widens the bus
+    /// when the card's SCR says it supports 4 data lines.
+    fn select(&mut self) -> Result<(), Error> {
+        self.set_clock(TRANSFER_CLOCK)?;
+        let rca = self.rca;
+        self.send_command(Command::SelectCard, rca)?;
+        self.wait_ready()?;
+        if !self.block_addressing {
+            // Byte addressed cards may default to another block length.
+            self.send_command(Command::SetBlocklen, BLOCK_SIZE as u32)?;
+        }
+
+        let mut scr = [0u32; 2];
+        self.wait_until(|r| r.STATUS.read() & status::DAT_INHIBIT == 0)?;
+        self.registers.BLKSIZECNT.write((1 << 16) | 8);
+        self.send_command(Command::SendScr, 0)?;
+        self.wait_interrupt(interrupt::READ_RDY)?;
+        for word in scr.iter_mut() {
+            self.wait_until(|r| r.STATUS.read() & status::READ_AVAILABLE != 0)?;
+            *word = self.registers.DATA.read();
+        }
+
+        if scr[0] & SCR_BUS_WIDTH_4 != 0 {
+            self.send_command(Command::SetBusWidth, 2)?;
+            self.registers.CONTROL0.or_mask(control0::HCTL_DWIDTH);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the card is addressed in blocks (SDHC/SDXC) rather
+    /// than in bytes (SDSC).
+    pub fn block_addressing(&self) -> bool {
+        self.block_addressing
+    }
+
+    /// Returns the capacity of the card in `BLOCK_SIZE` blocks, or `0` if its
+    /// card specific data has an unknown layout.
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Returns the card's relative address, in the upper 16 bits.
+    pub fn rca(&self) -> u32 {
+        self.rca
+    }
+
+    /// Spins until `condition` holds, for at most `TIMEOUT_US` microseconds.
+    fn wait_until<F: Fn(&Registers) -> bool>(&self, condition: F) -> Result<(), Error> {
+        let start = timer::current_time();
+        while !condition(&*self.registers) {
+            if timer::current_time() > start + TIMEOUT_US {
+                return Err(Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for any of the interrupt bits in `mask` and acknowledges them.
+    fn wait_interrupt(&mut self, mask: u32) -> Result<(), Error> {
+        let result = self.wait_until(|r| r.INTERRUPT.read() & (mask | interrupt::ERROR_MASK) != 0);
+        let flags = self.registers.INTERRUPT.read();
+
+        match result {
+            Err(error) => Err(error),
+            Ok(()) if flags & (interrupt::CMD_TIMEOUT | interrupt::DATA_TIMEOUT) != 0 => {
+                self.registers.INTERRUPT.write(flags);
+                Err(Error::Timeout)
+            }
+            Ok(()) if flags & interrupt::ERROR_MASK != 0 => {
+                self.registers.INTERRUPT.write(flags);
+                Err(Error::Controller(flags))
+            }
+            Ok(()) => {
+                self.registers.INTERRUPT.write(mask);
+                Ok(())
+            }
+        }
+    }
+
+    /// Polls the card's status until it is ready for data in the transfer
+    /// state. Cards signal busy on DAT0 while programming after a write and
+    /// after the R1b commands `SelectCard` and `StopTransmission`, and must
+    /// not be sent another data command until they are done.
+    fn wait_ready(&mut self) -> Result<(), Error> {
+        let start = timer::current_time();
+        loop {
+            let rca = self.rca;
+            if is_ready(self.send_command(Command::SendStatus, rca)?) {
+                return Ok(());
+            } else if timer::current_time() > start + TIMEOUT_US {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Resets the command and data lines after an error.
+    fn reset_lines(&mut self) {
+        self.registers.CONTROL1.or_mask(control1::SRST_CMD | control1::SRST_DATA);
+        let _ = self.wait_until(|r| {
+            r.CONTROL1.read() & (control1::SRST_CMD | control1::SRST_DATA) == 0
+        });
+    }
+
+    /// Sends `command` with argument `arg` and returns the first word of the
+    /// card's response. Application specific commands are preceded by
+    /// `AppCmd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CardStatus` if the response is an R1 card status with error
+    /// bits set, and `Timeout` or `Controller` if the command fails.
+    pub fn send_command(&mut self, command: Command, arg: u32) -> Result<u32, Error> {
+        if command.is_app() {
+            let rca = self.rca;
+            self.issue(Command::AppCmd, rca)?;
+        }
+
+        self.issue(command, arg)
+    }
+
+    /// Sends a single command to the card.
+    fn issue(&mut self, command: Command, arg: u32) -> Result<u32, Error> {
+        self.wait_until(|r| r.STATUS.read() & status::CMD_INHIBIT == 0)?;
+
+        let pending = self.registers.INTERRUPT.read();
+        self.registers.INTERRUPT.write(pending);
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write(command as u32);
+        self.wait_interrupt(interrupt::CMD_DONE)?;
+
+        let response = self.registers.RESP[0].read();
+        if command.has_card_status() && response & R1_ERRORS_MASK != 0 {
+            return Err(Error::CardStatus(response));
+        }
+
+        Ok(response)
+    }
+
+    /// The command argument addressing block `block`.
+    fn address(&self, block: u32) -> Result<u32, Error> {
+        if self.block_addressing {
+            Ok(block)
+        } else {
+            block.checked_mul(BLOCK_SIZE as u32).ok_or(Error::AddressOutOfRange)
+        }
+    }
+
+    /// Prepares a transfer of `buf_len` bytes starting at block `block` and
+    /// issues the data command. Returns the number of blocks to transfer.
+    fn start_transfer(&mut self, block: u32, buf_len: usize, single: Command,
+                      multiple: Command) -> Result<usize, Error> {
+        if buf_len == 0 || buf_len % BLOCK_SIZE != 0 {
+            return Err(Error::InvalidBuffer);
+        }
+
+        let count = buf_len / BLOCK_SIZE;
+        let address = self.address(block)?;
+        self.wait_until(|r| r.STATUS.read() & status::DAT_INHIBIT == 0)?;
+        self.registers.BLKSIZECNT.write(((count as u32) << 16) | BLOCK_SIZE as u32);
+
+        let command = if count == 1 { single } else { multiple };
+        self.send_command(command, address)?;
+        Ok(count)
+    }
+
+    /// Ends a transfer of `count` blocks, stopping it if it is a multiple
+    /// block transfer, and waits for the card to leave the busy state after a
+    /// stop or a `write`.
+    fn finish_transfer(&mut self, count: usize, write: bool) -> Result<(), Error> {
+        if count > 1 {
+            self.send_command(Command::StopTransmission, 0)?;
+        }
+        if count > 1 || write {
+            self.wait_ready()?;
+        }
+        Ok(())
+    }
+
+    /// Reads `buf.len() / BLOCK_SIZE` consecutive blocks starting at block
+    /// `block` into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidBuffer` if `buf.len()` is not a non-zero multiple of
+    /// `BLOCK_SIZE`, and `AddressOutOfRange` if `block` cannot be addressed.
+    /// Returns `Timeout`, `Controller` or `CardStatus` if the transfer fails.
+    pub fn read_blocks(&mut self, block: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let result = self.read_blocks_inner(block, buf);
+        if result.is_err() {
+            self.reset_lines();
+        }
+        result
+    }
+
+    fn read_blocks_inner(&mut self, block: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let count = self.start_transfer(block, buf.len(), Command::ReadSingleBlock,
+                                        Command::ReadMultipleBlock)?;
+
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            self.wait_interrupt(interrupt::READ_RDY)?;
+            for word in chunk.chunks_mut(4) {
+                let value = self.registers.DATA.read();
+                word[0] = value as u8;
+                word[1] = (value >> 8) as u8;
+                word[2] = (value >> 16) as u8;
+                word[3] = (value >> 24) as u8;
+            }
+        }
+
+        self.finish_transfer(count, false)
+    }
+
+    /// Writes `buf.len() / BLOCK_SIZE` consecutive blocks from `buf` starting
+    /// at block `block`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidBuffer` if `buf.len()` is not a non-zero multiple of
+    /// `BLOCK_SIZE`, and `AddressOutOfRange` if `block` cannot be addressed.
+    /// Returns `Timeout`, `Controller` or `CardStatus` if the transfer fails.
+    pub fn write_blocks(&mut self, block: u32, buf: &[u8]) -> Result<(), Error> {
+        let result = self.write_blocks_inner(block, buf);
+        if result.is_err() {
+            self.reset_lines();
+        }
+        result
+    }
+
+    fn write_blocks_inner(&mut self, block: u32, buf: &[u8]) -> Result<(), Error> {
+        let count = self.start_transfer(block, buf.len(), Command::WriteSingleBlock,
+                                        Command::WriteMultipleBlock)?;
+
+        for chunk in buf.chunks(BLOCK_SIZE) {
+            self.wait_interrupt(interrupt::WRITE_RDY)?;
+            for word in chunk.chunks(4) {
+                self.registers.DATA.write(word[0] as u32 | (word[1] as u32) << 8
+                                          | (word[2] as u32) << 16 | (word[3] as u32) << 24);
+            }
+        }
+
+        self.wait_interrupt(interrupt::DATA_DONE)?;
+        self.finish_transfer(count, true)
+    }
+}
+
+/// Routes the SD card pins to the EMMC controller: GPIO 47 is the card detect
+/// input and GPIO 48 to 53 carry the clock, command and data lines on ALT3,
+/// all with pull-ups enabled.
+fn route_pins() {
+    let mut detect = Gpio::new(47).into_input();
+    detect.set_pull(Pull::Up);
+
+    for pin in 48..54 {
+        let mut pin = Gpio::new(pin).into_alt(Function::Alt3);
+        pin.set_pull(Pull::Up);
+    }
+}
+
+/// Returns the `CONTROL1` clock divisor bits that set the SD clock to at most
+/// `frequency` Hz on a controller of version `host_version`.
+fn clock_divisor_field(host_version: u32, frequency: u32) -> u32 {
+    let target = BASE_CLOCK / frequency;
+    let divisor = if host_version > slotisr_ver::HOST_SPEC_V2 {
+        target
+    } else {
+        // Older controllers only divide by powers of two.
+        let bits = 32 - target.saturating_sub(1).leading_zeros();
+        1 << ::core::cmp::min(bits.saturating_sub(1), 7)
+    };
+    let divisor = ::core::cmp::max(divisor, 2);
+
+    ((divisor & 0xFF) << 8) | ((divisor & 0x300) >> 2)
+}
+
+/// Returns the `width` bits of the card specific data starting at bit `start`
+/// from the `CMD9` response `response`. The controller drops the CRC, so the
+/// response holds bits 8 to 127 of the register in its low 120 bits.
+fn csd_field(response: &[u32; 4], (start, width): (usize, usize)) -> u64 {
+    (0..width).fold(0, |field, i| {
+        let bit = start + i - 8;
+        field | ((response[bit / 32] >> (bit % 32)) as u64 & 1) << i
+    })
+}
+
+/// Returns the capacity in `BLOCK_SIZE` blocks described by the card specific
+/// data in the `CMD9` response `response`, or `0` for unknown CSD versions.
+fn csd_blocks(response: &[u32; 4]) -> u64 {
+    match csd_field(response, csd::STRUCTURE) {
+        // Standard capacity: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of
+        // 2^READ_BL_LEN bytes.
+        0 => {
+            let shift = csd_field(response, csd::C_SIZE_MULT_V1) + 2
+                + csd_field(response, csd::READ_BL_LEN);
+            ((csd_field(response, csd::C_SIZE_V1) + 1) << shift) / BLOCK_SIZE as u64
+        }
+        // High and extended capacity: (C_SIZE + 1) * 512KiB.
+        1 => (csd_field(response, csd::C_SIZE_V2) + 1) * 1024,
+        _ => 0,
+    }
+}
+
+/// The argument to `ACMD41`. Only cards that implement SD 2.0 may be told
+/// that the host supports high capacity cards.
+fn op_cond_argument(version_2: bool) -> u32 {
+    if version_2 {
+        ocr::HCS_ARGUMENT
+    } else {
+        ocr::VOLTAGE_WINDOW
+    }
+}
+
+/// Returns `true` if the R1 card status `status` says the card is ready for
+/// the next data command.
+fn is_ready(status: u32) -> bool {
+    let state = (status >> card_status::CURRENT_STATE_SHIFT) & card_status::CURRENT_STATE_MASK;
+    status & card_status::READY_FOR_DATA != 0 && state == card_status::STATE_TRAN
+}
+
+impl fmt::Debug for Emmc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Emmc")
+            .field("host_version", &self.host_version)
+            .field("rca", &self.rca)
+            .field("block_addressing", &self.block_addressing)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_divisor() {
+        // 41.67MHz / 400kHz rounds down to a divisor of 104.
+        assert_eq!(clock_divisor_field(2, IDENTIFICATION_CLOCK), 104 << 8);
+        assert_eq!(clock_divisor_field(1, IDENTIFICATION_CLOCK), 64 << 8);
+        assert_eq!(clock_divisor_field(2, TRANSFER_CLOCK), 2 << 8);
+        assert_eq!(clock_divisor_field(1, TRANSFER_CLOCK), 2 << 8);
+        // The upper two bits of a 10-bit divisor, here 0x301, go in bits 6 and 7.
+        assert_eq!(clock_divisor_field(2, 54_180), 0x01 << 8 | 0xC0);
+    }
+
+    #[test]
+    fn test_op_cond_argument() {
+        assert!(op_cond_argument(true) & ocr::CCS != 0);
+        assert_eq!(op_cond_argument(false) & ocr::CCS, 0);
+        assert_eq!(op_cond_argument(false) & ocr::VOLTAGE_WINDOW, ocr::VOLTAGE_WINDOW);
+    }
+
+    #[test]
+    fn test_is_ready() {
+        let tran = card_status::STATE_TRAN << card_status::CURRENT_STATE_SHIFT;
+        let programming = 7 << card_status::CURRENT_STATE_SHIFT;
+        assert!(is_ready(tran | card_status::READY_FOR_DATA));
+        assert!(!is_ready(tran));
+        assert!(!is_ready(programming));
+        assert!(!is_ready(programming | card_status::READY_FOR_DATA));
+    }
+
+    /// Returns a `CMD9` response with the card specific data `fields` set.
+    fn csd_response(fields: &[((usize, usize), u64)]) -> [u32; 4] {
+        let mut response = [0u32; 4];
+        for &((start, width), value) in fields {
+            for i in 0..width {
+                let bit = start + i - 8;
+                response[bit / 32] |= ((value >> i) as u32 & 1) << (bit % 32);
+            }
+        }
+        response
+    }
+
+    #[test]
+    fn test_csd_blocks() {
+        // A 2GB standard capacity card: 4096 * 2^9 blocks of 1024 bytes.
+        let v1 = csd_response(&[(csd::STRUCTURE, 0), (csd::READ_BL_LEN, 10),
+                                (csd::C_SIZE_V1, 4095), (csd::C_SIZE_MULT_V1, 7)]);
+        assert_eq!(csd_blocks(&v1), 4096 * 512 * 2);
+        // C_SIZE straddles the second and third response words.
+        assert_eq!(v1[1] >> 22, 0x3FF);
+        assert_eq!(v1[2] & 0x3, 0x3);
+
+        // An 8GB high capacity card.
+        let v2 = csd_response(&[(csd::STRUCTURE, 1), (csd::C_SIZE_V2, 15159)]);
+        assert_eq!(csd_blocks(&v2), 15160 * 1024);
+        assert_eq!(v2[1] >> 8, 15159);
+
+        assert_eq!(csd_blocks(&csd_response(&[(csd::STRUCTURE, 2)])), 0);
+    }
+
+    #[test]
+    fn test_commands() {
+        for command in [Command::SetBusWidth, Command::SdSendOpCond, Command::SendScr].iter() {
+            assert!(command.is_app());
+        }
+        assert!(!Command::AppCmd.is_app());
+        assert!(!Command::SendIfCond.has_card_status());
+        assert!(!Command::SendCsd.has_card_status());
+        assert!(Command::SendStatus.has_card_status());
+        assert!(Command::StopTransmission.has_card_status());
+        // Command indices are in the top byte.
+        assert_eq!(Command::SendStatus as u32 >> 24, 13);
+        assert_eq!(Command::SetBlocklen as u32 >> 24, 16);
+    }
+}
@@ -1,6 +1,7 @@
 use core::marker::PhantomData;
 
 use common::{IO_BASE, states};
+use timer;
 use volatile::prelude::*;
 use volatile::{Volatile, WriteVolatile, ReadVolatile, Reserved};
 
@@ -17,6 +18,14 @@ pub enum Function {
     Alt5 = 0b010
 }
 
+/// A pull-up/down resistor setting for a GPIO pin.
+#[repr(u8)]
+pub enum Pull {
+    Off = 0b00,
+    Down = 0b01,
+    Up = 0b10
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -79,6 +88,21 @@ impl<T> Gpio<T> {
             _state: PhantomData
         }
     }
+
+    /// Sets the pull-up/down resistor of `self` to `pull`.
+    ///
+    /// The setting is latched by clocking it into the pin, which requires
+    /// holding the control signal for at least 150 cycles before and after.
+    pub fn set_pull(&mut self, pull: Pull) {
+        let id = (self.pin / 32) as usize;
+        let offset = self.pin % 32;
+        self.registers.PUD.write(pull as u32);
+        timer::spin_sleep_us(1);
+        self.registers.PUDCLK[id].write(1 << offset);
+        timer::spin_sleep_us(1);
+        self.registers.PUD.write(0);
+        self.registers.PUDCLK[id].write(0);
+    }
 }
 
 impl Gpio<Uninitialized> {
@@ -104,6 +128,7 @@ impl Gpio<Uninitialized> {
     pub fn into_alt(self, function: Function) -> Gpio<Alt> {
         let id = (self.pin / 10) as usize;
         let offset = self.pin % 10 * 3;
+        self.registers.FSEL[id].and_mask(!(0b111 << offset));
         self.registers.FSEL[id].or_mask((function as u32) << offset);
         Gpio {
             pin: self.pin,
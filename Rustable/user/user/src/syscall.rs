@@ -52,4 +52,133 @@ pub fn sys_print(num: usize) -> usize {
         ); 
     }
     result as usize
-}
\ No newline at end of file
+}
+
+/// Error numbers returned by system calls. They match the kernel's `Errno`.
+pub const ENOSYS: usize = 1;
+pub const EBADF: usize = 2;
+pub const ENOENT: usize = 3;
+pub const EEXIST: usize = 4;
+pub const EINVAL: usize = 5;
+pub const EFAULT: usize = 6;
+pub const EMFILE: usize = 7;
+pub const EISDIR: usize = 8;
+pub const EACCES: usize = 9;
+pub const EIO: usize = 10;
+
+/// Flags for `sys_open`.
+pub const O_READ: usize = 1 << 0;
+pub const O_WRITE: usize = 1 << 1;
+pub const O_CREATE: usize = 1 << 2;
+pub const O_TRUNC: usize = 1 << 3;
+pub const O_APPEND: usize = 1 << 4;
+
+/// Origins for `sys_lseek`.
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+fn result(value: usize, error: usize) -> Result<usize, usize> {
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(error)
+    }
+}
+
+/// Opens the file at the absolute path `path` and returns its descriptor.
+pub fn sys_open(path: &str, flags: usize) -> Result<usize, usize> {
+    let fd: usize;
+    let error: usize;
+    unsafe {
+        asm!("mov x0, $2
+            mov x1, $3
+            mov x2, $4
+            svc 6
+            mov $0, x0
+            mov $1, x7"
+            : "=r"(fd), "=r"(error)
+            : "r"(path.as_ptr()), "r"(path.len()), "r"(flags)
+            : "x0", "x1", "x2", "x7", "memory"
+            : "volatile"
+        );
+    }
+    result(fd, error)
+}
+
+/// Reads from `fd` into `buf` and returns the number of bytes read.
+pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<usize, usize> {
+    let read: usize;
+    let error: usize;
+    unsafe {
+        asm!("mov x0, $2
+            mov x1, $3
+            mov x2, $4
+            svc 7
+            mov $0, x0
+            mov $1, x7"
+            : "=r"(read), "=r"(error)
+            : "r"(fd), "r"(buf.as_mut_ptr()), "r"(buf.len())
+            : "x0", "x1", "x2", "x7", "memory"
+            : "volatile"
+        );
+    }
+    result(read, error)
+}
+
+/// Writes `buf` to `fd` and returns the number of bytes written.
+pub fn sys_write(fd: usize, buf: &[u8]) -> Result<usize, usize> {
+    let written: usize;
+    let error: usize;
+    unsafe {
+        asm!("mov x0, $2
+            mov x1, $3
+            mov x2, $4
+            svc 8
+            mov $0, x0
+            mov $1, x7"
+            : "=r"(written), "=r"(error)
+            : "r"(fd), "r"(buf.as_ptr()), "r"(buf.len())
+            : "x0", "x1", "x2", "x7", "memory"
+            : "volatile"
+        );
+    }
+    result(written, error)
+}
+
+/// Closes `fd`.
+pub fn sys_close(fd: usize) -> Result<(), usize> {
+    let error: usize;
+    unsafe {
+        asm!("mov x0, $1
+            svc 9
+            mov $0, x7"
+            : "=r"(error)
+            : "r"(fd)
+            : "x0", "x7"
+            : "volatile"
+        );
+    }
+    result(0, error).map(|_| ())
+}
+
+/// Moves the position of `fd` to `offset` bytes from `whence` and returns the
+/// new position.
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> Result<usize, usize> {
+    let position: usize;
+    let error: usize;
+    unsafe {
+        asm!("mov x0, $2
+            mov x1, $3
+            mov x2, $4
+            svc 10
+            mov $0, x0
+            mov $1, x7"
+            : "=r"(position), "=r"(error)
+            : "r"(fd), "r"(offset), "r"(whence)
+            : "x0", "x1", "x2", "x7"
+            : "volatile"
+        );
+    }
+    result(position, error)
+}